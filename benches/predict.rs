@@ -0,0 +1,42 @@
+//! Throughput of batch prediction on generated models, on a single thread
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+
+use xgboost_predictor::testing::{load_model, random_gbtree_model, random_matrix};
+
+const NUM_ROWS: usize = 10_000;
+
+fn bench_predict_many(c: &mut Criterion) {
+    // (objective, features, groups, trees per group, max depth)
+    let models = [
+        ("binary:logistic", 32, 1, 100, 6),
+        ("binary:logistic", 32, 1, 1000, 5),
+        ("rank:pairwise", 64, 1, 2000, 6),
+        ("binary:logistic", 128, 1, 500, 8),
+        ("multi:softprob", 64, 3, 100, 8),
+    ];
+    let mut group = c.benchmark_group("predict_many");
+    group.throughput(Throughput::Elements(NUM_ROWS as u64));
+    for (obj, num_feature, num_output_group, num_trees, max_depth) in models.iter() {
+        let mut predictor = load_model(&random_gbtree_model(
+            1,
+            obj,
+            *num_feature,
+            *num_output_group,
+            *num_trees,
+            *max_depth,
+        ));
+        predictor.set_nthread(1).unwrap();
+        let feats = random_matrix(2, NUM_ROWS, *num_feature, 0.1);
+        let name = format!(
+            "{}x{}_depth{}_features{}",
+            num_output_group, num_trees, max_depth, num_feature
+        );
+        group.bench_with_input(BenchmarkId::from_parameter(name), &feats, |b, feats| {
+            b.iter(|| predictor.predict_many(feats.view(), true, 0).unwrap())
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_predict_many);
+criterion_main!(benches);
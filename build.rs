@@ -0,0 +1,32 @@
+//! Generates the header of the C API when built with the `capi` feature, into `OUT_DIR`. The
+//! build scripts of dependent packages find its directory in `DEP_XGBOOST_PREDICTOR_INCLUDE`.
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    #[cfg(feature = "capi")]
+    {
+        let crate_dir = std::env::var("CARGO_MANIFEST_DIR").unwrap();
+        let out_dir = std::env::var("OUT_DIR").unwrap();
+        println!("cargo:rerun-if-changed=src/capi.rs");
+
+        let config = cbindgen::Config {
+            language: cbindgen::Language::C,
+            header: Some(String::from(
+                "/* C API of xgboost-predictor, generated from src/capi.rs by cbindgen. */",
+            )),
+            include_guard: Some(String::from("XGBOOST_PREDICTOR_H")),
+            no_includes: true,
+            sys_includes: vec![String::from("stddef.h"), String::from("stdint.h")],
+            cpp_compat: true,
+            usize_is_size_t: true,
+            documentation_style: cbindgen::DocumentationStyle::C,
+            ..Default::default()
+        };
+        cbindgen::Builder::new()
+            .with_config(config)
+            .with_src(format!("{}/src/capi.rs", crate_dir))
+            .generate()
+            .expect("failed to generate the C header")
+            .write_to_file(format!("{}/xgboost_predictor.h", out_dir));
+        println!("cargo:include={}", out_dir);
+    }
+}
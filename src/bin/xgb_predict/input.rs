@@ -0,0 +1,367 @@
+//! Readers of the input rows. Rows are read in chunks, with their columns in the order of the
+//! features of the model when the input names them.
+use std::io::{BufRead, Read};
+
+use bytes::Bytes;
+use clap::ValueEnum;
+use parquet::arrow::arrow_reader::{ParquetRecordBatchReader, ParquetRecordBatchReaderBuilder};
+use xgboost_predictor::ndarray::Array2;
+use xgboost_predictor::record_batch::batch_columns_to_matrix;
+use xgboost_predictor::{Error, ErrorKind, FeatureValue, Predictor, Result};
+
+/// Rows read and predicted at a time
+pub const CHUNK_ROWS: usize = 16384;
+
+#[derive(Clone, Copy, Debug, PartialEq, ValueEnum)]
+pub enum Format {
+    /// `label index:value ...` lines, with 0-based feature indices
+    Libsvm,
+    /// comma separated values, with a header line of feature names unless `--no-header`
+    Csv,
+    Parquet,
+}
+
+impl Format {
+    /// Guesses the format from the extension of a file
+    pub fn from_extension(extension: &str) -> Option<Format> {
+        match extension.to_ascii_lowercase().as_str() {
+            "libsvm" | "svm" | "txt" => Some(Format::Libsvm),
+            "csv" => Some(Format::Csv),
+            "parquet" | "pq" => Some(Format::Parquet),
+            _ => None,
+        }
+    }
+}
+
+/// Source of chunks of rows
+pub trait Rows {
+    /// Next chunk of at most `CHUNK_ROWS` rows, `None` at the end of the input
+    fn next_chunk(&mut self) -> Result<Option<Array2<FeatureValue>>>;
+}
+
+/// Opens the input in the given format
+pub fn open<'a, R: BufRead + 'a>(
+    format: Format,
+    mut reader: R,
+    predictor: &Predictor,
+    has_header: bool,
+) -> Result<Box<dyn Rows + 'a>> {
+    match format {
+        Format::Libsvm => Ok(Box::new(LibSvmRows::new(
+            reader,
+            predictor.model_num_feature(),
+        ))),
+        Format::Csv => Ok(Box::new(CsvRows::new(reader, predictor, has_header)?)),
+        Format::Parquet => {
+            // the footer of parquet files is at the end, the input is read as a whole
+            let mut bytes = vec![];
+            reader.read_to_end(&mut bytes)?;
+            Ok(Box::new(ParquetRows::new(Bytes::from(bytes), predictor)?))
+        }
+    }
+}
+
+pub struct LibSvmRows<R: BufRead> {
+    reader: R,
+    num_feature: usize,
+    line_number: usize,
+}
+
+impl<R: BufRead> LibSvmRows<R> {
+    pub fn new(reader: R, num_feature: usize) -> LibSvmRows<R> {
+        LibSvmRows {
+            reader,
+            num_feature,
+            line_number: 0,
+        }
+    }
+
+    /// Sets the values of a line into `feat`, the label and `qid` are ignored. Returns whether
+    /// the line holds a row.
+    fn parse_line(&self, line: &str, feat: &mut [FeatureValue]) -> Result<bool> {
+        let line = line.split('#').next().unwrap_or("");
+        let mut tokens = line.split_whitespace();
+        if tokens.next().is_none() {
+            return Ok(false);
+        }
+        for token in tokens {
+            let invalid = || {
+                Error::from(format!(
+                    "line {}: invalid entry '{}'",
+                    self.line_number, token
+                ))
+            };
+            let (index, value) = token.split_once(':').ok_or_else(invalid)?;
+            if index == "qid" {
+                continue;
+            }
+            let index: usize = index.parse().map_err(|_| invalid())?;
+            let value: FeatureValue = value.parse().map_err(|_| invalid())?;
+            if index >= self.num_feature {
+                return Err(Error::from_kind(ErrorKind::InvalidInputShape(
+                    self.num_feature,
+                    index + 1,
+                )));
+            }
+            feat[index] = value;
+        }
+        Ok(true)
+    }
+}
+
+impl<R: BufRead> Rows for LibSvmRows<R> {
+    fn next_chunk(&mut self) -> Result<Option<Array2<FeatureValue>>> {
+        let mut values = vec![];
+        let mut num_rows = 0;
+        let mut feat = vec![FeatureValue::NAN; self.num_feature];
+        let mut line = String::new();
+        while num_rows < CHUNK_ROWS {
+            line.clear();
+            if self.reader.read_line(&mut line)? == 0 {
+                break;
+            }
+            self.line_number += 1;
+            feat.iter_mut().for_each(|value| *value = FeatureValue::NAN);
+            if self.parse_line(&line, &mut feat)? {
+                values.extend_from_slice(&feat);
+                num_rows += 1;
+            }
+        }
+        if num_rows == 0 {
+            return Ok(None);
+        }
+        let feats = Array2::from_shape_vec((num_rows, self.num_feature), values).unwrap();
+        Ok(Some(feats))
+    }
+}
+
+fn csv_error(error: csv::Error) -> Error {
+    Error::from(format!("CSV input: {}", error))
+}
+
+pub struct CsvRows<R: Read> {
+    reader: csv::Reader<R>,
+    /// CSV column of each column of the rows
+    columns: Vec<Option<usize>>,
+    record: csv::StringRecord,
+}
+
+impl<R: Read> CsvRows<R> {
+    /// Columns named by a header are aligned to the feature names of the model, columns are
+    /// taken in order otherwise
+    pub fn new(reader: R, predictor: &Predictor, has_header: bool) -> Result<CsvRows<R>> {
+        let mut reader = csv::ReaderBuilder::new()
+            .has_headers(has_header)
+            .from_reader(reader);
+        // the first record when there is no header, it is not consumed
+        let headers = reader.headers().map_err(csv_error)?.clone();
+        let columns = if has_header {
+            let names: Vec<&str> = headers.iter().collect();
+            predictor.column_order(&names)?
+        } else {
+            (0..headers.len()).map(Some).collect()
+        };
+        Ok(CsvRows {
+            reader,
+            columns,
+            record: csv::StringRecord::new(),
+        })
+    }
+}
+
+impl<R: Read> Rows for CsvRows<R> {
+    fn next_chunk(&mut self) -> Result<Option<Array2<FeatureValue>>> {
+        let mut values = vec![];
+        let mut num_rows = 0;
+        while num_rows < CHUNK_ROWS
+            && self
+                .reader
+                .read_record(&mut self.record)
+                .map_err(csv_error)?
+        {
+            for column in &self.columns {
+                let field = column.map_or("", |column| self.record[column].trim());
+                let value = if field.is_empty() {
+                    FeatureValue::NAN
+                } else {
+                    field.parse().map_err(|_| {
+                        let line = self.record.position().map_or(0, |position| position.line());
+                        Error::from(format!("line {}: invalid value '{}'", line, field))
+                    })?
+                };
+                values.push(value);
+            }
+            num_rows += 1;
+        }
+        if num_rows == 0 {
+            return Ok(None);
+        }
+        let feats = Array2::from_shape_vec((num_rows, self.columns.len()), values).unwrap();
+        Ok(Some(feats))
+    }
+}
+
+pub struct ParquetRows {
+    reader: ParquetRecordBatchReader,
+    /// column of the batches of each feature, as given by `Predictor::column_order`
+    columns: Vec<Option<usize>>,
+}
+
+impl ParquetRows {
+    /// Columns are aligned to the feature names of the model, or taken in order
+    pub fn new(bytes: Bytes, predictor: &Predictor) -> Result<ParquetRows> {
+        let parquet_error =
+            |error: parquet::errors::ParquetError| Error::from(format!("Parquet input: {}", error));
+        let builder = ParquetRecordBatchReaderBuilder::try_new(bytes).map_err(parquet_error)?;
+        let names: Vec<String> = builder
+            .schema()
+            .fields()
+            .iter()
+            .map(|field| field.name().clone())
+            .collect();
+        let names: Vec<&str> = names.iter().map(|name| name.as_str()).collect();
+        let columns = predictor.column_order(&names)?;
+        let reader = builder
+            .with_batch_size(CHUNK_ROWS)
+            .build()
+            .map_err(parquet_error)?;
+        Ok(ParquetRows { reader, columns })
+    }
+}
+
+impl Rows for ParquetRows {
+    fn next_chunk(&mut self) -> Result<Option<Array2<FeatureValue>>> {
+        match self.reader.next() {
+            Some(batch) => Ok(Some(batch_columns_to_matrix(&batch?, &self.columns)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::array::{ArrayRef, Float64Array, Int32Array};
+    use arrow::record_batch::RecordBatch;
+    use bytes::Bytes;
+    use parquet::arrow::ArrowWriter;
+    use xgboost_predictor::ndarray::{arr2, Array2};
+    use xgboost_predictor::{ErrorKind, FeatureValue, Predictor};
+
+    use super::{CsvRows, LibSvmRows, ParquetRows, Rows};
+
+    /// Model of the features "age" and "color" with a single leaf
+    const MODEL: &str = r#"{
+        "learner": {
+            "feature_names": ["age", "color"],
+            "feature_types": ["float", "float"],
+            "gradient_booster": {
+                "model": {
+                    "tree_info": [0],
+                    "trees": [{
+                        "base_weights": [0.5],
+                        "default_left": [0],
+                        "left_children": [-1],
+                        "loss_changes": [0],
+                        "parents": [2147483647],
+                        "right_children": [-1],
+                        "split_conditions": [0.5],
+                        "split_indices": [0],
+                        "sum_hessian": [1],
+                        "tree_param": {"num_feature": "2", "num_nodes": "1"}
+                    }]
+                },
+                "name": "gbtree"
+            },
+            "learner_model_param": {"base_score": "0", "num_class": "0", "num_feature": "2"},
+            "objective": {"name": "reg:linear"}
+        },
+        "version": [1, 7, 6]
+    }"#;
+
+    const NAN: FeatureValue = FeatureValue::NAN;
+
+    fn predictor() -> Predictor {
+        Predictor::from_json(MODEL.as_bytes()).unwrap()
+    }
+
+    /// Compares matrices holding NaN values
+    fn assert_same(feats: Array2<FeatureValue>, expected: Array2<FeatureValue>) {
+        let bits = |feats: Array2<FeatureValue>| feats.mapv(|value| value.to_string());
+        assert_eq!(bits(feats), bits(expected));
+    }
+
+    fn error_kind<T>(result: xgboost_predictor::Result<T>) -> ErrorKind {
+        match result {
+            Err(error) => error.0,
+            Ok(_) => panic!("the input is read"),
+        }
+    }
+
+    #[test]
+    fn test_libsvm_rows() {
+        let input = "1 0:20 1:1\n0 qid:3 1:2.5 # comment\n\n# comment line\n1\n";
+        let mut rows = LibSvmRows::new(input.as_bytes(), 2);
+        let feats = rows.next_chunk().unwrap().unwrap();
+        assert_same(feats, arr2(&[[20.0, 1.0], [NAN, 2.5], [NAN, NAN]]));
+        assert!(rows.next_chunk().unwrap().is_none());
+
+        let mut rows = LibSvmRows::new("1 2:1\n".as_bytes(), 2);
+        match error_kind(rows.next_chunk()) {
+            ErrorKind::InvalidInputShape(2, 3) => {}
+            kind => panic!("unexpected error {:?}", kind),
+        }
+        let mut rows = LibSvmRows::new("1 0:1\n1 1=2\n".as_bytes(), 2);
+        match error_kind(rows.next_chunk()) {
+            ErrorKind::Msg(message) => assert_eq!(message, "line 2: invalid entry '1=2'"),
+            kind => panic!("unexpected error {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn test_csv_rows() {
+        let predictor = predictor();
+        // columns are aligned to the feature names
+        let input = "color,age\n1,20\n,40\n";
+        let mut rows = CsvRows::new(input.as_bytes(), &predictor, true).unwrap();
+        let feats = rows.next_chunk().unwrap().unwrap();
+        assert_same(feats, arr2(&[[20.0, 1.0], [40.0, NAN]]));
+        assert!(rows.next_chunk().unwrap().is_none());
+
+        let mut rows = CsvRows::new("20,1\n40,\n".as_bytes(), &predictor, false).unwrap();
+        let feats = rows.next_chunk().unwrap().unwrap();
+        assert_same(feats, arr2(&[[20.0, 1.0], [40.0, NAN]]));
+
+        match error_kind(CsvRows::new("colour,age\n".as_bytes(), &predictor, true)) {
+            ErrorKind::FeatureMismatch(missing, unexpected) => {
+                assert_eq!(
+                    (missing, unexpected),
+                    (vec![String::from("color")], vec![String::from("colour")])
+                );
+            }
+            kind => panic!("unexpected error {:?}", kind),
+        }
+        let mut rows = CsvRows::new("age,color\n1,red\n".as_bytes(), &predictor, true).unwrap();
+        match error_kind(rows.next_chunk()) {
+            ErrorKind::Msg(message) => assert_eq!(message, "line 2: invalid value 'red'"),
+            kind => panic!("unexpected error {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn test_parquet_rows() {
+        let color: ArrayRef = Arc::new(Int32Array::from(vec![Some(1), None]));
+        let age: ArrayRef = Arc::new(Float64Array::from(vec![20.0, 40.0]));
+        let batch = RecordBatch::try_from_iter(vec![("color", color), ("age", age)]).unwrap();
+        let mut bytes = vec![];
+        let mut writer = ArrowWriter::try_new(&mut bytes, batch.schema(), None).unwrap();
+        writer.write(&batch).unwrap();
+        writer.close().unwrap();
+
+        let mut rows = ParquetRows::new(Bytes::from(bytes), &predictor()).unwrap();
+        let feats = rows.next_chunk().unwrap().unwrap();
+        assert_same(feats, arr2(&[[20.0, 1.0], [40.0, NAN]]));
+        assert!(rows.next_chunk().unwrap().is_none());
+    }
+}
@@ -0,0 +1,355 @@
+//! `xgb-predict`, scoring of LibSVM, CSV or Parquet rows with an xgboost model. Built with the
+//! `cli` feature.
+//!
+//! ```text
+//! xgb-predict predict model.json rows.csv --output preds.csv
+//! cat rows.libsvm | xgb-predict predict model.bin --format libsvm --margin
+//! xgb-predict info model.bin --json
+//! xgb-predict dump model.bin --fmap fmap.txt --with-stats
+//! ```
+//!
+//! Predictions are written as one line of comma separated values per row.
+mod input;
+
+use std::fmt::Display;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use xgboost_predictor::dump::{self, DumpFormat, FeatureMap};
+use xgboost_predictor::errors::ResultExt;
+use xgboost_predictor::info::ModelInfo;
+use xgboost_predictor::{Error, ErrorKind, FeatureValue, Predictor, Result};
+
+use crate::input::Format;
+
+#[derive(Parser)]
+#[command(name = "xgb-predict", version, about = "Predictions of xgboost models")]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Predicts the rows of a LibSVM, CSV or Parquet input
+    Predict(PredictArgs),
+    /// Describes a model: its format, booster, objective and trees
+    Info(InfoArgs),
+    /// Dumps the trees as `Booster.dump_model` of xgboost does
+    Dump(DumpArgs),
+}
+
+#[derive(Args)]
+struct PredictArgs {
+    /// Model in the binary or JSON format of xgboost, or in the mapped format
+    model: PathBuf,
+    /// Input rows, read from the standard input when absent or `-`
+    input: Option<PathBuf>,
+    /// Format of the input, given by the extension of the input file by default
+    #[arg(long, value_enum)]
+    format: Option<Format>,
+    /// Output file, the standard output by default
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+    /// Predicts the untransformed margins
+    #[arg(long, conflicts_with_all = ["leaf", "contribs"])]
+    margin: bool,
+    /// Number of trees of each output group used, 0 for all of them
+    #[arg(long, default_value_t = 0)]
+    ntree_limit: usize,
+    /// Predicts the id of the leaf reached in each tree
+    #[arg(long, conflicts_with = "contribs")]
+    leaf: bool,
+    /// Predicts the SHAP values of the features followed by the bias, for each output group
+    #[arg(long)]
+    contribs: bool,
+    /// Value marking missing values, in addition to NaN and empty CSV fields
+    #[arg(long, allow_negative_numbers = true)]
+    missing: Option<f64>,
+    /// CSV input without a header line, its columns are the features in order
+    #[arg(long)]
+    no_header: bool,
+    /// Number of threads, 0 for one per core
+    #[arg(long, default_value_t = 0)]
+    nthread: usize,
+}
+
+#[derive(Args)]
+struct InfoArgs {
+    /// Model in the binary or JSON format of xgboost, or in the mapped format
+    model: PathBuf,
+    /// Prints a JSON object instead of `name: value` lines
+    #[arg(long)]
+    json: bool,
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum DumpFormatArg {
+    Text,
+    Json,
+}
+
+#[derive(Args)]
+struct DumpArgs {
+    /// Model in the binary or JSON format of xgboost
+    model: PathBuf,
+    /// Format of the dump
+    #[arg(long, value_enum, default_value = "text")]
+    format: DumpFormatArg,
+    /// Feature map naming the features, as `<index> <name> <type>` lines
+    #[arg(long)]
+    fmap: Option<PathBuf>,
+    /// Adds the gain and the cover of the nodes
+    #[arg(long)]
+    with_stats: bool,
+    /// Output file, the standard output by default
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+}
+
+fn open_file(path: &Path) -> Result<File> {
+    File::open(path).chain_err(|| format!("failed to open {}", path.display()))
+}
+
+fn write_rows<W: Write, T: Display>(out: &mut W, rows: &[Vec<T>]) -> Result<()> {
+    for row in rows {
+        for (i, value) in row.iter().enumerate() {
+            if i > 0 {
+                out.write_all(b",")?;
+            }
+            write!(out, "{}", value)?;
+        }
+        out.write_all(b"\n")?;
+    }
+    Ok(())
+}
+
+fn predict(args: &PredictArgs) -> Result<()> {
+    let mut predictor = Predictor::read_any(&mut BufReader::new(open_file(&args.model)?))
+        .chain_err(|| format!("failed to load {}", args.model.display()))?;
+    predictor.set_nthread(args.nthread)?;
+
+    let path = args.input.as_deref().filter(|path| *path != Path::new("-"));
+    let format = match (args.format, path) {
+        (Some(format), _) => format,
+        (None, Some(path)) => path
+            .extension()
+            .and_then(|extension| Format::from_extension(&extension.to_string_lossy()))
+            .ok_or_else(|| {
+                Error::from(format!(
+                    "unknown format of {}, use --format",
+                    path.display()
+                ))
+            })?,
+        (None, None) => {
+            return Err(Error::from(
+                "--format is required to read the standard input",
+            ));
+        }
+    };
+    let stdin = io::stdin();
+    let reader: Box<dyn BufRead> = match path {
+        Some(path) => Box::new(BufReader::new(open_file(path)?)),
+        None => Box::new(stdin.lock()),
+    };
+    let mut rows = input::open(format, reader, &predictor, !args.no_header)?;
+
+    let stdout = io::stdout();
+    let mut out: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(
+            File::create(path).chain_err(|| format!("failed to create {}", path.display()))?,
+        )),
+        None => Box::new(BufWriter::new(stdout.lock())),
+    };
+    while let Some(mut feats) = rows.next_chunk()? {
+        if let Some(missing) = args.missing {
+            let missing = missing as FeatureValue;
+            feats.mapv_inplace(|value| {
+                if value == missing {
+                    FeatureValue::NAN
+                } else {
+                    value
+                }
+            });
+        }
+        if args.leaf {
+            write_rows(
+                &mut out,
+                &predictor.predict_leaf(feats.view(), args.ntree_limit)?,
+            )?;
+        } else if args.contribs {
+            write_rows(
+                &mut out,
+                &predictor.predict_contribs(feats.view(), args.ntree_limit)?,
+            )?;
+        } else {
+            let preds = predictor.predict_many(feats.view(), args.margin, args.ntree_limit)?;
+            write_rows(&mut out, &preds)?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn info(args: &InfoArgs) -> Result<()> {
+    let bytes =
+        fs::read(&args.model).chain_err(|| format!("failed to open {}", args.model.display()))?;
+    let info = ModelInfo::from_bytes(&bytes)
+        .chain_err(|| format!("failed to load {}", args.model.display()))?;
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+    if args.json {
+        writeln!(out, "{:#}", info.to_json())?;
+    } else {
+        write!(out, "{}", info)?;
+    }
+    Ok(())
+}
+
+/// Writes the dumps of the trees as `Booster.dump_model` does, each tree after a `booster[i]:`
+/// line in the text format and as a JSON array otherwise
+fn dump(args: &DumpArgs) -> Result<()> {
+    let predictor = Predictor::read_any(&mut BufReader::new(open_file(&args.model)?))
+        .chain_err(|| format!("failed to load {}", args.model.display()))?;
+    let fmap = match &args.fmap {
+        Some(path) => Some(
+            FeatureMap::read_from(BufReader::new(open_file(path)?))
+                .chain_err(|| format!("failed to read {}", path.display()))?,
+        ),
+        None => None,
+    };
+    let format = match args.format {
+        DumpFormatArg::Text => DumpFormat::Text,
+        DumpFormatArg::Json => DumpFormat::Json,
+    };
+    let trees = dump::dump_model(&predictor, fmap.as_ref(), args.with_stats, format)?;
+
+    let stdout = io::stdout();
+    let mut out: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(BufWriter::new(
+            File::create(path).chain_err(|| format!("failed to create {}", path.display()))?,
+        )),
+        None => Box::new(BufWriter::new(stdout.lock())),
+    };
+    match format {
+        DumpFormat::Text => {
+            for (i, tree) in trees.iter().enumerate() {
+                write!(out, "booster[{}]:\n{}", i, tree)?;
+            }
+        }
+        DumpFormat::Json => {
+            out.write_all(b"[\n")?;
+            out.write_all(trees.join(",\n").as_bytes())?;
+            out.write_all(b"\n]")?;
+        }
+    }
+    out.flush()?;
+    Ok(())
+}
+
+fn run(cli: &Cli) -> Result<()> {
+    match &cli.command {
+        Command::Predict(args) => predict(args),
+        Command::Info(args) => info(args),
+        Command::Dump(args) => dump(args),
+    }
+}
+
+fn main() {
+    if let Err(error) = run(&Cli::parse()) {
+        // the reader of the output stopped, e.g. `head`
+        if let ErrorKind::Io(io_error) = error.kind() {
+            if io_error.kind() == io::ErrorKind::BrokenPipe {
+                return;
+            }
+        }
+        eprintln!("xgb-predict: {}", error);
+        for cause in error.iter().skip(1) {
+            eprintln!("  caused by: {}", cause);
+        }
+        process::exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use clap::Parser;
+
+    use super::{run, Cli};
+
+    /// Model of the features "age" and "color" with a single split, `age < 30`, missing values
+    /// going right
+    const MODEL: &str = r#"{
+        "learner": {
+            "feature_names": ["age", "color"],
+            "feature_types": ["float", "float"],
+            "gradient_booster": {
+                "model": {
+                    "tree_info": [0],
+                    "trees": [{
+                        "base_weights": [0, -1, 1],
+                        "default_left": [0, 0, 0],
+                        "left_children": [1, -1, -1],
+                        "loss_changes": [4, 0, 0],
+                        "parents": [2147483647, 0, 0],
+                        "right_children": [2, -1, -1],
+                        "split_conditions": [30, -1, 1],
+                        "split_indices": [0, 0, 0],
+                        "sum_hessian": [2, 1, 1],
+                        "tree_param": {"num_feature": "2", "num_nodes": "3"}
+                    }]
+                },
+                "name": "gbtree"
+            },
+            "learner_model_param": {"base_score": "0", "num_class": "0", "num_feature": "2"},
+            "objective": {"name": "reg:linear"}
+        },
+        "version": [1, 7, 6]
+    }"#;
+
+    const ROWS: &str = "age,color\n20,1\n40,2\n-999,3\n";
+
+    /// Runs `xgb-predict predict` on the rows with the options and returns its output
+    fn predict(options: &[&str]) -> String {
+        let dir = tempfile::tempdir().unwrap();
+        let (model, input, output) = (
+            dir.path().join("model.json"),
+            dir.path().join("rows.csv"),
+            dir.path().join("preds.csv"),
+        );
+        fs::write(&model, MODEL).unwrap();
+        fs::write(&input, ROWS).unwrap();
+        let mut args = vec![
+            "xgb-predict",
+            "predict",
+            model.to_str().unwrap(),
+            input.to_str().unwrap(),
+            "--output",
+            output.to_str().unwrap(),
+        ];
+        args.extend_from_slice(options);
+        run(&Cli::try_parse_from(args).unwrap()).unwrap();
+        fs::read_to_string(output).unwrap()
+    }
+
+    #[test]
+    fn test_predict() {
+        assert_eq!(predict(&[]), "-1\n1\n-1\n");
+        assert_eq!(predict(&["--missing", "-999"]), "-1\n1\n1\n");
+        assert_eq!(predict(&["--leaf"]), "1\n2\n1\n");
+        assert_eq!(predict(&["--leaf", "--missing", "-999"]), "1\n2\n2\n");
+        // the expected value of the tree is 0, the bias
+        assert_eq!(
+            predict(&["--contribs", "--missing", "-999"]),
+            "-1,0,0\n1,0,0\n1,0,0\n"
+        );
+        assert!(
+            Cli::try_parse_from(["xgb-predict", "predict", "m", "--leaf", "--contribs"]).is_err()
+        );
+    }
+}
@@ -0,0 +1,531 @@
+//! Export of a model to a self-contained C header and source, for scoring where only a C
+//! compiler is available.
+//!
+//! For a model exported under the name `model`, `model.h` declares
+//!
+//! ```c
+//! void model_predict_margin(const float *features, float *margins);
+//! void model_predict(const float *features, float *preds);
+//! ```
+//!
+//! taking `MODEL_NUM_FEATURE` features, missing values being `NAN`, and writing
+//! `MODEL_NUM_OUTPUT_GROUP` margins and `MODEL_NUM_PREDICTION` predictions. The source only
+//! needs `math.h`. Compiled as C99 with `-ffp-contract=off`, the margins have the same bits as
+//! `Predictor::predict_many`, and so do the predictions when the C library gives the same
+//! `expf` as Rust.
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::errors::*;
+use crate::functions::FunctionType;
+use crate::gbm::flat_tree::{FlatNode, FlatTree};
+use crate::gbm::gblinear::GBLinear;
+use crate::gbm::gbtree::GBTree;
+use crate::gbm::grad_booster::BoosterModel;
+use crate::predictor::Predictor;
+use crate::source_gen::{self, Syntax};
+
+/// Header and source of an exported model
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CSource {
+    pub header: String,
+    pub source: String,
+}
+
+struct C;
+
+impl Syntax for C {
+    const TRUE: &'static str = "1";
+
+    fn f32_literal(value: f32) -> String {
+        f32_literal(value)
+    }
+
+    fn if_statement(condition: &str) -> String {
+        format!("if ({}) {{", condition)
+    }
+
+    fn is_nan(fvalue: &str) -> String {
+        format!("isnan({})", fvalue)
+    }
+
+    /// The categories are declared by `write_categories`
+    fn is_category(
+        fvalue: &str,
+        _tree: &FlatTree,
+        tid: usize,
+        nid: usize,
+        _categories: usize,
+    ) -> String {
+        format!(
+            "is_category({}, tree_{}_categories_{}, sizeof(tree_{}_categories_{}) / sizeof(uint32_t))",
+            fvalue, tid, nid, tid, nid
+        )
+    }
+}
+
+/// Formats a float as a C literal giving back the same bits
+fn f32_literal(value: f32) -> String {
+    if value.is_nan() {
+        String::from("NAN")
+    } else if value == f32::INFINITY {
+        String::from("INFINITY")
+    } else if value == f32::NEG_INFINITY {
+        String::from("-INFINITY")
+    } else {
+        // the shortest representation reading back as the same float
+        format!("{:?}f", value)
+    }
+}
+
+/// Formats a string as a C string literal
+fn string_literal(value: &str) -> String {
+    let mut literal = String::from("\"");
+    for byte in value.bytes() {
+        match byte {
+            b'"' | b'\\' => write!(literal, "\\{}", byte as char).unwrap(),
+            0x20..=0x7e => literal.push(byte as char),
+            _ => write!(literal, "\\{:03o}", byte).unwrap(),
+        }
+    }
+    literal.push('"');
+    literal
+}
+
+/// Declares the categories of the categorical splits of the tree, C has no empty arrays
+fn write_categories(out: &mut String, tree: &FlatTree, tid: usize) {
+    for nid in 0..tree.num_nodes() {
+        if let FlatNode::CategoricalSplit { categories, .. } = tree.node(nid) {
+            if tree.categories(categories).is_empty() {
+                continue;
+            }
+            let categories: Vec<String> = tree
+                .categories(categories)
+                .iter()
+                .map(|category| format!("{}u", category))
+                .collect();
+            writeln!(
+                out,
+                "static const uint32_t tree_{}_categories_{}[] = {{{}}};",
+                tid,
+                nid,
+                categories.join(", ")
+            )
+            .unwrap();
+        }
+    }
+}
+
+fn write_trees(
+    out: &mut String,
+    prefix: &str,
+    name: &str,
+    gbtree: &GBTree,
+    base_score: f32,
+    num_feature: usize,
+) -> Result<()> {
+    source_gen::check_split_indices(gbtree, num_feature)?;
+    writeln!(out, "#define BASE_SCORE {}\n", f32_literal(base_score)).unwrap();
+
+    if source_gen::has_categorical(gbtree) {
+        out.push_str(
+            "/* Whether a category is in the sorted list of categories of a split */
+static int is_category(float fvalue, const uint32_t *categories, size_t len) {
+    uint32_t category;
+    size_t lo = 0, hi = len;
+    if (fvalue < 0.0f || fvalue >= 16777216.0f) {
+        return 0;
+    }
+    category = (uint32_t)fvalue;
+    while (lo < hi) {
+        size_t mid = lo + (hi - lo) / 2;
+        if (categories[mid] == category) {
+            return 1;
+        } else if (categories[mid] < category) {
+            lo = mid + 1;
+        } else {
+            hi = mid;
+        }
+    }
+    return 0;
+}
+
+",
+        );
+    }
+
+    for (tid, tree) in gbtree.trees().iter().enumerate() {
+        let tree = tree.flat();
+        write_categories(out, tree, tid);
+        writeln!(out, "static float tree_{}(const float *features) {{", tid).unwrap();
+        if tree.max_split_index().is_none() {
+            out.push_str("    (void)features;\n");
+        }
+        source_gen::write_node::<C>(out, tree, tid, 0, 1);
+        out.push_str("}\n\n");
+    }
+
+    writeln!(
+        out,
+        "void {}_predict_margin(const float *features, float *margins) {{",
+        name
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "    float sums[{}_NUM_OUTPUT_GROUP] = {{0.0f}};",
+        prefix
+    )
+    .unwrap();
+    writeln!(out, "    int gid;").unwrap();
+    for (group, term) in source_gen::margin_terms::<C>(gbtree) {
+        writeln!(out, "    sums[{}] += {};", group, term).unwrap();
+    }
+    writeln!(
+        out,
+        "    for (gid = 0; gid < {}_NUM_OUTPUT_GROUP; gid++) {{
+        margins[gid] = BASE_SCORE + sums[gid];
+    }}
+}}
+",
+        prefix
+    )
+    .unwrap();
+    Ok(())
+}
+
+fn write_linear(out: &mut String, prefix: &str, name: &str, gblinear: &GBLinear, base_score: f32) {
+    let num_feature = gblinear.num_feature();
+    let num_group = gblinear.num_output_group();
+    let weights: Vec<String> = (0..num_group)
+        .map(|gid| {
+            let weights: Vec<String> = (0..num_feature)
+                .map(|fid| f32_literal(gblinear.weight(fid, gid)))
+                .collect();
+            format!("    {{{}}}", weights.join(", "))
+        })
+        .collect();
+    writeln!(
+        out,
+        "static const float weights[{}_NUM_OUTPUT_GROUP][{}_NUM_FEATURE] = {{\n{}\n}};",
+        prefix,
+        prefix,
+        weights.join(",\n")
+    )
+    .unwrap();
+    // the base score is folded into the bias
+    let biases: Vec<String> = (0..num_group)
+        .map(|gid| f32_literal(gblinear.bias(gid) + base_score))
+        .collect();
+    writeln!(
+        out,
+        "static const float biases[{}_NUM_OUTPUT_GROUP] = {{{}}};\n",
+        prefix,
+        biases.join(", ")
+    )
+    .unwrap();
+    writeln!(
+        out,
+        "void {}_predict_margin(const float *features, float *margins) {{
+    int gid, fid;
+    for (gid = 0; gid < {}_NUM_OUTPUT_GROUP; gid++) {{
+        /* the same start as a Rust float sum */
+        float sum = -0.0f;
+        for (fid = 0; fid < {}_NUM_FEATURE; fid++) {{
+            /* missing values do not contribute */
+            if (!isnan(features[fid])) {{
+                sum += features[fid] * weights[gid][fid];
+            }}
+        }}
+        margins[gid] = sum + biases[gid];
+    }}
+}}
+",
+        name, prefix, prefix
+    )
+    .unwrap();
+}
+
+/// The same computations as `functions::get_classify_function`
+fn write_transform(out: &mut String, prefix: &str, name: &str, objective: FunctionType) {
+    writeln!(
+        out,
+        "void {}_predict(const float *features, float *preds) {{
+    float margins[{}_NUM_OUTPUT_GROUP];
+    int gid;
+    {}_predict_margin(features, margins);",
+        name, prefix, name
+    )
+    .unwrap();
+    let group = format!("{}_NUM_OUTPUT_GROUP", prefix);
+    let body = match objective {
+        FunctionType::RankPairwise | FunctionType::BinaryLogitraw | FunctionType::RegLinear => {
+            format!(
+                "    for (gid = 0; gid < {group}; gid++) {{
+        preds[gid] = margins[gid];
+    }}
+",
+                group = group
+            )
+        }
+        FunctionType::BinaryLogistic => format!(
+            "    for (gid = 0; gid < {group}; gid++) {{
+        preds[gid] = 1.0f / (1.0f + expf(-margins[gid]));
+    }}
+",
+            group = group
+        ),
+        FunctionType::MultiSoftmax => format!(
+            "    int max_index = 0;
+    float max = margins[0];
+    for (gid = 1; gid < {group}; gid++) {{
+        if (!(max >= margins[gid])) {{
+            max_index = gid;
+            max = margins[gid];
+        }}
+    }}
+    preds[0] = (float)max_index;
+",
+            group = group
+        ),
+        FunctionType::MultiSoftprob => format!(
+            "    float max = margins[0];
+    float sum = -0.0f;
+    for (gid = 0; gid < {group}; gid++) {{
+        max = fmaxf(margins[gid], max);
+    }}
+    for (gid = 0; gid < {group}; gid++) {{
+        sum += expf(margins[gid] - max);
+    }}
+    for (gid = 0; gid < {group}; gid++) {{
+        preds[gid] = expf(margins[gid] - max) / sum;
+    }}
+",
+            group = group
+        ),
+    };
+    out.push_str(&body);
+    out.push_str("}\n");
+}
+
+/// Generates the C header and source of the model, `name` prefixing the exported functions
+/// and, in upper case, the macros. It has to be a valid C identifier.
+pub fn generate(predictor: &Predictor, name: &str) -> Result<CSource> {
+    let mut chars = name.chars();
+    let valid = matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("'{}' is not a valid C identifier", name),
+        )
+        .into());
+    }
+    let prefix = name.to_ascii_uppercase();
+
+    let num_feature = predictor.model_num_feature();
+    let num_group = source_gen::num_output_group(predictor, "c_export")?;
+    let num_prediction = predictor.num_output(false);
+
+    let mut header = String::from("/* Generated by xgboost-predictor, do not edit. */\n\n");
+    writeln!(header, "#ifndef {}_H\n#define {}_H\n", prefix, prefix).unwrap();
+    writeln!(header, "#define {}_NUM_FEATURE {}", prefix, num_feature).unwrap();
+    writeln!(header, "#define {}_NUM_OUTPUT_GROUP {}", prefix, num_group).unwrap();
+    writeln!(
+        header,
+        "#define {}_NUM_PREDICTION {}\n",
+        prefix, num_prediction
+    )
+    .unwrap();
+    if predictor.feature_names().is_some() {
+        writeln!(
+            header,
+            "extern const char *const {}_feature_names[{}_NUM_FEATURE];\n",
+            name, prefix
+        )
+        .unwrap();
+    }
+    writeln!(
+        header,
+        "/* Margins of the output groups of a row of {}_NUM_FEATURE features, missing values
+   being NAN */
+void {}_predict_margin(const float *features, float *margins);
+
+/* {}_NUM_PREDICTION predictions: the margins transformed by the objective function */
+void {}_predict(const float *features, float *preds);
+
+#endif",
+        prefix, name, prefix, name
+    )
+    .unwrap();
+
+    let mut source = String::from("/* Generated by xgboost-predictor, do not edit. */\n\n");
+    writeln!(
+        source,
+        "#include <math.h>\n#include <stddef.h>\n#include <stdint.h>\n\n#include \"{}.h\"\n",
+        name
+    )
+    .unwrap();
+    if let Some(names) = predictor.feature_names() {
+        let names: Vec<String> = names
+            .iter()
+            .map(|name| format!("    {}", string_literal(name)))
+            .collect();
+        writeln!(
+            source,
+            "const char *const {}_feature_names[{}_NUM_FEATURE] = {{\n{}\n}};\n",
+            name,
+            prefix,
+            names.join(",\n")
+        )
+        .unwrap();
+    }
+    match predictor.booster() {
+        BoosterModel::Trees(gbtree) => write_trees(
+            &mut source,
+            &prefix,
+            name,
+            gbtree,
+            predictor.base_score(),
+            num_feature,
+        )?,
+        BoosterModel::Linear(gblinear) => {
+            write_linear(&mut source, &prefix, name, gblinear, predictor.base_score())
+        }
+        // rejected above
+        BoosterModel::Compact(_) | BoosterModel::Mapped(_) => unreachable!(),
+    }
+    write_transform(&mut source, &prefix, name, predictor.objective());
+    Ok(CSource { header, source })
+}
+
+/// Writes `name.h` and `name.c` into `dir` and returns their paths
+pub fn write<P: AsRef<Path>>(
+    predictor: &Predictor,
+    dir: P,
+    name: &str,
+) -> Result<(PathBuf, PathBuf)> {
+    let exported = generate(predictor, name)?;
+    let header_path = dir.as_ref().join(format!("{}.h", name));
+    let source_path = dir.as_ref().join(format!("{}.c", name));
+    fs::write(&header_path, exported.header)?;
+    fs::write(&source_path, exported.source)?;
+    Ok((header_path, source_path))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Write;
+    use std::fs;
+    use std::process::Command;
+
+    use ndarray::Array2;
+
+    use crate::predictor::Predictor;
+    use crate::source_gen::fixtures::{expected_output, models};
+    use crate::testing::{load_model, JSON_MODEL};
+    use crate::{to_f32, FeatureValue};
+
+    use super::{generate, write};
+
+    /// Compiles the exported model with a program printing the bits of the margins and
+    /// predictions of the rows, and checks them against the interpreter
+    fn check_exported(name: &str, predictor: &Predictor, feats: &Array2<FeatureValue>) {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, source_path) = write(predictor, dir.path(), name).unwrap();
+        let upper = name.to_ascii_uppercase();
+        let mut main = format!(
+            "#include <stdint.h>
+#include <stdio.h>
+#include <string.h>
+
+#include \"{name}.h\"
+
+static void print_row(const uint32_t *bits) {{
+    float row[{upper}_NUM_FEATURE];
+    float values[{upper}_NUM_OUTPUT_GROUP + {upper}_NUM_PREDICTION];
+    uint32_t value_bits;
+    int i;
+    memcpy(row, bits, sizeof(row));
+    {name}_predict_margin(row, values);
+    {name}_predict(row, values + {upper}_NUM_OUTPUT_GROUP);
+    for (i = 0; i < {upper}_NUM_OUTPUT_GROUP + {upper}_NUM_PREDICTION; i++) {{
+        memcpy(&value_bits, &values[i], sizeof(value_bits));
+        printf(\"%08x \", (unsigned)value_bits);
+    }}
+    printf(\"\\n\");
+}}
+
+int main(void) {{
+",
+            name = name,
+            upper = upper
+        );
+        for (i, row) in feats.outer_iter().enumerate() {
+            let values: Vec<String> = row
+                .iter()
+                .map(|value| format!("{:#x}u", to_f32(*value).to_bits()))
+                .collect();
+            writeln!(
+                main,
+                "    static const uint32_t row_{}[] = {{{}}};\n    print_row(row_{});",
+                i,
+                values.join(", "),
+                i
+            )
+            .unwrap();
+        }
+        main.push_str("    return 0;\n}\n");
+        let main_path = dir.path().join("main.c");
+        fs::write(&main_path, main).unwrap();
+
+        let cc = std::env::var("CC").unwrap_or_else(|_| String::from("cc"));
+        let binary = dir.path().join(name);
+        let status = Command::new(cc)
+            .args([
+                "-std=c99",
+                "-O1",
+                "-ffp-contract=off",
+                "-Wall",
+                "-Wextra",
+                "-Werror",
+                "-o",
+            ])
+            .arg(&binary)
+            .arg(&main_path)
+            .arg(&source_path)
+            .arg("-lm")
+            .status()
+            .unwrap();
+        assert!(
+            status.success(),
+            "exported code of {} does not compile",
+            name
+        );
+        let output = Command::new(&binary).output().unwrap();
+        let output = String::from_utf8(output.stdout).unwrap();
+        assert_eq!(
+            output.lines().collect::<Vec<&str>>(),
+            expected_output(predictor, feats),
+            "{}",
+            name
+        );
+    }
+
+    #[test]
+    fn test_exported_code_matches_interpreter() {
+        for (name, predictor, feats) in &models() {
+            check_exported(name, predictor, feats);
+        }
+    }
+
+    #[test]
+    fn test_invalid_name() {
+        let predictor = load_model(JSON_MODEL.as_bytes());
+        assert!(generate(&predictor, "model").is_ok());
+        assert!(generate(&predictor, "1model").is_err());
+        assert!(generate(&predictor, "my-model").is_err());
+    }
+}
@@ -0,0 +1,755 @@
+//! C API, built with the `capi` feature. The header `xgboost_predictor.h` is generated from this
+//! module by the build script, into its `OUT_DIR`.
+//!
+//! Functions return `XGBP_OK`, or the error code of the kind of the error whose message is then
+//! given by `xgbp_last_error` on the same thread. Handles can be used by several threads at
+//! once, except by `xgbp_free`. Inputs are `float` values, predictions are written row after
+//! row into buffers of the caller sized with `xgbp_output_size`.
+use std::cell::RefCell;
+use std::cmp;
+use std::ffi::{CStr, CString};
+use std::fs::File;
+use std::io::BufReader;
+use std::os::raw::{c_char, c_int};
+use std::panic::{self, AssertUnwindSafe};
+use std::ptr;
+use std::slice;
+
+use ndarray::{Array2, ArrayView2};
+
+use crate::errors::*;
+use crate::predictor::Predictor;
+use crate::{FeatureValue, InputValue};
+
+/// Success
+pub const XGBP_OK: c_int = 0;
+/// The model could not be read
+pub const XGBP_ERR_IO: c_int = 1;
+/// A string of the model is not valid UTF-8
+pub const XGBP_ERR_UTF8: c_int = 2;
+/// The threads of prediction could not be started
+pub const XGBP_ERR_THREAD_POOL: c_int = 3;
+pub const XGBP_ERR_UNSUPPORTED_MODEL_TYPE: c_int = 4;
+pub const XGBP_ERR_UNSUPPORTED_OBJECTIVE: c_int = 5;
+/// The kind of prediction is not supported by the model
+pub const XGBP_ERR_UNSUPPORTED_PREDICTION_METHOD: c_int = 6;
+/// A feature tested by the trees is not in the rows
+pub const XGBP_ERR_UNAVAILABLE_DATA_INDEX: c_int = 7;
+pub const XGBP_ERR_UNIMPLEMENTED: c_int = 8;
+pub const XGBP_ERR_BROKEN_MODEL: c_int = 9;
+pub const XGBP_ERR_UNSUPPORTED_DATA_TYPE: c_int = 10;
+/// The number of columns does not match the number of features of the model
+pub const XGBP_ERR_INVALID_INPUT_SHAPE: c_int = 11;
+pub const XGBP_ERR_FEATURE_MISMATCH: c_int = 12;
+/// Null pointer, unknown kind of prediction, output buffer too small or invalid sparse rows
+pub const XGBP_ERR_INVALID_ARGUMENT: c_int = 13;
+/// Any other error, or a panic
+pub const XGBP_ERR_OTHER: c_int = 14;
+
+/// Predictions transformed by the objective
+pub const XGBP_PREDICT_VALUE: c_int = 0;
+/// Untransformed margins
+pub const XGBP_PREDICT_MARGIN: c_int = 1;
+/// Id of the leaf reached in each tree, as a float
+pub const XGBP_PREDICT_LEAF: c_int = 2;
+/// SHAP values of the features followed by the bias, for each output group in turn
+pub const XGBP_PREDICT_CONTRIBS: c_int = 3;
+
+/// Rows converted to feature values at a time, bounding the memory used for sparse rows
+const CHUNK_ROWS: usize = 4096;
+
+/// Model loaded by `xgbp_load_path` or `xgbp_load_buffer`
+pub struct XGBPredictor {
+    predictor: Predictor,
+    objective: CString,
+    feature_names: Option<Vec<CString>>,
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<CString> = RefCell::new(CString::default());
+}
+
+fn error_code(kind: &ErrorKind) -> c_int {
+    match kind {
+        ErrorKind::Io(_) => XGBP_ERR_IO,
+        ErrorKind::Utf8Error(_) => XGBP_ERR_UTF8,
+        ErrorKind::ThreadPool(_) => XGBP_ERR_THREAD_POOL,
+        ErrorKind::UnsupportedModelType(_) => XGBP_ERR_UNSUPPORTED_MODEL_TYPE,
+        ErrorKind::UnsupportedObjFunctionType(_) => XGBP_ERR_UNSUPPORTED_OBJECTIVE,
+        ErrorKind::UnsupportedPredictionMethod(..) => XGBP_ERR_UNSUPPORTED_PREDICTION_METHOD,
+        ErrorKind::UnavailableDataIndex(_) => XGBP_ERR_UNAVAILABLE_DATA_INDEX,
+        ErrorKind::UnimplementedFunc(_) => XGBP_ERR_UNIMPLEMENTED,
+        ErrorKind::BrokenModel(_) => XGBP_ERR_BROKEN_MODEL,
+        ErrorKind::UnsupportedDataType(_) => XGBP_ERR_UNSUPPORTED_DATA_TYPE,
+        ErrorKind::InvalidInputShape(..) => XGBP_ERR_INVALID_INPUT_SHAPE,
+        ErrorKind::FeatureMismatch(..) => XGBP_ERR_FEATURE_MISMATCH,
+        ErrorKind::InvalidArgument(_) => XGBP_ERR_INVALID_ARGUMENT,
+        _ => XGBP_ERR_OTHER,
+    }
+}
+
+/// C string of a Rust string, without its NUL characters
+fn c_string(value: &str) -> CString {
+    CString::new(value.replace('\0', "")).unwrap()
+}
+
+fn invalid_argument(message: String) -> Error {
+    Error::from_kind(ErrorKind::InvalidArgument(message))
+}
+
+/// Runs the body of a function, turning its errors and panics into error codes
+fn call<F: FnOnce() -> Result<()>>(body: F) -> c_int {
+    let (code, message) = match panic::catch_unwind(AssertUnwindSafe(body)) {
+        Ok(Ok(())) => return XGBP_OK,
+        Ok(Err(error)) => (error_code(error.kind()), error.to_string()),
+        Err(_) => (XGBP_ERR_OTHER, String::from("panic in xgboost-predictor")),
+    };
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = c_string(&message));
+    code
+}
+
+unsafe fn handle_ref<'a>(handle: *const XGBPredictor) -> Result<&'a XGBPredictor> {
+    handle
+        .as_ref()
+        .ok_or_else(|| invalid_argument(String::from("null handle")))
+}
+
+unsafe fn write_out<T>(out: *mut T, value: T) -> Result<()> {
+    if out.is_null() {
+        return Err(invalid_argument(String::from("null output pointer")));
+    }
+    *out = value;
+    Ok(())
+}
+
+/// Slice of `len` values at `data`, which may be null when `len` is 0
+unsafe fn input_slice<'a, T>(data: *const T, len: usize, name: &str) -> Result<&'a [T]> {
+    if len == 0 {
+        return Ok(&[]);
+    }
+    if data.is_null() {
+        return Err(invalid_argument(format!("null {}", name)));
+    }
+    Ok(slice::from_raw_parts(data, len))
+}
+
+fn checked_len(num_rows: usize, row_size: usize) -> Result<usize> {
+    num_rows
+        .checked_mul(row_size)
+        .ok_or_else(|| invalid_argument(format!("{} rows of {} values", num_rows, row_size)))
+}
+
+unsafe fn load(out: *mut *mut XGBPredictor, predictor: Predictor) -> Result<()> {
+    let handle = XGBPredictor {
+        objective: c_string(predictor.objective_name()),
+        feature_names: predictor
+            .feature_names()
+            .map(|names| names.iter().map(|name| c_string(name)).collect()),
+        predictor,
+    };
+    write_out(out, Box::into_raw(Box::new(handle)))
+}
+
+impl XGBPredictor {
+    /// Values per row of the predictions of a kind
+    fn output_size(&self, kind: c_int, ntree_limit: usize) -> Result<usize> {
+        match kind {
+            XGBP_PREDICT_VALUE => Ok(self.predictor.num_output(false)),
+            XGBP_PREDICT_MARGIN => Ok(self.predictor.num_output(true)),
+            XGBP_PREDICT_LEAF => self.predictor.num_leaf_output(ntree_limit),
+            XGBP_PREDICT_CONTRIBS => Ok(self.predictor.num_contribs_output()),
+            _ => Err(invalid_argument(format!(
+                "unknown prediction kind {}",
+                kind
+            ))),
+        }
+    }
+
+    fn predict(
+        &self,
+        feats: ArrayView2<'_, FeatureValue>,
+        kind: c_int,
+        ntree_limit: usize,
+    ) -> Result<Vec<Vec<f32>>> {
+        match kind {
+            XGBP_PREDICT_VALUE => self.predictor.predict_many(feats, false, ntree_limit),
+            XGBP_PREDICT_MARGIN => self.predictor.predict_many(feats, true, ntree_limit),
+            XGBP_PREDICT_LEAF => Ok(self
+                .predictor
+                .predict_leaf(feats, ntree_limit)?
+                .into_iter()
+                .map(|row| row.into_iter().map(|leaf| leaf as f32).collect())
+                .collect()),
+            XGBP_PREDICT_CONTRIBS => self.predictor.predict_contribs(feats, ntree_limit),
+            _ => Err(invalid_argument(format!(
+                "unknown prediction kind {}",
+                kind
+            ))),
+        }
+    }
+
+    /// Predicts `num_rows` rows of `num_cols` columns into `out`. The rows are built
+    /// `CHUNK_ROWS` at a time by `fill_rows`, given the index of the first row and a matrix of
+    /// missing values to fill.
+    #[allow(clippy::too_many_arguments)]
+    unsafe fn predict_rows<F>(
+        &self,
+        num_rows: usize,
+        num_cols: usize,
+        kind: c_int,
+        ntree_limit: usize,
+        out: *mut f32,
+        out_len: usize,
+        fill_rows: F,
+    ) -> Result<()>
+    where
+        F: Fn(usize, &mut Array2<FeatureValue>),
+    {
+        let row_size = self.output_size(kind, ntree_limit)?;
+        let len = checked_len(num_rows, row_size)?;
+        if out_len < len {
+            return Err(invalid_argument(format!(
+                "output buffer of {} values, {} are predicted",
+                out_len, len
+            )));
+        }
+        if len == 0 {
+            return Ok(());
+        }
+        if out.is_null() {
+            return Err(invalid_argument(String::from("null output buffer")));
+        }
+        let out = slice::from_raw_parts_mut(out, len);
+        for begin in (0..num_rows).step_by(CHUNK_ROWS) {
+            let end = cmp::min(begin + CHUNK_ROWS, num_rows);
+            let mut feats = Array2::from_elem((end - begin, num_cols), FeatureValue::NAN);
+            fill_rows(begin, &mut feats);
+            let preds = self.predict(feats.view(), kind, ntree_limit)?;
+            let chunk_out = out[begin * row_size..end * row_size].chunks_mut(row_size);
+            for (row_out, row) in chunk_out.zip(preds) {
+                row_out.copy_from_slice(&row);
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Message of the last error of the calling thread, valid until the next error on the thread
+#[no_mangle]
+pub extern "C" fn xgbp_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| last_error.borrow().as_ptr())
+}
+
+/// Loads a model in the binary or JSON format of xgboost, or in the mapped format, from a file.
+/// The handle is released by `xgbp_free`.
+///
+/// # Safety
+///
+/// `path` is a NUL-terminated string and `out` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn xgbp_load_path(path: *const c_char, out: *mut *mut XGBPredictor) -> c_int {
+    call(|| {
+        if path.is_null() {
+            return Err(invalid_argument(String::from("null path")));
+        }
+        let path = CStr::from_ptr(path)
+            .to_str()
+            .map_err(|error| invalid_argument(format!("path is not UTF-8, {}", error)))?;
+        let predictor = Predictor::read_any(&mut BufReader::new(File::open(path)?))?;
+        load(out, predictor)
+    })
+}
+
+/// Loads a model from the `len` bytes at `data`, which are copied
+///
+/// # Safety
+///
+/// `data` points to `len` bytes and `out` is a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn xgbp_load_buffer(
+    data: *const u8,
+    len: usize,
+    out: *mut *mut XGBPredictor,
+) -> c_int {
+    call(|| {
+        let mut bytes = input_slice(data, len, "model buffer")?;
+        load(out, Predictor::read_any(&mut bytes)?)
+    })
+}
+
+/// Releases a handle, null handles are ignored
+///
+/// # Safety
+///
+/// `handle` was given by a load function and is no longer used by any thread.
+#[no_mangle]
+pub unsafe extern "C" fn xgbp_free(handle: *mut XGBPredictor) {
+    if !handle.is_null() {
+        drop(Box::from_raw(handle));
+    }
+}
+
+/// Number of features of the model
+///
+/// # Safety
+///
+/// `handle` is a live handle and `out` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn xgbp_num_feature(handle: *const XGBPredictor, out: *mut usize) -> c_int {
+    call(|| write_out(out, handle_ref(handle)?.predictor.model_num_feature()))
+}
+
+/// Number of classes of multiclass models, 0 otherwise
+///
+/// # Safety
+///
+/// `handle` is a live handle and `out` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn xgbp_num_class(handle: *const XGBPredictor, out: *mut usize) -> c_int {
+    call(|| write_out(out, handle_ref(handle)?.predictor.num_class()))
+}
+
+/// Number of margins predicted per row
+///
+/// # Safety
+///
+/// `handle` is a live handle and `out` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn xgbp_num_output_group(
+    handle: *const XGBPredictor,
+    out: *mut usize,
+) -> c_int {
+    call(|| write_out(out, handle_ref(handle)?.predictor.num_output_group()))
+}
+
+/// Base score of the model, as a margin
+///
+/// # Safety
+///
+/// `handle` is a live handle and `out` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn xgbp_base_score(handle: *const XGBPredictor, out: *mut f32) -> c_int {
+    call(|| write_out(out, handle_ref(handle)?.predictor.base_score()))
+}
+
+/// Name of the objective, e.g. "binary:logistic", valid as long as the handle
+///
+/// # Safety
+///
+/// `handle` is a live handle and `out` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn xgbp_objective(
+    handle: *const XGBPredictor,
+    out: *mut *const c_char,
+) -> c_int {
+    call(|| write_out(out, handle_ref(handle)?.objective.as_ptr()))
+}
+
+/// Name of a feature, valid as long as the handle, or null when the model has no feature names
+///
+/// # Safety
+///
+/// `handle` is a live handle and `out` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn xgbp_feature_name(
+    handle: *const XGBPredictor,
+    index: usize,
+    out: *mut *const c_char,
+) -> c_int {
+    call(|| {
+        let handle = handle_ref(handle)?;
+        let name = match &handle.feature_names {
+            Some(names) => match names.get(index) {
+                Some(name) => name.as_ptr(),
+                None => {
+                    return Err(Error::from_kind(ErrorKind::UnavailableDataIndex(index)));
+                }
+            },
+            None => ptr::null(),
+        };
+        write_out(out, name)
+    })
+}
+
+/// Number of values per row of the predictions of a kind, `XGBP_PREDICT_*`. `ntree_limit`
+/// limits the number of trees of each output group, 0 meaning all of them.
+///
+/// # Safety
+///
+/// `handle` is a live handle and `out` a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn xgbp_output_size(
+    handle: *const XGBPredictor,
+    kind: c_int,
+    ntree_limit: usize,
+    out: *mut usize,
+) -> c_int {
+    call(|| write_out(out, handle_ref(handle)?.output_size(kind, ntree_limit)?))
+}
+
+/// Predicts rows of `num_cols` values laid out row after row, values equal to `missing` or NaN
+/// being missing. `out` holds at least `num_rows` times `xgbp_output_size` values.
+///
+/// # Safety
+///
+/// `handle` is a live handle, `data` points to `num_rows * num_cols` values and `out` to
+/// `out_len` values.
+#[no_mangle]
+pub unsafe extern "C" fn xgbp_predict_dense(
+    handle: *const XGBPredictor,
+    data: *const f32,
+    num_rows: usize,
+    num_cols: usize,
+    missing: f32,
+    kind: c_int,
+    ntree_limit: usize,
+    out: *mut f32,
+    out_len: usize,
+) -> c_int {
+    call(|| {
+        let handle = handle_ref(handle)?;
+        let data = input_slice(data, checked_len(num_rows, num_cols)?, "data")?;
+        handle.predict_rows(
+            num_rows,
+            num_cols,
+            kind,
+            ntree_limit,
+            out,
+            out_len,
+            |begin, feats| {
+                let rows = &data[begin * num_cols..(begin + feats.nrows()) * num_cols];
+                for (feat, value) in feats.iter_mut().zip(rows) {
+                    if *value != missing {
+                        *feat = value.to_feature_value();
+                    }
+                }
+            },
+        )
+    })
+}
+
+/// Predicts rows in the compressed sparse row format: the values of row `i` are
+/// `data[indptr[i]..indptr[i + 1]]`, of the columns given by `indices`. Values not given are
+/// missing. `out` holds at least `num_rows` times `xgbp_output_size` values.
+///
+/// # Safety
+///
+/// `handle` is a live handle, `indptr` points to `num_rows + 1` offsets, `indices` and `data`
+/// to `indptr[num_rows]` values and `out` to `out_len` values.
+#[no_mangle]
+pub unsafe extern "C" fn xgbp_predict_csr(
+    handle: *const XGBPredictor,
+    indptr: *const usize,
+    indices: *const u32,
+    data: *const f32,
+    num_rows: usize,
+    num_cols: usize,
+    kind: c_int,
+    ntree_limit: usize,
+    out: *mut f32,
+    out_len: usize,
+) -> c_int {
+    call(|| {
+        let handle = handle_ref(handle)?;
+        let num_offsets = num_rows
+            .checked_add(1)
+            .ok_or_else(|| invalid_argument(format!("{} rows", num_rows)))?;
+        let indptr = input_slice(indptr, num_offsets, "indptr")?;
+        let num_values = indptr[num_rows];
+        if indptr.windows(2).any(|offsets| offsets[0] > offsets[1]) {
+            return Err(invalid_argument(String::from("decreasing indptr")));
+        }
+        let indices = input_slice(indices, num_values, "indices")?;
+        let data = input_slice(data, num_values, "data")?;
+        if let Some(index) = indices.iter().find(|index| **index as usize >= num_cols) {
+            return Err(invalid_argument(format!(
+                "column {} of {} columns",
+                index, num_cols
+            )));
+        }
+        handle.predict_rows(
+            num_rows,
+            num_cols,
+            kind,
+            ntree_limit,
+            out,
+            out_len,
+            |begin, feats| {
+                for (i, mut feat) in feats.outer_iter_mut().enumerate() {
+                    let (start, end) = (indptr[begin + i], indptr[begin + i + 1]);
+                    for (index, value) in indices[start..end].iter().zip(&data[start..end]) {
+                        feat[*index as usize] = value.to_feature_value();
+                    }
+                }
+            },
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::{CStr, CString};
+    use std::io::Write;
+    use std::os::raw::c_char;
+    use std::ptr;
+
+    use crate::testing::{gblinear_model, random_gbtree_model, random_matrix, JSON_MODEL};
+
+    use super::*;
+
+    fn load_buffer(bytes: &[u8]) -> *mut XGBPredictor {
+        let mut handle = ptr::null_mut();
+        let code = unsafe { xgbp_load_buffer(bytes.as_ptr(), bytes.len(), &mut handle) };
+        assert_eq!(code, XGBP_OK);
+        handle
+    }
+
+    fn last_error() -> String {
+        unsafe { CStr::from_ptr(xgbp_last_error()) }
+            .to_str()
+            .unwrap()
+            .to_owned()
+    }
+
+    fn output_size(handle: *const XGBPredictor, kind: c_int) -> usize {
+        let mut size = 0;
+        assert_eq!(
+            unsafe { xgbp_output_size(handle, kind, 0, &mut size) },
+            XGBP_OK
+        );
+        size
+    }
+
+    #[test]
+    fn test_load_and_metadata() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(JSON_MODEL.as_bytes()).unwrap();
+        let path = CString::new(file.path().to_str().unwrap()).unwrap();
+        let mut handle = ptr::null_mut();
+        assert_eq!(
+            unsafe { xgbp_load_path(path.as_ptr(), &mut handle) },
+            XGBP_OK
+        );
+
+        let (mut num_feature, mut num_group, mut base_score) = (0, 0, 0f32);
+        let mut objective: *const c_char = ptr::null();
+        let mut name: *const c_char = ptr::null();
+        unsafe {
+            assert_eq!(xgbp_num_feature(handle, &mut num_feature), XGBP_OK);
+            assert_eq!(xgbp_num_output_group(handle, &mut num_group), XGBP_OK);
+            assert_eq!(xgbp_base_score(handle, &mut base_score), XGBP_OK);
+            assert_eq!(xgbp_objective(handle, &mut objective), XGBP_OK);
+            assert_eq!(xgbp_feature_name(handle, 1, &mut name), XGBP_OK);
+            assert_eq!((num_feature, num_group, base_score), (2, 1, 0.0));
+            assert_eq!(CStr::from_ptr(objective).to_str(), Ok("binary:logistic"));
+            assert_eq!(CStr::from_ptr(name).to_str(), Ok("color"));
+            assert_eq!(
+                xgbp_feature_name(handle, 2, &mut name),
+                XGBP_ERR_UNAVAILABLE_DATA_INDEX
+            );
+            xgbp_free(handle);
+        }
+
+        let missing = CString::new("/nonexistent/model.json").unwrap();
+        assert_eq!(
+            unsafe { xgbp_load_path(missing.as_ptr(), &mut handle) },
+            XGBP_ERR_IO
+        );
+        let code = unsafe { xgbp_load_buffer(b"binf".as_ptr(), 4, &mut handle) };
+        assert_eq!(code, XGBP_ERR_IO);
+        assert!(!last_error().is_empty());
+    }
+
+    #[test]
+    fn test_predict_dense_and_csr() {
+        let bytes = random_gbtree_model(43, "multi:softprob", 6, 3, 20, 5);
+        let predictor = Predictor::read_from(&mut &bytes[..]).unwrap();
+        let handle = load_buffer(&bytes);
+        let feats = random_matrix(47, 300, 6, 0.3);
+
+        // missing values given as -1 in the dense rows and left out of the sparse rows
+        let dense: Vec<f32> = feats
+            .iter()
+            .map(|value| {
+                if value.is_nan() {
+                    -1.0
+                } else {
+                    crate::to_f32(*value)
+                }
+            })
+            .collect();
+        let (mut indptr, mut indices, mut data) = (vec![0usize], vec![], vec![]);
+        for row in feats.outer_iter() {
+            for (index, value) in row.iter().enumerate() {
+                if !value.is_nan() {
+                    indices.push(index as u32);
+                    data.push(crate::to_f32(*value));
+                }
+            }
+            indptr.push(data.len());
+        }
+
+        for kind in &[
+            XGBP_PREDICT_VALUE,
+            XGBP_PREDICT_MARGIN,
+            XGBP_PREDICT_LEAF,
+            XGBP_PREDICT_CONTRIBS,
+        ] {
+            let expected: Vec<f32> = match *kind {
+                XGBP_PREDICT_VALUE => predictor.predict_many(feats.view(), false, 0),
+                XGBP_PREDICT_MARGIN => predictor.predict_many(feats.view(), true, 0),
+                XGBP_PREDICT_LEAF => predictor.predict_leaf(feats.view(), 0).map(|leaves| {
+                    leaves
+                        .into_iter()
+                        .map(|row| row.into_iter().map(|leaf| leaf as f32).collect())
+                        .collect()
+                }),
+                _ => predictor.predict_contribs(feats.view(), 0),
+            }
+            .unwrap()
+            .concat();
+            assert_eq!(expected.len(), 300 * output_size(handle, *kind));
+
+            let mut out = vec![0f32; expected.len()];
+            let code = unsafe {
+                xgbp_predict_dense(
+                    handle,
+                    dense.as_ptr(),
+                    300,
+                    6,
+                    -1.0,
+                    *kind,
+                    0,
+                    out.as_mut_ptr(),
+                    out.len(),
+                )
+            };
+            assert_eq!(code, XGBP_OK);
+            assert_eq!(out, expected);
+
+            let mut out = vec![0f32; expected.len()];
+            let code = unsafe {
+                xgbp_predict_csr(
+                    handle,
+                    indptr.as_ptr(),
+                    indices.as_ptr(),
+                    data.as_ptr(),
+                    300,
+                    6,
+                    *kind,
+                    0,
+                    out.as_mut_ptr(),
+                    out.len(),
+                )
+            };
+            assert_eq!(code, XGBP_OK);
+            assert_eq!(out, expected);
+        }
+        unsafe { xgbp_free(handle) };
+    }
+
+    #[test]
+    fn test_shared_handle() {
+        let bytes = random_gbtree_model(53, "reg:linear", 4, 1, 50, 6);
+        let handle = load_buffer(&bytes) as usize;
+        let feats = random_matrix(59, 1000, 4, 0.1).mapv(crate::to_f32);
+        let predict = || {
+            let mut out = vec![0f32; 1000];
+            let code = unsafe {
+                xgbp_predict_dense(
+                    handle as *const XGBPredictor,
+                    feats.as_ptr(),
+                    1000,
+                    4,
+                    f32::NAN,
+                    XGBP_PREDICT_VALUE,
+                    0,
+                    out.as_mut_ptr(),
+                    out.len(),
+                )
+            };
+            assert_eq!(code, XGBP_OK);
+            out
+        };
+        let expected = predict();
+        std::thread::scope(|scope| {
+            let threads: Vec<_> = (0..4).map(|_| scope.spawn(predict)).collect();
+            for thread in threads {
+                assert_eq!(thread.join().unwrap(), expected);
+            }
+        });
+        unsafe { xgbp_free(handle as *mut XGBPredictor) };
+    }
+
+    #[test]
+    fn test_errors() {
+        let weights = [1.0, 2.0, 0.5];
+        let handle = load_buffer(&gblinear_model("reg:linear", 0.5, 2, 1, &weights));
+        let feats = [1.0f32, 2.0, 3.0];
+        let mut out = [0f32; 3];
+        let predict = |num_cols: usize, kind: c_int, out: &mut [f32]| unsafe {
+            xgbp_predict_dense(
+                handle,
+                feats.as_ptr(),
+                3 / num_cols,
+                num_cols,
+                f32::NAN,
+                kind,
+                0,
+                out.as_mut_ptr(),
+                out.len(),
+            )
+        };
+
+        assert_eq!(
+            predict(1, XGBP_PREDICT_VALUE, &mut out),
+            XGBP_ERR_INVALID_INPUT_SHAPE
+        );
+        assert_eq!(
+            last_error(),
+            "Feature shape mismatch, expected: 2 features, got: 1 columns"
+        );
+        assert_eq!(
+            predict(2, XGBP_PREDICT_LEAF, &mut out),
+            XGBP_ERR_UNSUPPORTED_PREDICTION_METHOD
+        );
+        let mut size = 0;
+        assert_eq!(
+            unsafe { xgbp_output_size(handle, XGBP_PREDICT_LEAF, 0, &mut size) },
+            XGBP_ERR_UNSUPPORTED_PREDICTION_METHOD
+        );
+        assert_eq!(predict(3, 7, &mut out), XGBP_ERR_INVALID_ARGUMENT);
+        // one row of 3 contributions
+        assert_eq!(
+            predict(3, XGBP_PREDICT_CONTRIBS, &mut out[..2]),
+            XGBP_ERR_INVALID_ARGUMENT
+        );
+        assert_eq!(
+            last_error(),
+            "Invalid argument: output buffer of 2 values, 3 are predicted"
+        );
+
+        let indptr = [0usize, 1];
+        let code = unsafe {
+            xgbp_predict_csr(
+                handle,
+                indptr.as_ptr(),
+                [2u32].as_ptr(),
+                feats.as_ptr(),
+                1,
+                2,
+                XGBP_PREDICT_VALUE,
+                0,
+                out.as_mut_ptr(),
+                out.len(),
+            )
+        };
+        assert_eq!(code, XGBP_ERR_INVALID_ARGUMENT);
+        let mut num_feature = 0;
+        assert_eq!(
+            unsafe { xgbp_num_feature(ptr::null(), &mut num_feature) },
+            XGBP_ERR_INVALID_ARGUMENT
+        );
+        unsafe { xgbp_free(handle) };
+    }
+}
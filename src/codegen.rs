@@ -0,0 +1,337 @@
+//! Ahead-of-time compilation of a model to Rust source.
+//!
+//! Each tree becomes a function of nested `if` statements, the objective transform is inlined
+//! and the base score is folded into the generated constants. The source is meant to be
+//! included in a module of the crate using it:
+//!
+//! ```ignore
+//! mod model {
+//!     include!(concat!(env!("OUT_DIR"), "/model.rs"));
+//! }
+//!
+//! let preds = model::predict(&[1.0, f32::NAN, 0.5]);
+//! ```
+//!
+//! where `model.rs` is written by `build_rs` from the build script. The generated functions
+//! give the same bits as `Predictor::predict_many`.
+use std::fmt::Write;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::errors::*;
+use crate::functions::FunctionType;
+use crate::gbm::flat_tree::FlatTree;
+use crate::gbm::gblinear::GBLinear;
+use crate::gbm::gbtree::GBTree;
+use crate::gbm::grad_booster::BoosterModel;
+use crate::predictor::Predictor;
+use crate::source_gen::{self, Syntax};
+
+struct Rust;
+
+impl Syntax for Rust {
+    const TRUE: &'static str = "true";
+
+    fn f32_literal(value: f32) -> String {
+        f32_literal(value)
+    }
+
+    fn if_statement(condition: &str) -> String {
+        format!("if {} {{", condition)
+    }
+
+    fn is_nan(fvalue: &str) -> String {
+        format!("{}.is_nan()", fvalue)
+    }
+
+    fn is_category(
+        fvalue: &str,
+        tree: &FlatTree,
+        _tid: usize,
+        _nid: usize,
+        categories: usize,
+    ) -> String {
+        format!(
+            "is_category({}, &{:?})",
+            fvalue,
+            tree.categories(categories)
+        )
+    }
+}
+
+/// Formats a float as a Rust literal giving back the same bits
+fn f32_literal(value: f32) -> String {
+    if value.is_nan() {
+        String::from("f32::NAN")
+    } else if value == f32::INFINITY {
+        String::from("f32::INFINITY")
+    } else if value == f32::NEG_INFINITY {
+        String::from("f32::NEG_INFINITY")
+    } else {
+        format!("{:?}f32", value)
+    }
+}
+
+fn write_trees(out: &mut String, gbtree: &GBTree, num_feature: usize) -> Result<()> {
+    source_gen::check_split_indices(gbtree, num_feature)?;
+    if source_gen::has_categorical(gbtree) {
+        out.push_str(
+            "/// Whether a category is in the sorted list of categories of a split
+fn is_category(fvalue: f32, categories: &[u32]) -> bool {
+    if fvalue < 0f32 || fvalue >= (1u32 << f32::MANTISSA_DIGITS) as f32 {
+        return false;
+    }
+    categories.binary_search(&(fvalue as u32)).is_ok()
+}
+
+",
+        );
+    }
+
+    for (tid, tree) in gbtree.trees().iter().enumerate() {
+        let tree = tree.flat();
+        writeln!(out, "#[inline]").unwrap();
+        let features = if tree.max_split_index().is_some() {
+            "features"
+        } else {
+            "_features"
+        };
+        writeln!(
+            out,
+            "fn tree_{}({}: &[f32; NUM_FEATURE]) -> f32 {{",
+            tid, features
+        )
+        .unwrap();
+        source_gen::write_node::<Rust>(out, tree, tid, 0, 1);
+        out.push_str("}\n\n");
+    }
+
+    let num_group = gbtree.num_output_group();
+    out.push_str("/// Margins of the output groups\n#[allow(dead_code)]\n");
+    out.push_str(
+        "pub fn predict_margin(features: &[f32; NUM_FEATURE]) -> [f32; NUM_OUTPUT_GROUP] {\n",
+    );
+    for gid in 0..num_group {
+        writeln!(out, "    let mut margin_{} = 0f32;", gid).unwrap();
+    }
+    for (group, term) in source_gen::margin_terms::<Rust>(gbtree) {
+        writeln!(out, "    margin_{} += {};", group, term).unwrap();
+    }
+    let margins: Vec<String> = (0..num_group)
+        .map(|gid| format!("BASE_SCORE + margin_{}", gid))
+        .collect();
+    writeln!(out, "    [{}]\n}}\n", margins.join(", ")).unwrap();
+    Ok(())
+}
+
+fn write_linear(out: &mut String, gblinear: &GBLinear, base_score: f32) {
+    let num_feature = gblinear.num_feature();
+    let num_group = gblinear.num_output_group();
+    for gid in 0..num_group {
+        let weights: Vec<String> = (0..num_feature)
+            .map(|fid| f32_literal(gblinear.weight(fid, gid)))
+            .collect();
+        writeln!(
+            out,
+            "const WEIGHTS_{}: [f32; NUM_FEATURE] = [{}];",
+            gid,
+            weights.join(", ")
+        )
+        .unwrap();
+        // the base score is folded into the bias
+        writeln!(
+            out,
+            "const BIAS_{}: f32 = {};",
+            gid,
+            f32_literal(gblinear.bias(gid) + base_score)
+        )
+        .unwrap();
+    }
+    out.push_str(
+        "\n/// Margins of the output groups, missing values do not contribute\n#[allow(dead_code)]\n",
+    );
+    out.push_str(
+        "pub fn predict_margin(features: &[f32; NUM_FEATURE]) -> [f32; NUM_OUTPUT_GROUP] {\n",
+    );
+    let margins: Vec<String> = (0..num_group)
+        .map(|gid| {
+            format!(
+                "
+        features
+            .iter()
+            .zip(WEIGHTS_{}.iter())
+            .filter(|(fvalue, _)| !fvalue.is_nan())
+            .map(|(fvalue, weight)| fvalue * weight)
+            .sum::<f32>()
+            + BIAS_{},",
+                gid, gid
+            )
+        })
+        .collect();
+    writeln!(out, "    [{}\n    ]\n}}\n", margins.join("")).unwrap();
+}
+
+/// The same computations as `functions::get_classify_function`
+fn write_transform(out: &mut String, objective: FunctionType) {
+    out.push_str(
+        "/// Predictions: the margins transformed by the objective function
+#[allow(dead_code)]
+pub fn predict(features: &[f32; NUM_FEATURE]) -> Vec<f32> {
+    let preds = predict_margin(features);
+",
+    );
+    let body = match objective {
+        FunctionType::RankPairwise | FunctionType::BinaryLogitraw | FunctionType::RegLinear => {
+            "    preds.to_vec()\n"
+        }
+        FunctionType::BinaryLogistic => {
+            "    preds.iter().map(|x| 1f32 / (1f32 + (-x).exp())).collect()\n"
+        }
+        FunctionType::MultiSoftmax => {
+            "    let (max_index, _max) = preds.iter().enumerate().fold(
+        (0, &preds[0]),
+        |(i1, v1), (i2, v2)| if v1 >= v2 { (i1, v1) } else { (i2, v2) },
+    );
+    vec![max_index as f32; 1]
+"
+        }
+        FunctionType::MultiSoftprob => {
+            "    let max = preds.iter().fold(preds[0], |a, b| b.max(a));
+    let sum: f32 = preds.iter().map(|x| (x - max).exp()).sum();
+    preds.iter().map(|x| (x - max).exp() / sum).collect()
+"
+        }
+    };
+    out.push_str(body);
+    out.push_str("}\n");
+}
+
+/// Generates the Rust source of the model: constants `NUM_FEATURE`, `NUM_OUTPUT_GROUP` and
+/// `FEATURE_NAMES` when the model has names, and functions `predict_margin` and `predict`
+/// taking the features of a row, missing values being NaN
+pub fn generate(predictor: &Predictor) -> Result<String> {
+    let num_feature = predictor.model_num_feature();
+    let mut out = String::from("// Generated by xgboost-predictor, do not edit.\n\n");
+    writeln!(out, "pub const NUM_FEATURE: usize = {};", num_feature).unwrap();
+    let num_group = source_gen::num_output_group(predictor, "codegen")?;
+    writeln!(out, "pub const NUM_OUTPUT_GROUP: usize = {};", num_group).unwrap();
+    if let Some(names) = predictor.feature_names() {
+        writeln!(
+            out,
+            "#[allow(dead_code)]\npub const FEATURE_NAMES: [&str; NUM_FEATURE] = {:?};",
+            names
+        )
+        .unwrap();
+    }
+    out.push('\n');
+
+    match predictor.booster() {
+        BoosterModel::Trees(gbtree) => {
+            writeln!(
+                out,
+                "const BASE_SCORE: f32 = {};\n",
+                f32_literal(predictor.base_score())
+            )
+            .unwrap();
+            write_trees(&mut out, gbtree, num_feature)?;
+        }
+        BoosterModel::Linear(gblinear) => write_linear(&mut out, gblinear, predictor.base_score()),
+        // rejected above
+        BoosterModel::Compact(_) | BoosterModel::Mapped(_) => unreachable!(),
+    }
+    write_transform(&mut out, predictor.objective());
+    Ok(out)
+}
+
+/// Helper of build scripts: generates the source of the model at `model_path` into
+/// `$OUT_DIR/file_name`, asks cargo to rerun the script when the model changes and returns
+/// the path of the source
+pub fn build_rs<P: AsRef<Path>>(model_path: P, file_name: &str) -> Result<PathBuf> {
+    let model_path = model_path.as_ref();
+    let out_dir = std::env::var_os("OUT_DIR").ok_or_else(|| {
+        io::Error::new(
+            io::ErrorKind::NotFound,
+            "OUT_DIR is not set, build_rs is meant to be called from a build script",
+        )
+    })?;
+    println!("cargo:rerun-if-changed={}", model_path.display());
+    let predictor = Predictor::read_from(&mut fs::File::open(model_path)?)?;
+    let source_path = Path::new(&out_dir).join(file_name);
+    fs::write(&source_path, generate(&predictor)?)?;
+    Ok(source_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fmt::Write;
+    use std::fs;
+    use std::process::Command;
+
+    use ndarray::Array2;
+
+    use crate::predictor::Predictor;
+    use crate::source_gen::fixtures::{expected_output, models};
+    use crate::{to_f32, FeatureValue};
+
+    use super::generate;
+
+    /// Compiles the generated source of the model with a program printing the bits of the
+    /// margins and predictions of the rows, and checks them against the interpreter
+    fn check_generated(name: &str, predictor: &Predictor, feats: &Array2<FeatureValue>) {
+        let dir = tempfile::tempdir().unwrap();
+        fs::write(dir.path().join("model.rs"), generate(predictor).unwrap()).unwrap();
+        let mut main = String::from("mod model {\n    include!(\"model.rs\");\n}\n\nfn main() {\n");
+        for row in feats.outer_iter() {
+            let values: Vec<String> = row
+                .iter()
+                .map(|value| format!("f32::from_bits({:#x})", to_f32(*value).to_bits()))
+                .collect();
+            writeln!(
+                main,
+                "    let row = [{}];\n    for value in model::predict_margin(&row).iter().chain(&model::predict(&row)) {{\n        print!(\"{{:08x}} \", value.to_bits());\n    }}\n    println!();",
+                values.join(", ")
+            )
+            .unwrap();
+        }
+        main.push_str("}\n");
+        fs::write(dir.path().join("main.rs"), main).unwrap();
+
+        let rustc = std::env::var("RUSTC").unwrap_or_else(|_| String::from("rustc"));
+        let binary = dir.path().join(name);
+        let status = Command::new(rustc)
+            .args([
+                "--edition",
+                "2018",
+                "-C",
+                "opt-level=1",
+                "-D",
+                "warnings",
+                "-o",
+            ])
+            .arg(&binary)
+            .arg(dir.path().join("main.rs"))
+            .status()
+            .unwrap();
+        assert!(
+            status.success(),
+            "generated code of {} does not compile",
+            name
+        );
+        let output = Command::new(&binary).output().unwrap();
+        let output = String::from_utf8(output.stdout).unwrap();
+        assert_eq!(
+            output.lines().collect::<Vec<&str>>(),
+            expected_output(predictor, feats),
+            "{}",
+            name
+        );
+    }
+
+    #[test]
+    fn test_generated_code_matches_interpreter() {
+        for (name, predictor, feats) in &models() {
+            check_generated(name, predictor, feats);
+        }
+    }
+}
@@ -0,0 +1,639 @@
+//! Text and JSON dumps of the trees, the same as the ones of `Booster.get_dump` of xgboost.
+//!
+//! ```text
+//! 0:[f0<30] yes=1,no=2,missing=2,gain=2,cover=10
+//!     1:leaf=0.25,cover=4
+//!     2:[f1:{1,3}] yes=4,no=3,missing=3,gain=1,cover=6
+//! ```
+//!
+//! where nodes are indented with a tab per level.
+//! Features are named `f<index>` unless a feature map names them. The feature map also gives
+//! the type of the features: thresholds of integer features are rounded up and indicator
+//! features are printed without a condition.
+use std::fmt::Write;
+use std::io::BufRead;
+
+use crate::errors::*;
+use crate::gbm::flat_tree::FlatNode;
+use crate::gbm::grad_booster::BoosterModel;
+use crate::gbm::regtree::RegTree;
+use crate::predictor::Predictor;
+
+/// Format of the dump of a tree
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum DumpFormat {
+    /// one line per node, indented with tabs
+    Text,
+    /// JSON object of the root node, holding its children
+    Json,
+}
+
+impl DumpFormat {
+    pub fn from_name(name: &str) -> Result<DumpFormat> {
+        match name {
+            "text" => Ok(DumpFormat::Text),
+            "json" => Ok(DumpFormat::Json),
+            _ => Err(Error::from_kind(ErrorKind::InvalidArgument(format!(
+                "dump format {}, expected text or json",
+                name
+            )))),
+        }
+    }
+}
+
+/// Type of a feature of a feature map
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeatureMapType {
+    /// `i`, a binary feature
+    Indicator,
+    /// `q`
+    Quantitative,
+    /// `int`
+    Integer,
+    /// `float`
+    Float,
+    /// `c`
+    Categorical,
+}
+
+impl FeatureMapType {
+    pub fn from_name(name: &str) -> Result<FeatureMapType> {
+        match name {
+            "i" => Ok(FeatureMapType::Indicator),
+            "q" => Ok(FeatureMapType::Quantitative),
+            "int" => Ok(FeatureMapType::Integer),
+            "float" => Ok(FeatureMapType::Float),
+            "c" => Ok(FeatureMapType::Categorical),
+            _ => Err(Error::from_kind(ErrorKind::InvalidArgument(format!(
+                "feature type {}, expected i, q, int, float or c",
+                name
+            )))),
+        }
+    }
+}
+
+/// Names and types of the features, as in the `fmap.txt` files of xgboost
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FeatureMap {
+    features: Vec<(String, FeatureMapType)>,
+}
+
+impl FeatureMap {
+    pub fn new() -> FeatureMap {
+        FeatureMap::default()
+    }
+
+    /// Adds the next feature
+    pub fn push(&mut self, name: &str, feature_type: FeatureMapType) {
+        self.features.push((String::from(name), feature_type));
+    }
+
+    /// Reads `<index> <name> <type>` entries separated by whitespace, indices counting from 0
+    pub fn read_from<R: BufRead>(reader: R) -> Result<FeatureMap> {
+        let mut text = String::new();
+        for line in reader.lines() {
+            text.push_str(&line?);
+            text.push('\n');
+        }
+        let tokens: Vec<&str> = text.split_whitespace().collect();
+        let mut fmap = FeatureMap::new();
+        for entry in tokens.chunks(3) {
+            let invalid = || {
+                Error::from_kind(ErrorKind::InvalidArgument(format!(
+                    "feature map entry '{}'",
+                    entry.join(" ")
+                )))
+            };
+            if entry.len() != 3 || entry[0].parse::<usize>().ok() != Some(fmap.len()) {
+                return Err(invalid());
+            }
+            fmap.push(entry[1], FeatureMapType::from_name(entry[2])?);
+        }
+        Ok(fmap)
+    }
+
+    pub fn len(&self) -> usize {
+        self.features.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.features.is_empty()
+    }
+
+    pub fn name(&self, index: usize) -> &str {
+        &self.features[index].0
+    }
+
+    pub fn feature_type(&self, index: usize) -> FeatureMapType {
+        self.features[index].1
+    }
+}
+
+/// Formats a float as C++ streams of precision 9 do, that is as `%.9g`
+fn to_str(value: f32) -> String {
+    const PRECISION: i32 = 9;
+    if value.is_nan() {
+        return String::from(if value.is_sign_negative() {
+            "-nan"
+        } else {
+            "nan"
+        });
+    }
+    if value.is_infinite() {
+        return String::from(if value < 0.0 { "-inf" } else { "inf" });
+    }
+    if value == 0.0 {
+        return String::from(if value.is_sign_negative() { "-0" } else { "0" });
+    }
+    let value = f64::from(value);
+    // the exponent after rounding to the precision decides between fixed and scientific
+    let scientific = format!("{:.*e}", (PRECISION - 1) as usize, value);
+    let (mantissa, exponent) = scientific.split_once('e').unwrap();
+    let exponent: i32 = exponent.parse().unwrap();
+    let trim = |digits: &str| -> String {
+        if digits.contains('.') {
+            String::from(digits.trim_end_matches('0').trim_end_matches('.'))
+        } else {
+            String::from(digits)
+        }
+    };
+    if (-4..PRECISION).contains(&exponent) {
+        trim(&format!(
+            "{:.*}",
+            (PRECISION - 1 - exponent) as usize,
+            value
+        ))
+    } else {
+        let sign = if exponent < 0 { '-' } else { '+' };
+        format!("{}e{}{:02}", trim(mantissa), sign, exponent.abs())
+    }
+}
+
+/// Writes the trees in one of the formats
+struct TreeGenerator<'a> {
+    fmap: &'a FeatureMap,
+    with_stats: bool,
+    format: DumpFormat,
+}
+
+/// Condition of a split node, a threshold or a set of categories
+enum Condition {
+    Numerical(f32),
+    Categorical(usize),
+}
+
+/// Parts of a split node shared by the formats, nodes are given by their ids in the model
+struct Split {
+    nid: usize,
+    cleft: usize,
+    cright: usize,
+    /// positions of the children in the layout of the tree
+    children: (usize, usize),
+    split_index: usize,
+    default_left: bool,
+}
+
+impl Split {
+    fn default_child(&self) -> usize {
+        if self.default_left {
+            self.cleft
+        } else {
+            self.cright
+        }
+    }
+}
+
+impl TreeGenerator<'_> {
+    fn fname(&self, split_index: usize) -> String {
+        if split_index < self.fmap.len() {
+            String::from(self.fmap.name(split_index))
+        } else {
+            format!("f{}", split_index)
+        }
+    }
+
+    fn leaf(&self, tree: &RegTree, nid: usize, value: f32, depth: usize) -> String {
+        let cover = to_str(tree.sum_hess(nid));
+        let nid = tree.flat().node_id(nid);
+        match self.format {
+            DumpFormat::Text => {
+                let stats = if self.with_stats {
+                    format!(",cover={}", cover)
+                } else {
+                    String::new()
+                };
+                format!(
+                    "{}{}:leaf={}{}",
+                    "\t".repeat(depth),
+                    nid,
+                    to_str(value),
+                    stats
+                )
+            }
+            DumpFormat::Json => {
+                let stats = if self.with_stats {
+                    format!(", \"cover\": {} ", cover)
+                } else {
+                    String::new()
+                };
+                format!(
+                    "{{ \"nodeid\": {}, \"leaf\": {} {}}}",
+                    nid,
+                    to_str(value),
+                    stats
+                )
+            }
+        }
+    }
+
+    /// Split with a condition, `yes` and `no` being the children taken when it holds or not.
+    /// Categories are tested with `:` in the text format, thresholds with `<`.
+    fn conditional(
+        &self,
+        split: &Split,
+        operator: char,
+        cond: &str,
+        (yes, no): (usize, usize),
+        depth: usize,
+    ) -> String {
+        let fname = self.fname(split.split_index);
+        let missing = split.default_child();
+        match self.format {
+            DumpFormat::Text => format!(
+                "{}{}:[{}{}{}] yes={},no={},missing={}",
+                "\t".repeat(depth),
+                split.nid,
+                fname,
+                operator,
+                cond,
+                yes,
+                no,
+                missing
+            ),
+            DumpFormat::Json => format!(
+                " \"nodeid\": {}, \"depth\": {}, \"split\": \"{}\", \"split_condition\": {}, \
+                 \"yes\": {}, \"no\": {}, \"missing\": {}",
+                split.nid, depth, fname, cond, yes, no, missing
+            ),
+        }
+    }
+
+    /// Split of an indicator feature, whose `yes` child is the one of present values
+    fn indicator(&self, split: &Split, depth: usize) -> String {
+        let yes = if split.default_left {
+            split.cright
+        } else {
+            split.cleft
+        };
+        let fname = self.fmap.name(split.split_index);
+        let no = split.default_child();
+        match self.format {
+            // xgboost does not indent them
+            DumpFormat::Text => format!("{}:[{}] yes={},no={}", split.nid, fname, yes, no),
+            DumpFormat::Json => format!(
+                " \"nodeid\": {}, \"depth\": {}, \"split\": \"{}\", \"yes\": {}, \"no\": {}",
+                split.nid, depth, fname, yes, no
+            ),
+        }
+    }
+
+    fn split_node(&self, tree: &RegTree, nid: usize, depth: usize) -> Result<(String, Split)> {
+        let flat = tree.flat();
+        let (split, condition) = match flat.node(nid) {
+            FlatNode::Leaf(_) => unreachable!(),
+            FlatNode::Split {
+                cleft,
+                cright,
+                split_index,
+                split_cond,
+                default_left,
+            } => (
+                Split {
+                    nid: flat.node_id(nid),
+                    cleft: flat.node_id(cleft),
+                    cright: flat.node_id(cright),
+                    children: (cleft, cright),
+                    split_index,
+                    default_left,
+                },
+                Condition::Numerical(split_cond),
+            ),
+            FlatNode::CategoricalSplit {
+                cleft,
+                cright,
+                split_index,
+                default_left,
+                categories,
+            } => (
+                Split {
+                    nid: flat.node_id(nid),
+                    cleft: flat.node_id(cleft),
+                    cright: flat.node_id(cright),
+                    children: (cleft, cright),
+                    split_index,
+                    default_left,
+                },
+                Condition::Categorical(categories),
+            ),
+        };
+        let feature_type = if split.split_index < self.fmap.len() {
+            Some(self.fmap.feature_type(split.split_index))
+        } else {
+            None
+        };
+        let mismatch = |map_type: &str, node_type: &str| {
+            Error::from_kind(ErrorKind::InvalidArgument(format!(
+                "{} in feature map is {} but tree node is {}",
+                self.fmap.name(split.split_index),
+                map_type,
+                node_type
+            )))
+        };
+        let properties = match (condition, feature_type) {
+            (Condition::Categorical(categories), None | Some(FeatureMapType::Categorical)) => {
+                let categories: Vec<String> = flat
+                    .categories(categories)
+                    .iter()
+                    .map(|category| category.to_string())
+                    .collect();
+                let cond = match self.format {
+                    DumpFormat::Text => format!("{{{}}}", categories.join(",")),
+                    DumpFormat::Json => format!("[{}]", categories.join(", ")),
+                };
+                // rows with one of the categories go right
+                let children = (split.cright, split.cleft);
+                self.conditional(&split, ':', &cond, children, depth)
+            }
+            (Condition::Categorical(_), Some(_)) => {
+                return Err(mismatch("numerical", "categorical"));
+            }
+            (Condition::Numerical(_), Some(FeatureMapType::Categorical)) => {
+                return Err(mismatch("categorical", "numerical"));
+            }
+            (Condition::Numerical(_), Some(FeatureMapType::Indicator)) => {
+                self.indicator(&split, depth)
+            }
+            (Condition::Numerical(split_cond), Some(FeatureMapType::Integer)) => {
+                let floored = split_cond.floor();
+                let threshold = if floored == split_cond {
+                    floored as i32
+                } else {
+                    floored as i32 + 1
+                };
+                let children = (split.cleft, split.cright);
+                self.conditional(&split, '<', &threshold.to_string(), children, depth)
+            }
+            (Condition::Numerical(split_cond), _) => {
+                let children = (split.cleft, split.cright);
+                self.conditional(&split, '<', &to_str(split_cond), children, depth)
+            }
+        };
+        Ok((properties, split))
+    }
+
+    fn stats(&self, tree: &RegTree, nid: usize) -> String {
+        let (gain, cover) = (to_str(tree.loss_chg(nid)), to_str(tree.sum_hess(nid)));
+        match self.format {
+            DumpFormat::Text => format!(",gain={},cover={}", gain, cover),
+            DumpFormat::Json => format!(", \"gain\": {}, \"cover\": {}", gain, cover),
+        }
+    }
+
+    /// JSON objects are indented by two spaces per level, under the root's two spaces
+    fn indent(depth: usize) -> String {
+        "  ".repeat(depth + 1)
+    }
+
+    /// `nid` is the position of the node in the layout of the tree
+    fn build_tree(&self, tree: &RegTree, nid: usize, depth: usize) -> Result<String> {
+        let node = match tree.flat().node(nid) {
+            FlatNode::Leaf(value) => self.leaf(tree, nid, value, depth),
+            _ => {
+                let (properties, split) = self.split_node(tree, nid, depth)?;
+                let stats = if self.with_stats {
+                    self.stats(tree, nid)
+                } else {
+                    String::new()
+                };
+                let left = self.build_tree(tree, split.children.0, depth + 1)?;
+                let right = self.build_tree(tree, split.children.1, depth + 1)?;
+                match self.format {
+                    DumpFormat::Text => format!("{}{}\n{}\n{}", properties, stats, left, right),
+                    DumpFormat::Json => format!(
+                        "{{{} {}, \"children\": [{}, {}\n{}]}}",
+                        properties,
+                        stats,
+                        left,
+                        right,
+                        TreeGenerator::indent(depth)
+                    ),
+                }
+            }
+        };
+        Ok(match self.format {
+            DumpFormat::Text => node,
+            DumpFormat::Json => {
+                let newline = if depth == 0 { "" } else { "\n" };
+                format!("{}{}{}", newline, TreeGenerator::indent(depth), node)
+            }
+        })
+    }
+
+    fn dump(&self, tree: &RegTree) -> Result<String> {
+        let mut dump = self.build_tree(tree, 0, 0)?;
+        writeln!(dump).unwrap();
+        Ok(dump)
+    }
+}
+
+/// Dumps each tree of a gbtree or dart model, as `Booster.get_dump` does. `with_stats` adds the
+/// gain and the cover of the nodes.
+pub fn dump_model(
+    predictor: &Predictor,
+    fmap: Option<&FeatureMap>,
+    with_stats: bool,
+    format: DumpFormat,
+) -> Result<Vec<String>> {
+    let gbtree = match predictor.booster() {
+        BoosterModel::Trees(gbtree) => gbtree,
+        _ => {
+            return Err(Error::from_kind(ErrorKind::UnsupportedPredictionMethod(
+                String::from("dump_model"),
+                String::from("Detail: only gbtree and dart models keep their trees"),
+            )))
+        }
+    };
+    let empty = FeatureMap::new();
+    let generator = TreeGenerator {
+        fmap: fmap.unwrap_or(&empty),
+        with_stats,
+        format,
+    };
+    gbtree
+        .trees()
+        .iter()
+        .map(|tree| generator.dump(tree))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::ErrorKind;
+    use crate::testing::{
+        gblinear_model, gbtree_model, load_model, TestNode, TestTree, JSON_MODEL,
+    };
+    use crate::Predictor;
+
+    use super::{dump_model, to_str, DumpFormat, FeatureMap, FeatureMapType};
+
+    #[test]
+    fn test_to_str() {
+        let cases: [(f32, &str); 10] = [
+            (0.5, "0.5"),
+            (30.0, "30"),
+            (-1.0, "-1"),
+            (0.1, "0.100000001"),
+            (1e-5, "9.99999975e-06"),
+            (0.0001, "9.99999975e-05"),
+            (0.001, "0.00100000005"),
+            (123456789.0, "123456792"),
+            (1e10, "1e+10"),
+            (-0.0, "-0"),
+        ];
+        for (value, expected) in cases.iter() {
+            assert_eq!(to_str(*value), *expected);
+        }
+    }
+
+    #[test]
+    fn test_dump_text() {
+        let predictor = Predictor::from_json(JSON_MODEL.as_bytes()).unwrap();
+        let dump = dump_model(&predictor, None, false, DumpFormat::Text).unwrap();
+        assert_eq!(
+            dump,
+            vec![
+                "0:[f0<30] yes=1,no=2,missing=2\n\
+                 \t1:leaf=0.25\n\
+                 \t2:[f1:{1,3}] yes=4,no=3,missing=3\n\
+                 \t\t3:leaf=-1\n\
+                 \t\t4:leaf=1\n"
+            ]
+        );
+        let dump = dump_model(&predictor, None, true, DumpFormat::Text).unwrap();
+        assert_eq!(
+            dump[0],
+            "0:[f0<30] yes=1,no=2,missing=2,gain=2,cover=10\n\
+             \t1:leaf=0.25,cover=4\n\
+             \t2:[f1:{1,3}] yes=4,no=3,missing=3,gain=1,cover=6\n\
+             \t\t3:leaf=-1,cover=3\n\
+             \t\t4:leaf=1,cover=3\n"
+        );
+
+        // node ids of the model, which are not in the order of the layout
+        let mut tree = TestTree::new(
+            0,
+            vec![
+                TestNode::Split(0, 1.5, 1, 4, true),
+                TestNode::Split(1, 0.5, 2, 3, false),
+                TestNode::Leaf(1.0),
+                TestNode::Leaf(2.0),
+                TestNode::Leaf(3.0),
+            ],
+        );
+        tree.covers = vec![10.0, 6.0, 2.0, 4.0, 4.0];
+        let predictor = load_model(&gbtree_model("reg:linear", 0.5, 2, 1, &[tree], None));
+        let dump = dump_model(&predictor, None, true, DumpFormat::Text).unwrap();
+        assert_eq!(
+            dump[0],
+            "0:[f0<1.5] yes=1,no=4,missing=1,gain=2.5,cover=10\n\
+             \t1:[f1<0.5] yes=2,no=3,missing=3,gain=1.5,cover=6\n\
+             \t\t2:leaf=1,cover=2\n\
+             \t\t3:leaf=2,cover=4\n\
+             \t4:leaf=3,cover=4\n"
+        );
+    }
+
+    #[test]
+    fn test_dump_json() {
+        let predictor = Predictor::from_json(JSON_MODEL.as_bytes()).unwrap();
+        let dump = dump_model(&predictor, None, false, DumpFormat::Json).unwrap();
+        // children are separated by ", " followed by a new line
+        let expected = [
+            r#"  { "nodeid": 0, "depth": 0, "split": "f0", "split_condition": 30, "yes": 1, "no": 2, "missing": 2 , "children": ["#,
+            r#"    { "nodeid": 1, "leaf": 0.25 }, "#,
+            r#"    { "nodeid": 2, "depth": 1, "split": "f1", "split_condition": [1, 3], "yes": 4, "no": 3, "missing": 3 , "children": ["#,
+            r#"      { "nodeid": 3, "leaf": -1 }, "#,
+            r#"      { "nodeid": 4, "leaf": 1 }"#,
+            r#"    ]}"#,
+            r#"  ]}"#,
+            "",
+        ];
+        assert_eq!(dump[0], expected.join("\n"));
+        let dump = dump_model(&predictor, None, true, DumpFormat::Json).unwrap();
+        let expected = [
+            r#"  { "nodeid": 0, "depth": 0, "split": "f0", "split_condition": 30, "yes": 1, "no": 2, "missing": 2 , "gain": 2, "cover": 10, "children": ["#,
+            r#"    { "nodeid": 1, "leaf": 0.25 , "cover": 4 }, "#,
+        ];
+        assert!(dump[0].starts_with(&expected.join("\n")));
+        // the dump is valid JSON
+        for with_stats in &[false, true] {
+            let dump = dump_model(&predictor, None, *with_stats, DumpFormat::Json).unwrap();
+            serde_json::from_str::<serde_json::Value>(&dump[0]).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_feature_map() {
+        let fmap = FeatureMap::read_from("0 age int\n1 color c\n".as_bytes()).unwrap();
+        assert_eq!(fmap.len(), 2);
+        assert_eq!(
+            (fmap.name(1), fmap.feature_type(1)),
+            ("color", FeatureMapType::Categorical)
+        );
+        let predictor = Predictor::from_json(JSON_MODEL.as_bytes()).unwrap();
+        let dump = dump_model(&predictor, Some(&fmap), false, DumpFormat::Text).unwrap();
+        assert!(dump[0]
+            .starts_with("0:[age<30] yes=1,no=2,missing=2\n\t1:leaf=0.25\n\t2:[color:{1,3}]"));
+
+        let trees = [TestTree::new(
+            0,
+            vec![
+                TestNode::Split(0, 2.5, 1, 2, false),
+                TestNode::Split(1, 0.5, 3, 4, true),
+                TestNode::Leaf(0.1),
+                TestNode::Leaf(-0.2),
+                TestNode::Leaf(0.3),
+            ],
+        )];
+        let predictor = load_model(&gbtree_model("reg:linear", 0.5, 2, 1, &trees, None));
+        let fmap = FeatureMap::read_from("0\tsize\tint\n1\tflag\ti\n".as_bytes()).unwrap();
+        let dump = dump_model(&predictor, Some(&fmap), false, DumpFormat::Text).unwrap();
+        assert_eq!(
+            dump[0],
+            "0:[size<3] yes=1,no=2,missing=2\n\
+             1:[flag] yes=4,no=3\n\
+             \t\t3:leaf=-0.200000003\n\
+             \t\t4:leaf=0.300000012\n\
+             \t2:leaf=0.100000001\n"
+        );
+
+        // the types of the map must match the splits
+        let fmap = FeatureMap::read_from("0 age q\n1 color q\n".as_bytes()).unwrap();
+        let predictor = Predictor::from_json(JSON_MODEL.as_bytes()).unwrap();
+        match dump_model(&predictor, Some(&fmap), false, DumpFormat::Text) {
+            Err(error) => match error.kind() {
+                ErrorKind::InvalidArgument(message) => assert_eq!(
+                    message,
+                    "color in feature map is numerical but tree node is categorical"
+                ),
+                kind => panic!("unexpected error {:?}", kind),
+            },
+            Ok(_) => panic!("the tree is dumped"),
+        }
+        assert!(FeatureMap::read_from("1 age q\n".as_bytes()).is_err());
+        assert!(FeatureMap::read_from("0 age x\n".as_bytes()).is_err());
+
+        let predictor = load_model(&gblinear_model("reg:linear", 0.5, 1, 1, &[2.0, 1.0]));
+        assert!(dump_model(&predictor, None, false, DumpFormat::Text).is_err());
+    }
+}
@@ -0,0 +1,404 @@
+//! scikit-learn compatible estimators over a loaded model. They follow the estimator API, with
+//! the constructor parameters returned by `get_params`, so that they can replace the xgboost
+//! estimators in pipelines. The model being already trained, `fit` does nothing.
+use ndarray::{Array1, Array2};
+use numpy::IntoPyArray;
+use pyo3::prelude::*;
+use pyo3::types::PyDict;
+use pyo3::{exceptions, PyErr};
+
+use crate::errors::*;
+use crate::exceptions::to_py_err;
+use crate::functions::FunctionType;
+use crate::wrapper::{preds_to_numpy, PredictorWrapper};
+
+/// Parameters of both estimators
+fn get_params(
+    py: Python,
+    predictor: &Py<PredictorWrapper>,
+    ntree_limit: usize,
+) -> PyResult<PyObject> {
+    let params = PyDict::new(py);
+    params.set_item("predictor", predictor)?;
+    params.set_item("ntree_limit", ntree_limit)?;
+    Ok(params.to_object(py))
+}
+
+/// Updates the parameters of both estimators, unknown names are rejected as by scikit-learn
+fn set_params(
+    predictor: &mut Py<PredictorWrapper>,
+    ntree_limit: &mut usize,
+    params: Option<&PyDict>,
+) -> PyResult<()> {
+    if let Some(params) = params {
+        for (name, value) in params.iter() {
+            match name.extract::<&str>()? {
+                "predictor" => *predictor = value.extract()?,
+                "ntree_limit" => *ntree_limit = value.extract()?,
+                name => {
+                    return Err(PyErr::new::<exceptions::PyValueError, _>(format!(
+                        "Invalid parameter '{}', valid parameters are predictor and ntree_limit",
+                        name
+                    )))
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Classes of the predictions of a classification model
+fn predicted_classes(objective: FunctionType, preds: &[Vec<f32>]) -> Vec<i64> {
+    preds
+        .iter()
+        .map(|row| match objective {
+            FunctionType::BinaryLogistic => (row[0] > 0.5) as i64,
+            FunctionType::BinaryLogitraw => (row[0] > 0.0) as i64,
+            FunctionType::MultiSoftmax => row[0] as i64,
+            // the first of the highest probabilities, as numpy.argmax
+            _ => {
+                let mut best = 0;
+                for (class, prob) in row.iter().enumerate() {
+                    if *prob > row[best] {
+                        best = class;
+                    }
+                }
+                best as i64
+            }
+        })
+        .collect()
+}
+
+/// Probabilities of the classes from the predictions of binary:logistic and multi:softprob
+/// models
+fn class_probabilities(
+    objective: FunctionType,
+    preds: Vec<Vec<f32>>,
+    num_classes: usize,
+) -> Result<Array2<f32>> {
+    let num_rows = preds.len();
+    match objective {
+        FunctionType::BinaryLogistic => Ok(Array2::from_shape_fn((num_rows, 2), |(row, class)| {
+            if class == 1 {
+                preds[row][0]
+            } else {
+                1.0 - preds[row][0]
+            }
+        })),
+        FunctionType::MultiSoftprob => {
+            let probs = Array2::from_shape_vec((num_rows, num_classes), preds.concat());
+            probs.map_err(|error| {
+                Error::from_kind(ErrorKind::BrokenModel(format!(
+                    "Detail: {} probabilities per row, {}",
+                    num_classes, error
+                )))
+            })
+        }
+        _ => Err(Error::from_kind(ErrorKind::UnsupportedPredictionMethod(
+            String::from("predict_proba"),
+            format!(
+                "Detail: {} models do not predict probabilities",
+                objective.name()
+            ),
+        ))),
+    }
+}
+
+/// Regressor predicting the transformed margins of any model
+#[pyclass(module = "xgboost_predictor")]
+pub struct XGBPredictorRegressor {
+    predictor: Py<PredictorWrapper>,
+    ntree_limit: usize,
+}
+
+#[pymethods]
+impl XGBPredictorRegressor {
+    #[new]
+    #[args(ntree_limit = "0")]
+    pub fn new(predictor: Py<PredictorWrapper>, ntree_limit: usize) -> Self {
+        XGBPredictorRegressor {
+            predictor,
+            ntree_limit,
+        }
+    }
+
+    #[classattr]
+    fn _estimator_type() -> &'static str {
+        "regressor"
+    }
+
+    /// Number of features of the model
+    #[getter]
+    pub fn n_features_in_(&self, py: Python) -> usize {
+        self.predictor.borrow(py).predictor.model_num_feature()
+    }
+
+    #[args(y = "None", fit_params = "**")]
+    pub fn fit<'p>(
+        slf: PyRef<'p, Self>,
+        x: &PyAny,
+        y: Option<&PyAny>,
+        fit_params: Option<&PyDict>,
+    ) -> PyRef<'p, Self> {
+        let _ = (x, y, fit_params);
+        slf
+    }
+
+    /// Predictions of shape `(n_rows,)`, or `(n_rows, n_groups)` for models of several output
+    /// groups
+    pub fn predict(&self, py: Python, x: &PyAny) -> PyResult<PyObject> {
+        let wrapper = self.predictor.borrow(py);
+        let preds = wrapper.predict_rows(py, x, self.ntree_limit, false)?;
+        preds_to_numpy(py, preds, wrapper.predictor.num_output(false))
+    }
+
+    #[args(deep = "true")]
+    pub fn get_params(&self, py: Python, deep: bool) -> PyResult<PyObject> {
+        let _ = deep;
+        get_params(py, &self.predictor, self.ntree_limit)
+    }
+
+    #[args(params = "**")]
+    pub fn set_params<'p>(
+        mut slf: PyRefMut<'p, Self>,
+        params: Option<&PyDict>,
+    ) -> PyResult<PyRefMut<'p, Self>> {
+        let slf_ref = &mut *slf;
+        set_params(&mut slf_ref.predictor, &mut slf_ref.ntree_limit, params)?;
+        Ok(slf)
+    }
+
+    /// Unfitted copy for `sklearn.base.clone`, sharing the predictor
+    pub fn __sklearn_clone__(&self, py: Python) -> Self {
+        XGBPredictorRegressor {
+            predictor: self.predictor.clone_ref(py),
+            ntree_limit: self.ntree_limit,
+        }
+    }
+
+    pub fn __copy__(&self, py: Python) -> Self {
+        self.__sklearn_clone__(py)
+    }
+
+    /// Copy with its own predictor, rebuilt from the serialised model and settings
+    pub fn __deepcopy__(&self, py: Python, _memo: &PyAny) -> PyResult<Self> {
+        Ok(XGBPredictorRegressor {
+            predictor: Py::new(py, self.predictor.borrow(py).__copy__()?)?,
+            ntree_limit: self.ntree_limit,
+        })
+    }
+}
+
+/// Classifier over binary:logistic, binary:logitraw, multi:softprob and multi:softmax models.
+/// Probabilities are given by binary:logistic and multi:softprob models.
+#[pyclass(module = "xgboost_predictor")]
+pub struct XGBPredictorClassifier {
+    predictor: Py<PredictorWrapper>,
+    ntree_limit: usize,
+}
+
+impl XGBPredictorClassifier {
+    fn objective(&self, py: Python) -> FunctionType {
+        self.predictor.borrow(py).predictor.objective()
+    }
+
+    fn num_classes(&self, py: Python) -> usize {
+        match self.objective(py) {
+            FunctionType::MultiSoftmax | FunctionType::MultiSoftprob => {
+                self.predictor.borrow(py).predictor.num_class()
+            }
+            _ => 2,
+        }
+    }
+}
+
+#[pymethods]
+impl XGBPredictorClassifier {
+    #[new]
+    #[args(ntree_limit = "0")]
+    pub fn new(py: Python, predictor: Py<PredictorWrapper>, ntree_limit: usize) -> PyResult<Self> {
+        let objective = predictor.borrow(py).predictor.objective();
+        match objective {
+            FunctionType::BinaryLogistic
+            | FunctionType::BinaryLogitraw
+            | FunctionType::MultiSoftmax
+            | FunctionType::MultiSoftprob => Ok(XGBPredictorClassifier {
+                predictor,
+                ntree_limit,
+            }),
+            _ => Err(to_py_err(Error::from_kind(
+                ErrorKind::UnsupportedObjFunctionType(String::from(
+                    predictor.borrow(py).predictor.objective_name(),
+                )),
+            ))),
+        }
+    }
+
+    #[classattr]
+    fn _estimator_type() -> &'static str {
+        "classifier"
+    }
+
+    /// Class labels, the indices of the classes as xgboost has no labels
+    #[getter]
+    pub fn classes_(&self, py: Python) -> PyObject {
+        let classes = Array1::from((0..self.num_classes(py) as i64).collect::<Vec<i64>>());
+        classes.into_pyarray(py).to_object(py)
+    }
+
+    /// Number of features of the model
+    #[getter]
+    pub fn n_features_in_(&self, py: Python) -> usize {
+        self.predictor.borrow(py).predictor.model_num_feature()
+    }
+
+    #[args(y = "None", fit_params = "**")]
+    pub fn fit<'p>(
+        slf: PyRef<'p, Self>,
+        x: &PyAny,
+        y: Option<&PyAny>,
+        fit_params: Option<&PyDict>,
+    ) -> PyRef<'p, Self> {
+        let _ = (x, y, fit_params);
+        slf
+    }
+
+    /// Predicted classes, of shape `(n_rows,)`
+    pub fn predict(&self, py: Python, x: &PyAny) -> PyResult<PyObject> {
+        let objective = self.objective(py);
+        let preds = self
+            .predictor
+            .borrow(py)
+            .predict_rows(py, x, self.ntree_limit, false)?;
+        let classes = predicted_classes(objective, &preds);
+        Ok(Array1::from(classes).into_pyarray(py).to_object(py))
+    }
+
+    /// Probabilities of the classes, of shape `(n_rows, n_classes)`
+    pub fn predict_proba(&self, py: Python, x: &PyAny) -> PyResult<PyObject> {
+        let objective = self.objective(py);
+        let preds = self
+            .predictor
+            .borrow(py)
+            .predict_rows(py, x, self.ntree_limit, false)?;
+        let probs =
+            class_probabilities(objective, preds, self.num_classes(py)).map_err(to_py_err)?;
+        Ok(probs.into_pyarray(py).to_object(py))
+    }
+
+    #[args(deep = "true")]
+    pub fn get_params(&self, py: Python, deep: bool) -> PyResult<PyObject> {
+        let _ = deep;
+        get_params(py, &self.predictor, self.ntree_limit)
+    }
+
+    #[args(params = "**")]
+    pub fn set_params<'p>(
+        mut slf: PyRefMut<'p, Self>,
+        params: Option<&PyDict>,
+    ) -> PyResult<PyRefMut<'p, Self>> {
+        let slf_ref = &mut *slf;
+        set_params(&mut slf_ref.predictor, &mut slf_ref.ntree_limit, params)?;
+        Ok(slf)
+    }
+
+    /// Unfitted copy for `sklearn.base.clone`, sharing the predictor
+    pub fn __sklearn_clone__(&self, py: Python) -> Self {
+        XGBPredictorClassifier {
+            predictor: self.predictor.clone_ref(py),
+            ntree_limit: self.ntree_limit,
+        }
+    }
+
+    pub fn __copy__(&self, py: Python) -> Self {
+        self.__sklearn_clone__(py)
+    }
+
+    /// Copy with its own predictor, rebuilt from the serialised model and settings
+    pub fn __deepcopy__(&self, py: Python, _memo: &PyAny) -> PyResult<Self> {
+        Ok(XGBPredictorClassifier {
+            predictor: Py::new(py, self.predictor.borrow(py).__copy__()?)?,
+            ntree_limit: self.ntree_limit,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::arr2;
+    use pyo3::prelude::*;
+    use pyo3::AsPyPointer;
+
+    use crate::functions::FunctionType;
+    use crate::testing::{load_model, random_gbtree_model};
+    use crate::wrapper::PredictorWrapper;
+
+    use super::{class_probabilities, predicted_classes, XGBPredictorClassifier};
+
+    #[test]
+    fn test_classes_and_probabilities() {
+        let binary = vec![vec![0.25], vec![0.75]];
+        assert_eq!(
+            predicted_classes(FunctionType::BinaryLogistic, &binary),
+            vec![0, 1]
+        );
+        assert_eq!(
+            class_probabilities(FunctionType::BinaryLogistic, binary, 2).unwrap(),
+            arr2(&[[0.75, 0.25], [0.25, 0.75]])
+        );
+
+        let multi = vec![vec![0.25, 0.5, 0.25], vec![0.4, 0.2, 0.4]];
+        assert_eq!(
+            predicted_classes(FunctionType::MultiSoftprob, &multi),
+            vec![1, 0]
+        );
+        assert_eq!(
+            class_probabilities(FunctionType::MultiSoftprob, multi, 3).unwrap(),
+            arr2(&[[0.25, 0.5, 0.25], [0.4, 0.2, 0.4]])
+        );
+
+        let softmax = vec![vec![2.0], vec![0.0]];
+        assert_eq!(
+            predicted_classes(FunctionType::MultiSoftmax, &softmax),
+            vec![2, 0]
+        );
+        assert!(class_probabilities(FunctionType::MultiSoftmax, softmax, 3).is_err());
+        assert_eq!(
+            predicted_classes(FunctionType::BinaryLogitraw, &[vec![-0.5], vec![0.5]]),
+            vec![0, 1]
+        );
+    }
+
+    #[test]
+    fn test_copies() {
+        let gil = Python::acquire_gil();
+        let py = gil.python();
+        let predictor = load_model(&random_gbtree_model(1, "binary:logistic", 4, 1, 5, 3));
+        let predictor = Py::new(py, PredictorWrapper { predictor }).unwrap();
+        let classifier = XGBPredictorClassifier::new(py, predictor.clone_ref(py), 3).unwrap();
+        let classifier = Py::new(py, classifier).unwrap();
+
+        // shallow copies share the predictor, deep copies have their own
+        let copy = py.import("copy").unwrap();
+        for (method, shared) in &[("copy", true), ("deepcopy", false)] {
+            let copied = copy.call1(method, (classifier.clone_ref(py),)).unwrap();
+            let copied: PyRef<XGBPredictorClassifier> = copied.extract().unwrap();
+            assert_eq!(copied.predictor.as_ptr() == predictor.as_ptr(), *shared);
+            assert_eq!(copied.ntree_limit, 3);
+            let feats = arr2(&[[0.5, 1.0, 1.5, 2.0]]);
+            assert_eq!(
+                copied
+                    .predictor
+                    .borrow(py)
+                    .predictor
+                    .predict_many(feats.view(), false, 0)
+                    .unwrap(),
+                predictor
+                    .borrow(py)
+                    .predictor
+                    .predict_many(feats.view(), false, 0)
+                    .unwrap()
+            );
+        }
+    }
+}
@@ -0,0 +1,312 @@
+//! Python exceptions mirroring `errors::ErrorKind`. Every exception derives from
+//! `XGBPredictorError`, which derives from `ValueError`, and from the builtin exception suiting
+//! its kind, e.g. `IndexError` for `UnavailableDataIndex`, and carries the fields of its error
+//! kind as attributes.
+use std::ffi::CString;
+
+use pyo3::once_cell::GILOnceCell;
+use pyo3::prelude::*;
+use pyo3::type_object::PyTypeObject;
+use pyo3::types::{PyTuple, PyType};
+use pyo3::{create_exception, exceptions, ffi, AsPyPointer, PyErr};
+
+use crate::errors::{Error, ErrorKind};
+
+create_exception!(
+    xgboost_predictor,
+    XGBPredictorError,
+    exceptions::PyValueError
+);
+
+/// Creates an exception class named `xgboost_predictor.<name>` with the given bases
+fn new_type(py: Python, name: &str, bases: &[&PyType]) -> Py<PyType> {
+    let name = CString::new(format!("xgboost_predictor.{}", name)).unwrap();
+    let bases = PyTuple::new(py, bases);
+    unsafe {
+        Py::from_owned_ptr(
+            py,
+            ffi::PyErr_NewException(
+                name.as_ptr() as *mut _,
+                bases.as_ptr(),
+                std::ptr::null_mut(),
+            ),
+        )
+    }
+}
+
+/// Like `create_exception!`, with `XGBPredictorError` and a builtin exception as bases, which
+/// `create_exception!` cannot express
+macro_rules! create_kind_exception {
+    ($name: ident, $builtin: ty) => {
+        #[repr(transparent)]
+        pub struct $name(PyAny);
+
+        pyo3::impl_exception_boilerplate!($name);
+
+        pyo3::pyobject_native_type_core!(
+            $name,
+            ffi::PyBaseExceptionObject,
+            *$name::type_object_raw(Python::assume_gil_acquired()),
+            Some("xgboost_predictor"),
+            $name::check
+        );
+
+        impl $name {
+            unsafe fn check(ptr: *mut ffi::PyObject) -> std::os::raw::c_int {
+                ffi::PyObject_TypeCheck(
+                    ptr,
+                    Self::type_object_raw(Python::assume_gil_acquired()) as *mut _,
+                )
+            }
+
+            fn type_object_raw(py: Python) -> *mut ffi::PyTypeObject {
+                static TYPE_OBJECT: GILOnceCell<Py<PyType>> = GILOnceCell::new();
+                TYPE_OBJECT
+                    .get_or_init(py, || {
+                        new_type(
+                            py,
+                            stringify!($name),
+                            &[
+                                py.get_type::<XGBPredictorError>(),
+                                py.get_type::<$builtin>(),
+                            ],
+                        )
+                    })
+                    .as_ptr() as *mut _
+            }
+        }
+    };
+}
+
+create_kind_exception!(UnsupportedModelType, exceptions::PyValueError);
+create_kind_exception!(UnsupportedObjective, exceptions::PyValueError);
+create_kind_exception!(
+    UnsupportedPredictionMethod,
+    exceptions::PyNotImplementedError
+);
+create_kind_exception!(UnimplementedFunction, exceptions::PyNotImplementedError);
+create_kind_exception!(UnavailableDataIndex, exceptions::PyIndexError);
+create_kind_exception!(BrokenModel, exceptions::PyValueError);
+create_kind_exception!(ModelReadError, exceptions::PyOSError);
+create_kind_exception!(UnsupportedDataType, exceptions::PyTypeError);
+create_kind_exception!(InvalidInputShape, exceptions::PyValueError);
+create_kind_exception!(FeatureMismatch, exceptions::PyValueError);
+
+/// Adds the exception classes to the module
+pub fn register(py: Python, m: &PyModule) -> PyResult<()> {
+    m.add("XGBPredictorError", py.get_type::<XGBPredictorError>())?;
+    m.add(
+        "UnsupportedModelType",
+        py.get_type::<UnsupportedModelType>(),
+    )?;
+    m.add(
+        "UnsupportedObjective",
+        py.get_type::<UnsupportedObjective>(),
+    )?;
+    m.add(
+        "UnsupportedPredictionMethod",
+        py.get_type::<UnsupportedPredictionMethod>(),
+    )?;
+    m.add(
+        "UnimplementedFunction",
+        py.get_type::<UnimplementedFunction>(),
+    )?;
+    m.add(
+        "UnavailableDataIndex",
+        py.get_type::<UnavailableDataIndex>(),
+    )?;
+    m.add("BrokenModel", py.get_type::<BrokenModel>())?;
+    m.add("ModelReadError", py.get_type::<ModelReadError>())?;
+    m.add("UnsupportedDataType", py.get_type::<UnsupportedDataType>())?;
+    m.add("InvalidInputShape", py.get_type::<InvalidInputShape>())?;
+    m.add("FeatureMismatch", py.get_type::<FeatureMismatch>())?;
+    Ok(())
+}
+
+fn new_err<T: PyTypeObject>(py: Python, message: String, attrs: Vec<(&str, PyObject)>) -> PyErr {
+    let err = PyErr::new::<T, _>(message);
+    let instance = err.instance(py);
+    for (name, value) in attrs {
+        if let Err(error) = instance.setattr(name, value) {
+            return error;
+        }
+    }
+    err
+}
+
+/// Maps an error to the exception class of its kind
+pub fn to_py_err(error: Error) -> PyErr {
+    let gil = Python::acquire_gil();
+    let py = gil.python();
+    let message = format!("{}", error);
+    match error.kind() {
+        ErrorKind::UnsupportedModelType(model_type) => new_err::<UnsupportedModelType>(
+            py,
+            message,
+            vec![("model_type", model_type.to_object(py))],
+        ),
+        ErrorKind::UnsupportedObjFunctionType(objective) => new_err::<UnsupportedObjective>(
+            py,
+            message,
+            vec![("objective", objective.to_object(py))],
+        ),
+        ErrorKind::UnsupportedPredictionMethod(method, detail) => {
+            new_err::<UnsupportedPredictionMethod>(
+                py,
+                message,
+                vec![
+                    ("method", method.to_object(py)),
+                    ("detail", detail.to_object(py)),
+                ],
+            )
+        }
+        ErrorKind::UnimplementedFunc(detail) => {
+            new_err::<UnimplementedFunction>(py, message, vec![("detail", detail.to_object(py))])
+        }
+        ErrorKind::UnavailableDataIndex(index) => new_err::<UnavailableDataIndex>(
+            py,
+            message,
+            vec![("feature_index", index.to_object(py))],
+        ),
+        ErrorKind::BrokenModel(detail) => {
+            new_err::<BrokenModel>(py, message, vec![("detail", detail.to_object(py))])
+        }
+        ErrorKind::Io(error) => new_err::<ModelReadError>(
+            py,
+            message.clone(),
+            vec![
+                ("errno", error.raw_os_error().to_object(py)),
+                ("detail", message.to_object(py)),
+            ],
+        ),
+        // invalid strings in the model
+        ErrorKind::Utf8Error(_) | ErrorKind::Msg(_) => {
+            new_err::<BrokenModel>(py, message.clone(), vec![("detail", message.to_object(py))])
+        }
+        ErrorKind::UnsupportedDataType(data_type) => new_err::<UnsupportedDataType>(
+            py,
+            message,
+            vec![("data_type", data_type.to_object(py))],
+        ),
+        ErrorKind::InvalidInputShape(expected, actual) => new_err::<InvalidInputShape>(
+            py,
+            message,
+            vec![
+                ("expected", expected.to_object(py)),
+                ("actual", actual.to_object(py)),
+            ],
+        ),
+        ErrorKind::FeatureMismatch(missing, unexpected) => new_err::<FeatureMismatch>(
+            py,
+            message,
+            vec![
+                ("missing", missing.to_object(py)),
+                ("unexpected", unexpected.to_object(py)),
+            ],
+        ),
+        _ => new_err::<XGBPredictorError>(py, message, vec![]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+
+    use pyo3::prelude::*;
+    use pyo3::type_object::PyTypeObject;
+    use pyo3::{exceptions, PyErr, PyTypeInfo};
+
+    use crate::errors::{Error, ErrorKind};
+
+    use super::*;
+
+    fn assert_bases<T: PyTypeObject, B: PyTypeInfo>(py: Python) {
+        let class = T::type_object(py);
+        assert!(class.is_subclass::<XGBPredictorError>().unwrap());
+        assert!(class.is_subclass::<B>().unwrap());
+    }
+
+    #[test]
+    fn test_exception_bases() {
+        let gil = Python::acquire_gil();
+        let py = gil.python();
+        assert_bases::<UnsupportedModelType, exceptions::PyValueError>(py);
+        assert_bases::<UnsupportedObjective, exceptions::PyValueError>(py);
+        assert_bases::<UnsupportedPredictionMethod, exceptions::PyNotImplementedError>(py);
+        assert_bases::<UnimplementedFunction, exceptions::PyNotImplementedError>(py);
+        assert_bases::<UnavailableDataIndex, exceptions::PyIndexError>(py);
+        assert_bases::<BrokenModel, exceptions::PyValueError>(py);
+        assert_bases::<ModelReadError, exceptions::PyOSError>(py);
+        assert_bases::<UnsupportedDataType, exceptions::PyTypeError>(py);
+        assert_bases::<InvalidInputShape, exceptions::PyValueError>(py);
+        assert_bases::<FeatureMismatch, exceptions::PyValueError>(py);
+        assert!(py
+            .get_type::<XGBPredictorError>()
+            .is_subclass::<exceptions::PyValueError>()
+            .unwrap());
+        assert_eq!(
+            py.get_type::<UnavailableDataIndex>().name(),
+            "UnavailableDataIndex"
+        );
+    }
+
+    fn attr(py: Python, err: &PyErr, name: &str) -> PyObject {
+        err.instance(py).getattr(name).unwrap().into()
+    }
+
+    #[test]
+    fn test_exception_attributes() {
+        let gil = Python::acquire_gil();
+        let py = gil.python();
+
+        let err = to_py_err(Error::from_kind(ErrorKind::UnavailableDataIndex(7)));
+        assert!(err.is_instance::<UnavailableDataIndex>(py));
+        assert!(err.is_instance::<exceptions::PyIndexError>(py));
+        assert_eq!(
+            attr(py, &err, "feature_index")
+                .extract::<usize>(py)
+                .unwrap(),
+            7
+        );
+
+        let err = to_py_err(Error::from_kind(ErrorKind::UnsupportedPredictionMethod(
+            String::from("predict_leaf"),
+            String::from("Detail: linear"),
+        )));
+        assert!(err.is_instance::<exceptions::PyNotImplementedError>(py));
+        assert_eq!(
+            attr(py, &err, "method").extract::<String>(py).unwrap(),
+            "predict_leaf"
+        );
+        assert_eq!(
+            attr(py, &err, "detail").extract::<String>(py).unwrap(),
+            "Detail: linear"
+        );
+
+        let err = to_py_err(Error::from_kind(ErrorKind::FeatureMismatch(
+            vec![String::from("age")],
+            vec![],
+        )));
+        assert!(err.is_instance::<XGBPredictorError>(py));
+        assert!(err.is_instance::<exceptions::PyValueError>(py));
+        assert_eq!(
+            attr(py, &err, "missing")
+                .extract::<Vec<String>>(py)
+                .unwrap(),
+            vec!["age"]
+        );
+
+        let err = to_py_err(Error::from(io::Error::from(io::ErrorKind::UnexpectedEof)));
+        assert!(err.is_instance::<ModelReadError>(py));
+        assert!(err.is_instance::<exceptions::PyOSError>(py));
+
+        let err = to_py_err(Error::from_kind(ErrorKind::UnsupportedDataType(
+            String::from("Utf8"),
+        )));
+        assert!(err.is_instance::<exceptions::PyTypeError>(py));
+        assert_eq!(
+            attr(py, &err, "data_type").extract::<String>(py).unwrap(),
+            "Utf8"
+        );
+    }
+}
@@ -1,5 +1,6 @@
 use crate::errors::*;
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum FunctionType {
     RankPairwise,
     BinaryLogistic,
@@ -9,33 +10,44 @@ pub enum FunctionType {
     RegLinear,
 }
 
+impl FunctionType {
+    /// Name of the objective in xgboost. reg:logistic and reg:squarederror models are read as
+    /// binary:logistic and reg:linear, `Predictor::objective_name` gives the name in the model.
+    pub fn name(&self) -> &'static str {
+        match self {
+            FunctionType::RankPairwise => "rank:pairwise",
+            FunctionType::BinaryLogistic => "binary:logistic",
+            FunctionType::BinaryLogitraw => "binary:logitraw",
+            FunctionType::MultiSoftmax => "multi:softmax",
+            FunctionType::MultiSoftprob => "multi:softprob",
+            FunctionType::RegLinear => "reg:linear",
+        }
+    }
+}
+
 /// interface of objective function
 pub struct ObjFunction {
-    pub vector: fn(&Vec<f32>) -> Vec<f32>,
-    pub scalar: fn(f32) -> Result<f32>,
+    pub tp: FunctionType,
+    pub vector: fn(&[f32]) -> Vec<f32>,
 }
 
 #[inline]
-fn sigmoid(x: f32) -> Result<f32> {
-    Ok(1f32 / (1f32 + (-x).exp()))
+fn sigmoid(x: f32) -> f32 {
+    1f32 / (1f32 + (-x).exp())
 }
 
-fn dump_vec(preds: &Vec<f32>) -> Vec<f32> {
-    return preds.clone();
-}
-
-fn dump(pred: f32) -> Result<f32> {
-    Ok(pred)
+fn dump_vec(preds: &[f32]) -> Vec<f32> {
+    return preds.to_vec();
 }
 
 /// Logistic regression.
-fn logistic_vec(preds: &Vec<f32>) -> Vec<f32> {
-    return preds.into_iter().map(|x| sigmoid(*x).unwrap()).collect();
+fn logistic_vec(preds: &[f32]) -> Vec<f32> {
+    return preds.iter().map(|x| sigmoid(*x)).collect();
 }
 
 /// Multiclass classification.
-fn multiclass_vec(preds: &Vec<f32>) -> Vec<f32> {
-    match preds.get(0) {
+fn multiclass_vec(preds: &[f32]) -> Vec<f32> {
+    match preds.first() {
         Option::Some(init) => {
             let (max_index, _max) =
                 preds
@@ -45,29 +57,23 @@ fn multiclass_vec(preds: &Vec<f32>) -> Vec<f32> {
                         (0, init),
                         |(i1, v1), (i2, v2)| if v1 >= v2 { (i1, v1) } else { (i2, v2) },
                     );
-            return vec![max_index as f32; 1];
+            vec![max_index as f32; 1]
         }
         // empty vector
-        Option::None => preds.clone(),
+        Option::None => preds.to_vec(),
     }
 }
 
-fn unimplemented(_pred: f32) -> Result<f32> {
-    Err(Error::from_kind(ErrorKind::UnimplementedFunc(
-        String::from("Unimplemented output margin for scalar"),
-    )))
-}
-
 ///  Multiclass classification (predicted probability).
-fn multiclass_pred_prob_vec(preds: &Vec<f32>) -> Vec<f32> {
-    match preds.get(0) {
+fn multiclass_pred_prob_vec(preds: &[f32]) -> Vec<f32> {
+    match preds.first() {
         Option::Some(init) => {
             let max = preds.iter().fold(*init, |a, b| b.max(a));
             let sum: f32 = preds.iter().map(|x| (x - max).exp()).sum();
-            return preds.iter().map(|x| (x - max).exp() / sum).collect();
+            preds.iter().map(|x| (x - max).exp() / sum).collect()
         }
         // empty vector
-        Option::None => preds.clone(),
+        Option::None => preds.to_vec(),
     }
 }
 
@@ -75,25 +81,36 @@ pub fn get_classify_function(tp: FunctionType) -> ObjFunction {
     match tp {
         FunctionType::RankPairwise | FunctionType::BinaryLogitraw | FunctionType::RegLinear => {
             ObjFunction {
+                tp,
                 vector: dump_vec,
-                scalar: dump,
             }
         }
         FunctionType::BinaryLogistic => ObjFunction {
+            tp,
             vector: logistic_vec,
-            scalar: sigmoid,
         },
         FunctionType::MultiSoftmax => ObjFunction {
+            tp,
             vector: multiclass_vec,
-            scalar: unimplemented,
         },
         FunctionType::MultiSoftprob => ObjFunction {
+            tp,
             vector: multiclass_pred_prob_vec,
-            scalar: unimplemented,
         },
     }
 }
 
+/// Transforms a global bias given as a probability, as the JSON model format stores it, into
+/// a margin
+pub fn prob_to_margin(tp: &FunctionType, base_score: f32) -> f32 {
+    match tp {
+        FunctionType::BinaryLogistic | FunctionType::BinaryLogitraw => {
+            -(1f32 / base_score - 1f32).ln()
+        }
+        _ => base_score,
+    }
+}
+
 pub fn get_classify_func_type(obj_name: Vec<u8>) -> Result<FunctionType> {
     return match obj_name.as_slice() {
         b"rank:pairwise" => Ok(FunctionType::RankPairwise),
@@ -101,7 +118,8 @@ pub fn get_classify_func_type(obj_name: Vec<u8>) -> Result<FunctionType> {
         b"binary:logitraw" => Ok(FunctionType::BinaryLogitraw),
         b"multi:softmax" => Ok(FunctionType::MultiSoftmax),
         b"multi:softprob" => Ok(FunctionType::MultiSoftprob),
-        b"reg:linear" => Ok(FunctionType::RegLinear),
+        b"reg:linear" | b"reg:squarederror" => Ok(FunctionType::RegLinear),
+        b"reg:logistic" => Ok(FunctionType::BinaryLogistic),
         _ => Err(Error::from_kind(ErrorKind::UnsupportedObjFunctionType(
             String::from_utf8(obj_name)?,
         ))),
@@ -116,6 +134,6 @@ mod tests {
     #[test]
     fn test_get_classify_function() {
         let func = get_classify_function(BinaryLogistic);
-        (func.vector)(&vec![1.0f32, 4.6f32]);
+        (func.vector)(&[1.0f32, 4.6f32]);
     }
 }
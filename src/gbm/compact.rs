@@ -0,0 +1,383 @@
+//! Compact representation of tree ensembles, for processes holding many models.
+//!
+//! The split conditions of each feature are deduplicated and sorted, and splits keep the rank
+//! of their condition as a 16 bit bin bound. A row is binned once, feature by feature, and the
+//! trees compare bins instead of values. Leaf values live in a table shared by all trees, and
+//! the node statistics and original node ids are dropped. The predictions have the same bits as
+//! the ones of `GBTree`.
+use std::cmp;
+use std::collections::HashMap;
+
+use ndarray::ArrayView2;
+
+use crate::errors::*;
+use crate::gbm::flat_tree::FlatNode;
+use crate::gbm::gbtree::GBTree;
+use crate::gbm::grad_booster::{BoosterModel, GradBooster};
+use crate::FeatureValue;
+
+/// Bin of missing values
+const MISSING_BIN: u16 = u16::MAX;
+/// the node is a leaf, `left` is its index into the leaf table
+const LEAF: u16 = 1;
+/// missing values go to the left child
+const DEFAULT_LEFT: u16 = 2;
+
+/// Node of 12 bytes
+#[derive(Clone, Copy)]
+struct CompactNode {
+    /// left child of split nodes, the right child is next to it
+    left: u32,
+    split_index: u32,
+    /// the row goes left when the bin of the feature is below the bound
+    bound: u16,
+    flags: u16,
+}
+
+/// Tree ensemble with quantised split conditions
+pub struct CompactGBTree {
+    num_output_group: usize,
+    /// nodes of all trees in breadth-first order, tree by tree
+    nodes: Vec<CompactNode>,
+    /// first node of each tree
+    roots: Vec<u32>,
+    /// largest feature index tested by each tree, plus one
+    tree_num_columns: Vec<u32>,
+    /// indices of the trees of each output group
+    group_trees: Vec<Vec<u32>>,
+    weight_drop: Option<Vec<f32>>,
+    /// distinct split conditions of each feature in ascending order
+    thresholds: Vec<Vec<f32>>,
+    leaf_values: Vec<f32>,
+}
+
+impl CompactGBTree {
+    /// Quantises the trees of `gbtree`, which must not have categorical splits nor more than
+    /// 65534 distinct conditions on a feature
+    pub fn new(gbtree: &GBTree) -> Result<CompactGBTree> {
+        let unsupported = |detail: String| {
+            Error::from_kind(ErrorKind::UnsupportedPredictionMethod(
+                String::from("compact"),
+                detail,
+            ))
+        };
+
+        let mut thresholds: Vec<Vec<f32>> = vec![];
+        for tree in gbtree.trees() {
+            let tree = tree.flat();
+            for nid in 0..tree.num_nodes() {
+                match tree.node(nid) {
+                    FlatNode::Leaf(_) => {}
+                    FlatNode::Split {
+                        split_index,
+                        split_cond,
+                        ..
+                    } => {
+                        if thresholds.len() <= split_index {
+                            thresholds.resize(split_index + 1, vec![]);
+                        }
+                        // no value is below a NaN condition, it gets the bound 0
+                        if !split_cond.is_nan() {
+                            thresholds[split_index].push(split_cond);
+                        }
+                    }
+                    FlatNode::CategoricalSplit { .. } => {
+                        return Err(unsupported(String::from(
+                            "Detail: categorical splits can not be quantised",
+                        )))
+                    }
+                }
+            }
+        }
+        for (fid, feature_thresholds) in thresholds.iter_mut().enumerate() {
+            feature_thresholds.sort_by(|a, b| a.total_cmp(b));
+            feature_thresholds.dedup_by(|a, b| a == b);
+            if feature_thresholds.len() >= MISSING_BIN as usize {
+                return Err(unsupported(format!(
+                    "Detail: {} distinct split conditions on feature {}",
+                    feature_thresholds.len(),
+                    fid
+                )));
+            }
+        }
+
+        let mut nodes = vec![];
+        let mut roots = vec![];
+        let mut tree_num_columns = vec![];
+        let mut leaf_values = vec![];
+        let mut leaf_ids: HashMap<u32, u32> = HashMap::new();
+        for tree in gbtree.trees() {
+            let tree = tree.flat();
+            let root = nodes.len() as u32;
+            roots.push(root);
+            tree_num_columns.push(tree.max_split_index().map_or(0, |index| index as u32 + 1));
+            for nid in 0..tree.num_nodes() {
+                let node = match tree.node(nid) {
+                    FlatNode::Leaf(value) => {
+                        let leaf = *leaf_ids.entry(value.to_bits()).or_insert_with(|| {
+                            leaf_values.push(value);
+                            leaf_values.len() as u32 - 1
+                        });
+                        CompactNode {
+                            left: leaf,
+                            split_index: 0,
+                            bound: 0,
+                            flags: LEAF,
+                        }
+                    }
+                    FlatNode::Split {
+                        cleft,
+                        split_index,
+                        split_cond,
+                        default_left,
+                        ..
+                    } => {
+                        let bound = if split_cond.is_nan() {
+                            0
+                        } else {
+                            bin(&thresholds[split_index], FeatureValue::from(split_cond))
+                        };
+                        CompactNode {
+                            left: root + cleft as u32,
+                            split_index: split_index as u32,
+                            bound,
+                            flags: if default_left { DEFAULT_LEFT } else { 0 },
+                        }
+                    }
+                    FlatNode::CategoricalSplit { .. } => unreachable!(),
+                };
+                nodes.push(node);
+            }
+        }
+
+        let num_output_group = gbtree.num_output_group();
+        let group_trees = (0..num_output_group)
+            .map(|gid| {
+                (0..gbtree.tree_info().len() as u32)
+                    .filter(|tid| gbtree.tree_info()[*tid as usize] == gid as i32)
+                    .collect()
+            })
+            .collect();
+        Ok(CompactGBTree {
+            num_output_group,
+            nodes,
+            roots,
+            tree_num_columns,
+            group_trees,
+            weight_drop: gbtree.weight_drop().map(|weights| weights.to_vec()),
+            thresholds,
+            leaf_values,
+        })
+    }
+
+    pub(crate) fn num_trees(&self) -> usize {
+        self.roots.len()
+    }
+
+    /// Output group of each tree
+    pub(crate) fn tree_info(&self) -> Vec<i32> {
+        let mut tree_info = vec![0; self.num_trees()];
+        for (gid, trees) in self.group_trees.iter().enumerate() {
+            for tid in trees {
+                tree_info[*tid as usize] = gid as i32;
+            }
+        }
+        tree_info
+    }
+
+    pub(crate) fn weight_drop(&self) -> Option<&[f32]> {
+        self.weight_drop.as_deref()
+    }
+
+    /// Nodes of a tree in breadth-first order with their split conditions restored, the
+    /// condition of rank `k` having the bound `k + 1`
+    pub(crate) fn flat_nodes(&self, tid: usize) -> Vec<FlatNode> {
+        let root = self.roots[tid] as usize;
+        let end = self
+            .roots
+            .get(tid + 1)
+            .map_or(self.nodes.len(), |next| *next as usize);
+        self.nodes[root..end]
+            .iter()
+            .map(|node| {
+                if node.flags & LEAF != 0 {
+                    return FlatNode::Leaf(self.leaf_values[node.left as usize]);
+                }
+                let split_index = node.split_index as usize;
+                let cleft = node.left as usize - root;
+                FlatNode::Split {
+                    cleft,
+                    cright: cleft + 1,
+                    split_index,
+                    split_cond: match node.bound {
+                        0 => f32::NAN,
+                        bound => self.thresholds[split_index][bound as usize - 1],
+                    },
+                    default_left: node.flags & DEFAULT_LEFT != 0,
+                }
+            })
+            .collect()
+    }
+
+    fn limited_group_trees(&self, gid: usize, ntree_limit: usize) -> &[u32] {
+        let trees = &self.group_trees[gid];
+        if ntree_limit == 0 {
+            trees
+        } else {
+            &trees[..cmp::min(ntree_limit, trees.len())]
+        }
+    }
+
+    fn leaf_value(&self, tid: usize, bins: &[u16]) -> f32 {
+        let mut node = self.nodes[self.roots[tid] as usize];
+        while node.flags & LEAF == 0 {
+            let bin = bins[node.split_index as usize];
+            let go_left = if bin == MISSING_BIN {
+                node.flags & DEFAULT_LEFT != 0
+            } else {
+                bin < node.bound
+            };
+            node = self.nodes[node.left as usize + !go_left as usize];
+        }
+        self.leaf_values[node.left as usize]
+    }
+}
+
+/// Number of conditions not above the value: the value is below the condition of rank `k` if
+/// and only if its bin is at most `k`
+fn bin(thresholds: &[f32], value: FeatureValue) -> u16 {
+    thresholds.partition_point(|threshold| FeatureValue::from(*threshold) <= value) as u16
+}
+
+impl GradBooster for CompactGBTree {
+    fn num_output_group(&self) -> usize {
+        self.num_output_group
+    }
+
+    fn model(&self) -> BoosterModel<'_> {
+        BoosterModel::Compact(self)
+    }
+
+    fn predict_many(
+        &self,
+        feats: ArrayView2<'_, FeatureValue>,
+        base_score: f32,
+        ntree_limit: usize,
+    ) -> Result<Vec<Vec<f32>>> {
+        let num_columns = feats.ncols();
+        for gid in 0..self.num_output_group {
+            for tid in self.limited_group_trees(gid, ntree_limit) {
+                let tree_num_columns = self.tree_num_columns[*tid as usize] as usize;
+                if tree_num_columns > num_columns {
+                    return Err(Error::from_kind(ErrorKind::UnavailableDataIndex(
+                        tree_num_columns - 1,
+                    )));
+                }
+            }
+        }
+
+        let mut bins = vec![MISSING_BIN; self.thresholds.len()];
+        let mut preds = Vec::with_capacity(feats.nrows());
+        for feat in feats.outer_iter() {
+            // features of no split are never read, they stay missing
+            for ((bin_value, thresholds), value) in
+                bins.iter_mut().zip(&self.thresholds).zip(feat.iter())
+            {
+                *bin_value = if value.is_nan() {
+                    MISSING_BIN
+                } else {
+                    bin(thresholds, *value)
+                };
+            }
+            // margins are summed in the same order as `GBTree`
+            let row = (0..self.num_output_group)
+                .map(|gid| {
+                    let mut margin = 0f32;
+                    for tid in self.limited_group_trees(gid, ntree_limit) {
+                        let weight = match &self.weight_drop {
+                            Some(weight_drop) => weight_drop[*tid as usize],
+                            None => 1f32,
+                        };
+                        margin += weight * self.leaf_value(*tid as usize, &bins);
+                    }
+                    base_score + margin
+                })
+                .collect();
+            preds.push(row);
+        }
+        Ok(preds)
+    }
+
+    fn predict_leaf(
+        &self,
+        _feats: ArrayView2<'_, FeatureValue>,
+        _ntree_limit: usize,
+    ) -> Result<Vec<Vec<u32>>> {
+        Err(Error::from_kind(ErrorKind::UnsupportedPredictionMethod(
+            String::from("predict_leaf"),
+            String::from("Detail: compact models keep no node ids"),
+        )))
+    }
+
+    fn predict_contribs(
+        &self,
+        _feats: ArrayView2<'_, FeatureValue>,
+        _base_score: f32,
+        _ntree_limit: usize,
+    ) -> Result<Vec<Vec<f32>>> {
+        Err(Error::from_kind(ErrorKind::UnsupportedPredictionMethod(
+            String::from("predict_contribs"),
+            String::from("Detail: compact models keep no node statistics"),
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::testing::{gbtree_model, load_model, random_matrix, random_trees, JSON_MODEL};
+
+    use super::CompactNode;
+
+    #[test]
+    fn test_compact_is_bit_exact() {
+        assert_eq!(std::mem::size_of::<CompactNode>(), 12);
+        let feats = random_matrix(23, 400, 8, 0.2);
+        // values on the grid of the split conditions
+        let grid_feats = feats.mapv(|value| (value * 16.0).floor() / 16.0);
+        let weights: Vec<f32> = (0..180).map(|i| 1f32 / (i + 1) as f32).collect();
+        let bits = |preds: Vec<Vec<f32>>| -> Vec<u32> {
+            preds.into_iter().flatten().map(f32::to_bits).collect()
+        };
+        for weight_drop in &[None, Some(&weights[..])] {
+            let trees = random_trees(29, 8, 3, 60, 8);
+            let model = gbtree_model("multi:softprob", 0.5, 8, 3, &trees, *weight_drop);
+            let predictor = load_model(&model);
+            let mut compact = load_model(&model);
+            compact.compact().unwrap();
+            for feats in &[&feats, &grid_feats] {
+                for ntree_limit in &[0, 20] {
+                    let expected = predictor
+                        .predict_many(feats.view(), true, *ntree_limit)
+                        .unwrap();
+                    let preds = compact
+                        .predict_many(feats.view(), true, *ntree_limit)
+                        .unwrap();
+                    assert_eq!(bits(preds), bits(expected));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_compact_rejects_categorical_splits() {
+        let mut predictor = load_model(JSON_MODEL.as_bytes());
+        assert!(predictor.compact().is_err());
+        assert_eq!(
+            predictor
+                .predict_many(ndarray::arr2(&[[20.0, 1.0]]).view(), true, 0)
+                .unwrap()
+                .len(),
+            1
+        );
+    }
+}
@@ -0,0 +1,447 @@
+//! Struct-of-arrays layout of a regression tree, built at load time for traversal. Nodes are
+//! stored in breadth-first order so that the top levels, which every row visits, share cache
+//! lines, and so that the right child of a split always follows its left child.
+use std::collections::VecDeque;
+
+use crate::FeatureValue;
+
+/// the node is a leaf, its `value` is the leaf value
+const LEAF: u32 = 1;
+/// missing values go to the left child
+const DEFAULT_LEFT: u32 = 2;
+/// the split tests the membership of the feature value in `category_sets[category_set]`
+const CATEGORICAL: u32 = 4;
+
+/// Number of rows traversed through a tree before moving to the next tree
+pub const BLOCK_ROWS: usize = 64;
+
+/// Whether `FlatTree::add_block` traverses trees without categorical splits with the AVX2
+/// kernel, which needs the `simd` feature, f32 inputs and a CPU supporting AVX2
+#[cfg(all(feature = "simd", target_arch = "x86_64", not(feature = "use_f64")))]
+pub fn simd_traversal() -> bool {
+    is_x86_feature_detected!("avx2")
+}
+
+#[cfg(not(all(feature = "simd", target_arch = "x86_64", not(feature = "use_f64"))))]
+pub fn simd_traversal() -> bool {
+    false
+}
+
+/// Layout of a node handed to `FlatTree::from_nodes`
+#[derive(Clone, Copy)]
+pub enum FlatNode {
+    Leaf(f32),
+    Split {
+        cleft: usize,
+        cright: usize,
+        split_index: usize,
+        split_cond: f32,
+        default_left: bool,
+    },
+    CategoricalSplit {
+        cleft: usize,
+        cright: usize,
+        split_index: usize,
+        default_left: bool,
+        categories: usize,
+    },
+}
+
+#[derive(Clone)]
+pub struct FlatTree {
+    /// feature index of split nodes
+    split_index: Vec<u32>,
+    /// split condition of split nodes, leaf value of leaves
+    value: Vec<f32>,
+    /// left child of split nodes, the right child is next to it
+    left: Vec<u32>,
+    /// 32 bit wide, as the other arrays, to be gathered by the SIMD kernel
+    flags: Vec<u32>,
+    /// index into `category_sets` of categorical splits
+    category_set: Vec<u32>,
+    /// sorted categories going right
+    category_sets: Vec<Vec<u32>>,
+    /// id of each node in the original tree
+    node_ids: Vec<u32>,
+    /// largest feature index tested by a split
+    max_split_index: Option<usize>,
+    /// number of splits on the longest path from the root to a leaf
+    depth: usize,
+    has_categorical: bool,
+}
+
+impl FlatTree {
+    /// Lays out the nodes reachable from `nodes[0]`, which must form a tree
+    pub fn from_nodes(nodes: &[FlatNode], category_sets: &[Vec<u32>]) -> FlatTree {
+        let mut order = vec![];
+        let mut depths = vec![0usize; nodes.len()];
+        let mut queue: VecDeque<usize> = VecDeque::new();
+        queue.push_back(0);
+        while let Some(nid) = queue.pop_front() {
+            order.push(nid);
+            match nodes[nid] {
+                FlatNode::Leaf(_) => {}
+                FlatNode::Split { cleft, cright, .. }
+                | FlatNode::CategoricalSplit { cleft, cright, .. } => {
+                    depths[cleft] = depths[nid] + 1;
+                    depths[cright] = depths[nid] + 1;
+                    queue.push_back(cleft);
+                    queue.push_back(cright);
+                }
+            }
+        }
+        let mut position = vec![0u32; nodes.len()];
+        for (pos, nid) in order.iter().enumerate() {
+            position[*nid] = pos as u32;
+        }
+
+        let mut tree = FlatTree {
+            split_index: Vec::with_capacity(order.len()),
+            value: Vec::with_capacity(order.len()),
+            left: Vec::with_capacity(order.len()),
+            flags: Vec::with_capacity(order.len()),
+            category_set: Vec::with_capacity(order.len()),
+            category_sets: category_sets.to_vec(),
+            node_ids: order.iter().map(|nid| *nid as u32).collect(),
+            max_split_index: None,
+            depth: order.iter().map(|nid| depths[*nid]).max().unwrap_or(0),
+            has_categorical: false,
+        };
+        for nid in order {
+            let (split_index, value, cleft, flags, category_set) = match nodes[nid] {
+                FlatNode::Leaf(leaf_value) => (0, leaf_value, nid, LEAF, 0),
+                FlatNode::Split {
+                    cleft,
+                    split_index,
+                    split_cond,
+                    default_left,
+                    ..
+                } => (
+                    split_index,
+                    split_cond,
+                    cleft,
+                    if default_left { DEFAULT_LEFT } else { 0 },
+                    0,
+                ),
+                FlatNode::CategoricalSplit {
+                    cleft,
+                    split_index,
+                    default_left,
+                    categories,
+                    ..
+                } => (
+                    split_index,
+                    0f32,
+                    cleft,
+                    CATEGORICAL | if default_left { DEFAULT_LEFT } else { 0 },
+                    categories,
+                ),
+            };
+            tree.has_categorical |= flags & CATEGORICAL != 0;
+            if flags & LEAF == 0 {
+                tree.max_split_index = tree.max_split_index.max(Some(split_index));
+            }
+            tree.split_index.push(split_index as u32);
+            tree.value.push(value);
+            tree.left.push(position[cleft]);
+            tree.flags.push(flags);
+            tree.category_set.push(category_set as u32);
+        }
+        tree
+    }
+
+    pub fn num_nodes(&self) -> usize {
+        self.flags.len()
+    }
+
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+
+    /// Node at a position of the layout, children are given by their positions
+    pub fn node(&self, nid: usize) -> FlatNode {
+        let flags = self.flags[nid];
+        let cleft = self.left[nid] as usize;
+        if flags & LEAF != 0 {
+            FlatNode::Leaf(self.value[nid])
+        } else if flags & CATEGORICAL != 0 {
+            FlatNode::CategoricalSplit {
+                cleft,
+                cright: cleft + 1,
+                split_index: self.split_index[nid] as usize,
+                default_left: flags & DEFAULT_LEFT != 0,
+                categories: self.category_set[nid] as usize,
+            }
+        } else {
+            FlatNode::Split {
+                cleft,
+                cright: cleft + 1,
+                split_index: self.split_index[nid] as usize,
+                split_cond: self.value[nid],
+                default_left: flags & DEFAULT_LEFT != 0,
+            }
+        }
+    }
+
+    /// Id in the original tree of the node at a position of the layout
+    pub fn node_id(&self, nid: usize) -> usize {
+        self.node_ids[nid] as usize
+    }
+
+    /// Replaces the ids of the nodes, `node_ids` giving the id of each node handed to
+    /// `from_nodes`
+    pub fn renumber(&mut self, node_ids: &[u32]) {
+        for node_id in self.node_ids.iter_mut() {
+            *node_id = node_ids[*node_id as usize];
+        }
+    }
+
+    /// Sorted categories going right at the categorical splits given this set
+    pub fn categories(&self, category_set: usize) -> &[u32] {
+        &self.category_sets[category_set]
+    }
+
+    /// Largest feature index tested by the tree, rows must be longer than it
+    pub fn max_split_index(&self) -> Option<usize> {
+        self.max_split_index
+    }
+
+    /// Position of the child of a split node followed by the row. Forced inline, as with
+    /// callers outside the row loop it was otherwise called once per node.
+    #[inline(always)]
+    pub fn next(&self, nid: usize, feat: &[FeatureValue]) -> usize {
+        let fvalue = feat[self.split_index[nid] as usize];
+        let flags = self.flags[nid];
+        let go_left = if fvalue.is_nan() {
+            flags & DEFAULT_LEFT != 0
+        } else if flags & CATEGORICAL != 0 {
+            !is_chosen_category(&self.category_sets[self.category_set[nid] as usize], fvalue)
+        } else {
+            fvalue < FeatureValue::from(self.value[nid])
+        };
+        self.left[nid] as usize + !go_left as usize
+    }
+
+    /// Position of the leaf reached by the row, which holds at least `max_split_index() + 1`
+    /// values
+    #[inline(always)]
+    fn leaf(&self, feat: &[FeatureValue]) -> usize {
+        let mut nid = 0;
+        while self.flags[nid] & LEAF == 0 {
+            nid = self.next(nid, feat);
+        }
+        nid
+    }
+
+    /// Id of the leaf reached by the row in the original tree
+    pub fn leaf_id(&self, feat: &[FeatureValue]) -> usize {
+        self.node_id(self.leaf(feat))
+    }
+
+    /// Adds `weight` times the leaf value of each row of the row-major block `rows`, made of
+    /// rows of `num_columns` values, to `preds`. Rows must hold the features tested by the
+    /// tree.
+    pub fn add_block(
+        &self,
+        rows: &[FeatureValue],
+        num_columns: usize,
+        weight: f32,
+        preds: &mut [f32],
+    ) {
+        #[cfg(all(feature = "simd", target_arch = "x86_64", not(feature = "use_f64")))]
+        {
+            if !self.has_categorical
+                && rows.len() <= i32::MAX as usize
+                && is_x86_feature_detected!("avx2")
+            {
+                // the features tested are within the rows, checked by the caller, and the
+                // offsets of the values fit in i32
+                unsafe { simd::add_block_avx2(self, rows, num_columns, weight, preds) };
+                return;
+            }
+        }
+        self.add_block_scalar(rows, num_columns, weight, preds);
+    }
+
+    /// Kept out of `add_block` so the traversal is inlined in a loop of its own, where the
+    /// arrays of the tree stay in registers
+    #[inline(never)]
+    fn add_block_scalar(
+        &self,
+        rows: &[FeatureValue],
+        num_columns: usize,
+        weight: f32,
+        preds: &mut [f32],
+    ) {
+        for (rid, pred) in preds.iter_mut().enumerate() {
+            let feat = &rows[rid * num_columns..(rid + 1) * num_columns];
+            *pred += weight * self.value[self.leaf(feat)];
+        }
+    }
+}
+
+/// Traversal of 8 rows at a time with AVX2: each lane holds the node of a row, and the split
+/// index, condition, flags and children of the nodes are gathered from the tree's arrays. Lanes
+/// move down for `depth` steps, lanes which reached a leaf stay on it. Missing values follow
+/// the default direction as in `FlatTree::next`; categorical splits are not handled.
+#[cfg(all(feature = "simd", target_arch = "x86_64", not(feature = "use_f64")))]
+mod simd {
+    use std::arch::x86_64::*;
+
+    use super::{FlatTree, DEFAULT_LEFT, LEAF};
+    use crate::FeatureValue;
+
+    const LANES: usize = 8;
+
+    #[target_feature(enable = "avx2")]
+    pub unsafe fn add_block_avx2(
+        tree: &FlatTree,
+        rows: &[FeatureValue],
+        num_columns: usize,
+        weight: f32,
+        preds: &mut [f32],
+    ) {
+        let num_full = preds.len() / LANES * LANES;
+        let split_index = tree.split_index.as_ptr() as *const i32;
+        let value = tree.value.as_ptr();
+        let left = tree.left.as_ptr() as *const i32;
+        let flags = tree.flags.as_ptr() as *const i32;
+        let leaf_flag = _mm256_set1_epi32(LEAF as i32);
+        let default_left_flag = _mm256_set1_epi32(DEFAULT_LEFT as i32);
+        let one = _mm256_set1_epi32(1);
+        let lane_offsets = _mm256_mullo_epi32(
+            _mm256_setr_epi32(0, 1, 2, 3, 4, 5, 6, 7),
+            _mm256_set1_epi32(num_columns as i32),
+        );
+        let mut leaf_values = [0f32; LANES];
+        for begin in (0..num_full).step_by(LANES) {
+            let row_offsets = _mm256_add_epi32(
+                lane_offsets,
+                _mm256_set1_epi32((begin * num_columns) as i32),
+            );
+            let mut nid = _mm256_setzero_si256();
+            for _ in 0..tree.depth {
+                let node_flags = _mm256_i32gather_epi32(flags, nid, 4);
+                let is_leaf =
+                    _mm256_cmpeq_epi32(_mm256_and_si256(node_flags, leaf_flag), leaf_flag);
+                let default_left = _mm256_cmpeq_epi32(
+                    _mm256_and_si256(node_flags, default_left_flag),
+                    default_left_flag,
+                );
+                let findex = _mm256_i32gather_epi32(split_index, nid, 4);
+                let fvalue =
+                    _mm256_i32gather_ps(rows.as_ptr(), _mm256_add_epi32(row_offsets, findex), 4);
+                let cond = _mm256_i32gather_ps(value, nid, 4);
+                let is_less = _mm256_castps_si256(_mm256_cmp_ps(fvalue, cond, _CMP_LT_OQ));
+                let is_nan = _mm256_castps_si256(_mm256_cmp_ps(fvalue, fvalue, _CMP_UNORD_Q));
+                // all ones when the row goes left
+                let go_left = _mm256_blendv_epi8(is_less, default_left, is_nan);
+                // the right child follows the left child
+                let next = _mm256_add_epi32(
+                    _mm256_i32gather_epi32(left, nid, 4),
+                    _mm256_add_epi32(one, go_left),
+                );
+                nid = _mm256_blendv_epi8(next, nid, is_leaf);
+            }
+            _mm256_storeu_ps(leaf_values.as_mut_ptr(), _mm256_i32gather_ps(value, nid, 4));
+            for (pred, leaf_value) in preds[begin..begin + LANES].iter_mut().zip(&leaf_values) {
+                *pred += weight * leaf_value;
+            }
+        }
+        tree.add_block_scalar(
+            &rows[num_full * num_columns..],
+            num_columns,
+            weight,
+            &mut preds[num_full..],
+        );
+    }
+}
+
+/// Whether a category is in the sorted list of categories of a split. Negative values and
+/// values too large to be represented exactly are not valid categories, as in xgboost.
+pub(crate) fn is_chosen_category(categories: &[u32], fvalue: FeatureValue) -> bool {
+    if fvalue < 0.0 || fvalue >= (1u32 << f32::MANTISSA_DIGITS) as FeatureValue {
+        return false;
+    }
+    return categories.binary_search(&(fvalue as u32)).is_ok();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FlatNode, FlatTree};
+    use crate::FeatureValue;
+
+    #[test]
+    fn test_breadth_first_layout() {
+        // 0: f0 < 0.5 ? 3 : 1, 1: f1 in {2} ? 4 : 2, leaves 2, 3, 4
+        let nodes = [
+            FlatNode::Split {
+                cleft: 3,
+                cright: 1,
+                split_index: 0,
+                split_cond: 0.5,
+                default_left: false,
+            },
+            FlatNode::CategoricalSplit {
+                cleft: 2,
+                cright: 4,
+                split_index: 1,
+                default_left: true,
+                categories: 0,
+            },
+            FlatNode::Leaf(2.0),
+            FlatNode::Leaf(3.0),
+            FlatNode::Leaf(4.0),
+        ];
+        let tree = FlatTree::from_nodes(&nodes, &[vec![2]]);
+        assert_eq!(tree.node_ids, vec![0, 3, 1, 2, 4]);
+        assert_eq!(tree.max_split_index(), Some(1));
+        assert_eq!(tree.depth(), 2);
+
+        assert_eq!(tree.leaf_id(&[0.0, 2.0]), 3);
+        assert_eq!(tree.leaf_id(&[1.0, 2.0]), 4);
+        assert_eq!(tree.leaf_id(&[1.0, 1.0]), 2);
+        assert_eq!(tree.leaf_id(&[FeatureValue::NAN, FeatureValue::NAN]), 2);
+        assert_eq!(tree.leaf_id(&[1.0, -2.0]), 2);
+
+        let mut preds = vec![1.0, 1.0, 1.0];
+        tree.add_block(&[0.0, 0.0, 1.0, 2.0, 1.0, 3.0], 2, 0.5, &mut preds);
+        assert_eq!(preds, vec![2.5, 3.0, 2.0]);
+    }
+
+    #[cfg(all(feature = "simd", target_arch = "x86_64", not(feature = "use_f64")))]
+    #[test]
+    fn test_simd_matches_scalar() {
+        use crate::testing::{random_matrix, random_tree, Lcg, TestNode};
+
+        if !is_x86_feature_detected!("avx2") {
+            return;
+        }
+        let mut rng = Lcg::new(23);
+        let feats = random_matrix(29, 203, 6, 0.2);
+        let rows = feats.as_slice().unwrap();
+        for _ in 0..20 {
+            let test_tree = random_tree(&mut rng, 0, 6, 7);
+            let nodes: Vec<FlatNode> = test_tree
+                .nodes
+                .iter()
+                .map(|node| match *node {
+                    TestNode::Leaf(value) => FlatNode::Leaf(value),
+                    TestNode::Split(index, cond, left, right, default_left) => FlatNode::Split {
+                        cleft: left as usize,
+                        cright: right as usize,
+                        split_index: index as usize,
+                        split_cond: cond,
+                        default_left,
+                    },
+                })
+                .collect();
+            let tree = FlatTree::from_nodes(&nodes, &[]);
+            let mut simd_preds = vec![0.5f32; feats.nrows()];
+            unsafe { super::simd::add_block_avx2(&tree, rows, 6, 0.25, &mut simd_preds) };
+            let mut scalar_preds = vec![0.5f32; feats.nrows()];
+            tree.add_block_scalar(rows, 6, 0.25, &mut scalar_preds);
+            assert_eq!(simd_preds, scalar_preds);
+        }
+    }
+}
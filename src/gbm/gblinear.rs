@@ -1,13 +1,13 @@
 use std::iter::FromIterator;
 
-use ndarray::{ArrayView1, ArrayView2, Array, Ix1};
+use ndarray::{Array, ArrayView2, Ix1};
+use serde_json::Value;
 
-use std::time::{Duration, Instant};
-
-use itertools::izip;
 use crate::errors::*;
-use crate::gbm::grad_booster::GradBooster;
+use crate::gbm::grad_booster::{BoosterModel, GradBooster};
+use crate::json_reader::JsonReader;
 use crate::model_reader::ModelReader;
+use crate::{to_f32, FeatureValue};
 
 struct ModelParam {
     /// number of features
@@ -20,10 +20,8 @@ struct ModelParam {
 
 impl ModelParam {
     fn read_from<T: ModelReader>(reader: &mut T) -> Result<ModelParam> {
-        let (num_feature, num_output_group) = (
-            reader.read_i32_le()? as usize,
-            reader.read_i32_le()? as usize,
-        );
+        let (num_feature, num_output_group) =
+            (reader.read_i32_as_usize()?, reader.read_i32_as_usize()?);
         let mut reserved = [0i32; 32];
         reader.read_to_i32_buffer(&mut reserved)?;
         // read padding
@@ -41,7 +39,7 @@ pub struct GBLinear {
 }
 
 impl GBLinear {
-    pub fn read_from<T: ModelReader>(with_pbuffer: bool, reader: &mut T) -> Result<Self> {
+    pub fn read_from<T: ModelReader>(_with_pbuffer: bool, reader: &mut T) -> Result<Self> {
         let mparam = ModelParam::read_from(reader)?;
         // read padding
         reader.read_i32_le()?;
@@ -50,62 +48,182 @@ impl GBLinear {
         Ok(GBLinear { mparam, weights })
     }
 
-    fn bias(&self, gid: usize) -> f32 {
+    /// Instantiates with the `gradient_booster` object of the JSON model format
+    pub fn from_json(
+        gradient_booster: &Value,
+        num_feature: usize,
+        num_output_group: usize,
+    ) -> Result<Self> {
+        let weights = gradient_booster.field("model")?.f32_vec_field("weights")?;
+        if weights.len() != (num_feature + 1) * num_output_group {
+            return Err(Error::from_kind(ErrorKind::BrokenModel(format!(
+                "Detail: expected {} weights, got {}",
+                (num_feature + 1) * num_output_group,
+                weights.len()
+            ))));
+        }
+        Ok(GBLinear {
+            mparam: ModelParam {
+                num_feature,
+                num_output_group,
+            },
+            weights,
+        })
+    }
+
+    /// Instantiates with the weights of the features then of the bias, group by group within
+    /// each of them, which must hold `(num_feature + 1) * num_output_group` values
+    pub(crate) fn new(num_feature: usize, num_output_group: usize, weights: Vec<f32>) -> GBLinear {
+        GBLinear {
+            mparam: ModelParam {
+                num_feature,
+                num_output_group,
+            },
+            weights,
+        }
+    }
+
+    pub fn num_feature(&self) -> usize {
+        self.mparam.num_feature
+    }
+
+    pub fn num_output_group(&self) -> usize {
+        self.mparam.num_output_group
+    }
+
+    pub fn bias(&self, gid: usize) -> f32 {
         self.weight(self.mparam.num_feature, gid)
     }
 
-    fn weight(&self, fid: usize, gid: usize) -> f32 {
+    pub fn weight(&self, fid: usize, gid: usize) -> f32 {
         self.weights[(fid * self.mparam.num_output_group) + gid]
     }
 
     fn group_weights(&self, gid: usize) -> Array<f32, Ix1> {
-        <Array<f32, Ix1> as FromIterator<f32>>::from_iter((0..self.mparam.num_feature).map(|fid| self.weight(fid, gid)))
+        <Array<f32, Ix1> as FromIterator<f32>>::from_iter(
+            (0..self.mparam.num_feature).map(|fid| self.weight(fid, gid)),
+        )
     }
 
-    fn pred(&self, feat: ArrayView1<'_, f32>, gid: usize) -> f32 {
-        feat.dot(&self.group_weights(gid)) + self.bias(gid as usize)
-    }
-
-    fn pred_many(&self, feats: ArrayView2<'_, f32>, base_score: f32,  gid: usize) -> Vec<f32> {
-        (feats.dot(&self.group_weights(gid)) + self.bias(gid as usize) + base_score).to_vec()
+    /// Missing (NaN) feature values do not contribute, as in sparse xgboost input. Products are
+    /// summed in the precision of the input values.
+    fn pred_many(
+        &self,
+        feats: ArrayView2<'_, FeatureValue>,
+        base_score: f32,
+        gid: usize,
+    ) -> Vec<f32> {
+        let weights = self.group_weights(gid);
+        let bias = self.bias(gid) + base_score;
+        feats
+            .outer_iter()
+            .map(|feat| {
+                to_f32(
+                    feat.iter()
+                        .zip(weights.iter())
+                        .filter(|(fvalue, _)| !fvalue.is_nan())
+                        .map(|(fvalue, weight)| fvalue * FeatureValue::from(*weight))
+                        .sum::<FeatureValue>(),
+                ) + bias
+            })
+            .collect()
     }
 }
 
 impl GradBooster for GBLinear {
-    // fn predict(&self, feat: ArrayView1<'_, f32>, ntree_limit: usize) -> Result<Vec<f32>> {
-    //     let mut data: Vec<f32> = vec![];
-    //     for gid in 0..self.mparam.num_output_group {
-    //         data.push(self.pred(feat, gid)?)
-    //     }
-    //     Ok(data)
-    // }
-
-    // fn predict_single(&self, feat: ArrayView1<'_, f32>, ntree_limit: usize) -> Result<f32> {
-    //     if self.mparam.num_output_group != 1 {
-    //         return Err(Error::from_kind(ErrorKind::UnsupportedPredictionMethod(
-    //             String::from("predict_single"),
-    //             format!(
-    //                 "Detail: output group must be equal to 1, current value {}",
-    //                 self.mparam.num_output_group
-    //             ),
-    //         )));
-    //     }
-    //     self.pred(feat, 0)
-    // }
-
-    // fn predict_leaf(&self, feat: ArrayView1<'_, f32>, ntree_limit: usize) -> Result<Vec<usize>> {
-    //     Err(Error::from_kind(ErrorKind::UnsupportedPredictionMethod(
-    //         String::from("predict_leaf"),
-    //         format!("Detail: gblinear model does not support predict leaf index",),
-    //     )))
-    // }
+    fn num_output_group(&self) -> usize {
+        GBLinear::num_output_group(self)
+    }
+
+    fn model(&self) -> BoosterModel<'_> {
+        BoosterModel::Linear(self)
+    }
+
+    fn predict_leaf(
+        &self,
+        _feats: ArrayView2<'_, FeatureValue>,
+        _ntree_limit: usize,
+    ) -> Result<Vec<Vec<u32>>> {
+        Err(Error::from_kind(ErrorKind::UnsupportedPredictionMethod(
+            String::from("predict_leaf"),
+            String::from("Detail: gblinear model does not support predict leaf index"),
+        )))
+    }
+
+    /// The contribution of a feature is its value times its weight, missing values do not
+    /// contribute
+    fn predict_contribs(
+        &self,
+        feats: ArrayView2<'_, FeatureValue>,
+        base_score: f32,
+        _ntree_limit: usize,
+    ) -> Result<Vec<Vec<f32>>> {
+        let num_feature = self.mparam.num_feature;
+        let num_group = self.mparam.num_output_group;
+        Ok(feats
+            .outer_iter()
+            .map(|feat| {
+                let mut row = vec![0f32; num_group * (num_feature + 1)];
+                for (gid, contribs) in row.chunks_mut(num_feature + 1).enumerate() {
+                    for (fid, fvalue) in feat.iter().enumerate().take(num_feature) {
+                        if !fvalue.is_nan() {
+                            contribs[fid] =
+                                to_f32(fvalue * FeatureValue::from(self.weight(fid, gid)));
+                        }
+                    }
+                    contribs[num_feature] = self.bias(gid) + base_score;
+                }
+                row
+            })
+            .collect())
+    }
 
     fn predict_many(
         &self,
-        feats: ArrayView2<'_, f32>,
-        base_score: f32, 
-        ntree_limit: usize,
+        feats: ArrayView2<'_, FeatureValue>,
+        base_score: f32,
+        _ntree_limit: usize,
     ) -> Result<Vec<Vec<f32>>> {
-        Ok(izip!((0..self.mparam.num_output_group).map(|gid| self.pred_many(feats, base_score, gid))).collect())
+        let mut preds = vec![vec![0f32; self.mparam.num_output_group]; feats.nrows()];
+        for gid in 0..self.mparam.num_output_group {
+            for (row, pred) in preds.iter_mut().zip(self.pred_many(feats, base_score, gid)) {
+                row[gid] = pred;
+            }
+        }
+        Ok(preds)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::arr2;
+
+    use crate::testing::{gblinear_model, load_model};
+    use crate::FeatureValue;
+
+    #[test]
+    fn test_missing_values_do_not_contribute() {
+        let predictor = load_model(&gblinear_model(
+            "reg:linear",
+            0.5,
+            2,
+            2,
+            &[2.0, -1.0, 3.0, 1.0, 0.25, 0.0],
+        ));
+        let nan = FeatureValue::NAN;
+        let feats = arr2(&[[1.0, 1.0], [1.0, nan], [nan, 2.0], [nan, nan]]);
+        let preds = predictor.predict_many(feats.view(), true, 0).unwrap();
+        // a missing value is left out of the sum, as in sparse input, rather than making it NaN
+        assert_eq!(
+            preds,
+            vec![
+                vec![5.75, 0.5],
+                vec![2.75, -0.5],
+                vec![6.75, 2.5],
+                vec![0.75, 0.5],
+            ]
+        );
+        let contribs = predictor.predict_contribs(feats.view(), 0).unwrap();
+        assert_eq!(contribs[1], vec![2.0, 0.0, 0.75, -1.0, 0.0, 0.5]);
     }
 }
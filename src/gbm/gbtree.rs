@@ -1,13 +1,39 @@
 use std::cmp;
+use std::sync::OnceLock;
 
-use itertools::izip;
-use ndarray::{ArrayView1, ArrayView2};
+use ndarray::ArrayView2;
+use serde_json::Value;
 
 use crate::errors::*;
-use crate::gbm::grad_booster::GradBooster;
+use crate::gbm::flat_tree::{simd_traversal, FlatTree, BLOCK_ROWS};
+use crate::gbm::grad_booster::{BoosterModel, GradBooster};
+use crate::gbm::quick_scorer::QuickScorer;
 use crate::gbm::regtree::RegTree;
+use crate::json_reader::JsonReader;
 use crate::model_reader::ModelReader;
+use crate::FeatureValue;
 
+/// Deepest trees scored with QuickScorer rather than the AVX2 traversal
+const SIMD_QUICK_SCORER_MAX_DEPTH: usize = 5;
+/// Fewest trees scored with QuickScorer rather than the AVX2 traversal
+const SIMD_QUICK_SCORER_MIN_TREES: usize = 1000;
+
+/// Whether the trees are scored with QuickScorer instead of being traversed. Without the AVX2
+/// traversal QuickScorer is used whenever it supports the trees; with it, only for large
+/// ensembles of trees of depth 5 at most, where it is faster on the benchmarks, see
+/// benches/README.md
+fn use_quick_scorer(trees: &[&FlatTree]) -> bool {
+    if trees.is_empty() || !QuickScorer::supports(trees) {
+        return false;
+    }
+    !simd_traversal()
+        || (trees.len() >= SIMD_QUICK_SCORER_MIN_TREES
+            && trees
+                .iter()
+                .all(|tree| tree.depth() <= SIMD_QUICK_SCORER_MAX_DEPTH))
+}
+
+#[allow(dead_code)]
 struct ModelParam {
     /// number of trees
     num_trees: i32,
@@ -32,14 +58,11 @@ impl ModelParam {
             reader.read_i32_le()?,
             reader.read_i32_le()?,
         );
-        println!("num feature {:?}", num_feature);
         // read padding
         reader.read_i32_le()?;
-        let num_pbuffer = reader.read_i64_le()? as usize;
-        let (num_output_group, size_leaf_vector) = (
-            reader.read_i32_le()? as usize,
-            reader.read_i32_le()? as usize,
-        );
+        let num_pbuffer = reader.read_i64_as_usize()?;
+        let (num_output_group, size_leaf_vector) =
+            (reader.read_i32_as_usize()?, reader.read_i32_as_usize()?);
         let mut reserved = [0i32; 31];
         reader.read_to_i32_buffer(&mut reserved)?;
         // read padding
@@ -54,8 +77,11 @@ impl ModelParam {
         });
     }
 
-    pub fn pred_buffer_size(&self) -> usize {
-        return self.num_pbuffer * (self.num_output_group) * (self.size_leaf_vector + 1);
+    pub fn pred_buffer_size(&self) -> Option<usize> {
+        return self
+            .num_pbuffer
+            .checked_mul(self.num_output_group)?
+            .checked_mul(self.size_leaf_vector + 1);
     }
 }
 
@@ -64,27 +90,78 @@ pub struct GBTree {
     mparam: ModelParam,
     trees: Vec<RegTree>,
     tree_info: Vec<i32>,
-    group_trees: Vec<Vec<RegTree>>,
+    /// indices into `trees` of the trees of each output group
+    group_trees: Vec<Vec<usize>>,
     // use only in DART tree
     weight_drop: Option<Vec<f32>>,
+    /// engine evaluating the trees when they are shallow enough and the AVX2 traversal is not
+    /// available, traversal is used otherwise
+    quick_scorer: Option<QuickScorer>,
+    /// `node_mean_values` of each tree, computed by the first prediction of contributions
+    mean_values: OnceLock<Vec<Vec<f32>>>,
 }
 
 impl GBTree {
-    fn parse_group_trees(
-        num_output_group: usize,
-        tree_info: &Vec<i32>,
-        trees: &Vec<RegTree>,
-    ) -> Vec<Vec<RegTree>> {
+    fn parse_group_trees(num_output_group: usize, tree_info: &[i32]) -> Vec<Vec<usize>> {
         (0..num_output_group)
             .map(|i| {
                 (0..tree_info.len())
                     .filter(|j| tree_info[*j] == i as i32)
-                    .map(|j| trees[j].clone())
                     .collect()
             })
             .collect()
     }
 
+    fn new(
+        mparam: ModelParam,
+        trees: Vec<RegTree>,
+        tree_info: Vec<i32>,
+        group_trees: Vec<Vec<usize>>,
+        weight_drop: Option<Vec<f32>>,
+    ) -> GBTree {
+        let flat_trees: Vec<&FlatTree> = trees.iter().map(|tree| tree.flat()).collect();
+        let quick_scorer = if use_quick_scorer(&flat_trees) {
+            Some(QuickScorer::new(&flat_trees))
+        } else {
+            None
+        };
+        GBTree {
+            mparam,
+            trees,
+            tree_info,
+            group_trees,
+            weight_drop,
+            quick_scorer,
+            mean_values: OnceLock::new(),
+        }
+    }
+
+    fn check_tree_info(tree_info: &[i32], num_output_group: usize) -> Result<()> {
+        match tree_info
+            .iter()
+            .find(|group| **group < 0 || **group as usize >= num_output_group)
+        {
+            Some(group) => Err(Error::from_kind(ErrorKind::BrokenModel(format!(
+                "Detail: tree of output group {}, the model has {} groups",
+                group, num_output_group
+            )))),
+            None => Ok(()),
+        }
+    }
+
+    fn check_weight_drop(weight_drop: &Option<Vec<f32>>, num_trees: usize) -> Result<()> {
+        match weight_drop {
+            Some(weights) if weights.len() != num_trees => {
+                Err(Error::from_kind(ErrorKind::BrokenModel(format!(
+                    "Detail: {} DART weights given for {} trees",
+                    weights.len(),
+                    num_trees
+                ))))
+            }
+            _ => Ok(()),
+        }
+    }
+
     pub fn read_from<T: ModelReader>(
         with_pbuffer: bool,
         reader: &mut T,
@@ -96,203 +173,561 @@ impl GBTree {
             .collect();
         let trees = trees_result?;
 
-        let tree_info = reader.read_int_vec(mparam.num_trees as usize)?;
+        let tree_info = reader.read_int_vec(trees.len())?;
+        GBTree::check_tree_info(&tree_info, mparam.num_output_group)?;
 
         if mparam.num_pbuffer != 0 && with_pbuffer {
-            reader.skip(8 * mparam.pred_buffer_size())?;
+            match mparam
+                .pred_buffer_size()
+                .and_then(|size| size.checked_mul(8))
+            {
+                Some(size) => reader.skip(size)?,
+                None => {
+                    return Err(Error::from_kind(ErrorKind::BrokenModel(String::from(
+                        "Detail: prediction buffer size overflows",
+                    ))))
+                }
+            }
         }
 
-        let group_trees =
-            GBTree::parse_group_trees(mparam.num_output_group as usize, &tree_info, &trees);
+        let group_trees = GBTree::parse_group_trees(mparam.num_output_group, &tree_info);
 
         let weight_drop = if is_dart {
             // if gbtree.mparam.num_trees != 0 {
-            let size = reader.read_i64_le()? as usize;
+            let size = reader.read_i64_as_usize()?;
             Some(reader.read_float_vec(size)?)
         } else {
             None
         };
+        GBTree::check_weight_drop(&weight_drop, trees.len())?;
 
-        Ok(GBTree {
+        Ok(GBTree::new(
             mparam,
             trees,
             tree_info,
             group_trees,
             weight_drop,
-        })
+        ))
     }
 
-    fn pred(
-        &self,
-        feat: ArrayView1<'_, f32>,
-        bst_group: usize,
-        root_index: usize,
-        ntree_limit: usize,
-    ) -> Result<f32> {
-        match &self.weight_drop {
-            None => self.pred_as_gbtree(feat, bst_group, root_index, ntree_limit),
-            Some(weight_drop) => {
-                self.pred_as_dart(feat, &weight_drop, bst_group, root_index, ntree_limit)
-            }
-        }
+    /// Instantiates with trees built from another representation of the model
+    pub(crate) fn from_trees(
+        trees: Vec<RegTree>,
+        tree_info: Vec<i32>,
+        weight_drop: Option<Vec<f32>>,
+        num_feature: usize,
+        num_output_group: usize,
+    ) -> Result<GBTree> {
+        GBTree::check_tree_info(&tree_info, num_output_group)?;
+        GBTree::check_weight_drop(&weight_drop, trees.len())?;
+        let mparam = ModelParam {
+            num_trees: trees.len() as i32,
+            num_roots: 1,
+            num_feature: num_feature as i32,
+            num_pbuffer: 0,
+            num_output_group,
+            size_leaf_vector: 0,
+        };
+        let group_trees = GBTree::parse_group_trees(num_output_group, &tree_info);
+        Ok(GBTree::new(
+            mparam,
+            trees,
+            tree_info,
+            group_trees,
+            weight_drop,
+        ))
     }
 
-    fn pred_as_dart(
-        &self,
-        feat: ArrayView1<'_, f32>,
-        weight_drop: &Vec<f32>,
-        bst_group: usize,
-        root_index: usize,
-        ntree_limit: usize,
-    ) -> Result<f32> {
-        let trees = self.group_trees[bst_group].clone();
-        let treeleft = if ntree_limit == 0 {
-            trees.len()
+    /// Instantiates with the `gradient_booster` object of the JSON model format
+    pub fn from_json(
+        gradient_booster: &Value,
+        num_feature: usize,
+        num_output_group: usize,
+    ) -> Result<Self> {
+        let is_dart = gradient_booster.str_field("name")? == "dart";
+        let model = if is_dart {
+            gradient_booster.field("gbtree")?.field("model")?
         } else {
-            cmp::min(ntree_limit, trees.len())
+            gradient_booster.field("model")?
         };
-        let mut pred = 0f32;
-        for i in 0..treeleft {
-            pred += weight_drop[i] * trees[i].get_leaf_value(feat, root_index)?
+
+        let trees = model
+            .array_field("trees")?
+            .iter()
+            .map(RegTree::from_json)
+            .collect::<Result<Vec<RegTree>>>()?;
+        let tree_info: Vec<i32> = model
+            .i64_vec_field("tree_info")?
+            .into_iter()
+            .map(|group| group as i32)
+            .collect();
+        if tree_info.len() != trees.len() {
+            return Err(Error::from_kind(ErrorKind::BrokenModel(String::from(
+                "Detail: tree_info does not match the trees",
+            ))));
         }
-        Ok(pred)
+        GBTree::check_tree_info(&tree_info, num_output_group)?;
+
+        let mparam = ModelParam {
+            num_trees: trees.len() as i32,
+            num_roots: 1,
+            num_feature: num_feature as i32,
+            num_pbuffer: 0,
+            num_output_group,
+            size_leaf_vector: 0,
+        };
+        let group_trees = GBTree::parse_group_trees(num_output_group, &tree_info);
+        let weight_drop = if is_dart {
+            Some(gradient_booster.f32_vec_field("weight_drop")?)
+        } else {
+            None
+        };
+        GBTree::check_weight_drop(&weight_drop, trees.len())?;
+
+        Ok(GBTree::new(
+            mparam,
+            trees,
+            tree_info,
+            group_trees,
+            weight_drop,
+        ))
     }
 
-    fn pred_as_gbtree(
-        &self,
-        feat: ArrayView1<'_, f32>,
-        bst_group: usize,
-        root_index: usize,
-        ntree_limit: usize,
-    ) -> Result<f32> {
-        let trees = self.group_trees[bst_group].clone();
+    pub fn trees(&self) -> &[RegTree] {
+        &self.trees
+    }
+
+    /// Output group of each tree
+    pub fn tree_info(&self) -> &[i32] {
+        &self.tree_info
+    }
+
+    pub fn num_output_group(&self) -> usize {
+        self.mparam.num_output_group
+    }
+
+    /// Number of rows of the prediction buffer, 0 when the model has none
+    pub fn num_pbuffer(&self) -> usize {
+        self.mparam.num_pbuffer
+    }
+
+    /// Weights of the trees of a DART model
+    pub fn weight_drop(&self) -> Option<&[f32]> {
+        self.weight_drop.as_deref()
+    }
+
+    /// Trees of the group limited to the first `ntree_limit`, 0 meaning all of them
+    fn limited_group_trees(&self, bst_group: usize, ntree_limit: usize) -> &[usize] {
+        let trees = &self.group_trees[bst_group];
         let treeleft = if ntree_limit == 0 {
             trees.len()
         } else {
             cmp::min(ntree_limit, trees.len())
         };
-        let mut pred = 0f32;
-        for i in 0..treeleft {
-            pred += trees[i].get_leaf_value(feat, root_index)?
+        &trees[..treeleft]
+    }
+
+    /// Trees used for prediction under `ntree_limit`, in model order
+    pub fn leaf_trees(&self, ntree_limit: usize) -> Vec<usize> {
+        let mut trees: Vec<usize> = (0..self.mparam.num_output_group)
+            .flat_map(|gid| self.limited_group_trees(gid, ntree_limit).iter().copied())
+            .collect();
+        trees.sort_unstable();
+        trees
+    }
+
+    /// Checks that the rows hold every feature tested by the trees used for prediction
+    fn check_num_columns(&self, num_columns: usize, ntree_limit: usize) -> Result<()> {
+        for gid in 0..self.mparam.num_output_group {
+            for tree_index in self.limited_group_trees(gid, ntree_limit) {
+                match self.trees[*tree_index].flat().max_split_index() {
+                    Some(split_index) if split_index >= num_columns => {
+                        return Err(Error::from_kind(ErrorKind::UnavailableDataIndex(
+                            split_index,
+                        )))
+                    }
+                    _ => {}
+                }
+            }
         }
-        Ok(pred)
+        Ok(())
     }
 
-    fn pred_many(
-        &self,
-        feats: ArrayView2<'_, f32>,
-        bst_group: usize,
-        root_index: usize,
-        ntree_limit: usize,
-    ) -> Result<Vec<f32>> {
+    /// DART trees are scaled by their weight, which is indexed by the position of the tree in
+    /// the whole model
+    fn tree_weight(&self, tree_index: usize) -> f32 {
         match &self.weight_drop {
-            None => self.pred_many_as_gbtree(feats, bst_group, root_index, ntree_limit),
-            Some(weight_drop) => {
-                self.pred_many_as_dart(feats, &weight_drop, bst_group, root_index, ntree_limit)
-            }
+            Some(weight_drop) => weight_drop[tree_index],
+            None => 1f32,
         }
     }
 
-    fn pred_many_as_dart(
+    /// Adds the margins of a block of row-major rows to `block_preds`, walking the trees one at
+    /// a time through the whole block
+    fn pred_block_traversal(
         &self,
-        feats: ArrayView2<'_, f32>,
-        weight_drop: &Vec<f32>,
-        bst_group: usize,
-        root_index: usize,
+        rows: &[FeatureValue],
+        num_columns: usize,
         ntree_limit: usize,
-    ) -> Result<Vec<f32>> {
-        let trees = self.group_trees[bst_group].clone();
-        let treeleft = if ntree_limit == 0 {
-            trees.len()
-        } else {
-            cmp::min(ntree_limit, trees.len())
-        };
-        let mut preds: Vec<f32> = vec![];
-        for feat in 0..feats.nrows() {
-            let mut pred = 0f32;
-            for i in 0..treeleft {
-                pred += weight_drop[i] * trees[i].get_leaf_value(feats.row(feat), root_index)?
+        block_preds: &mut [Vec<f32>],
+    ) {
+        let mut margins = vec![0f32; block_preds.len()];
+        for gid in 0..self.mparam.num_output_group {
+            margins.iter_mut().for_each(|margin| *margin = 0f32);
+            for tree_index in self.limited_group_trees(gid, ntree_limit) {
+                self.trees[*tree_index].flat().add_block(
+                    rows,
+                    num_columns,
+                    self.tree_weight(*tree_index),
+                    &mut margins,
+                );
+            }
+            for (row, margin) in block_preds.iter_mut().zip(margins.iter()) {
+                row[gid] += margin;
             }
-            preds.push(pred)
         }
-        Ok(preds)
     }
 
-    fn pred_many_as_gbtree(
+    /// Adds the margins of a block of row-major rows to `block_preds` with QuickScorer. Leaf
+    /// values are summed in the same order as `pred_block_traversal`, so that both give the
+    /// same bits.
+    fn pred_block_quick_scorer(
         &self,
-        feats: ArrayView2<'_, f32>,
-        bst_group: usize,
-        root_index: usize,
+        scorer: &QuickScorer,
+        rows: &[FeatureValue],
+        num_columns: usize,
         ntree_limit: usize,
-    ) -> Result<Vec<f32>> {
-        let trees = self.group_trees[bst_group].clone();
-        let treeleft = if ntree_limit == 0 {
-            trees.len()
-        } else {
-            cmp::min(ntree_limit, trees.len())
-        };
-        let mut preds: Vec<f32> = vec![];
-        for feat in 0..feats.nrows() {
-            let mut pred = 0f32;
-            for i in 0..treeleft {
-                pred += trees[i].get_leaf_value(feats.row(feat), root_index)?
+        block_preds: &mut [Vec<f32>],
+    ) {
+        // trees after the last one used are not evaluated
+        let num_trees = (0..self.mparam.num_output_group)
+            .filter_map(|gid| self.limited_group_trees(gid, ntree_limit).last())
+            .max()
+            .map_or(0, |tree_index| tree_index + 1);
+        let mut leaves = vec![0u64; num_trees];
+        let mut values = vec![0f32; num_trees];
+        for (rid, row) in block_preds.iter_mut().enumerate() {
+            let feat = &rows[rid * num_columns..(rid + 1) * num_columns];
+            scorer.leaf_values(feat, &mut leaves, &mut values);
+            for (gid, pred) in row.iter_mut().enumerate() {
+                let mut margin = 0f32;
+                for tree_index in self.limited_group_trees(gid, ntree_limit) {
+                    margin += self.tree_weight(*tree_index) * values[*tree_index];
+                }
+                *pred += margin;
             }
-            preds.push(pred)
         }
-        Ok(preds)
+    }
+}
+
+impl GradBooster for GBTree {
+    fn num_output_group(&self) -> usize {
+        GBTree::num_output_group(self)
+    }
+
+    fn model(&self) -> BoosterModel<'_> {
+        BoosterModel::Trees(self)
     }
 
-    fn pred_path(
+    fn predict_leaf(
         &self,
-        feat: ArrayView1<'_, f32>,
-        root_index: usize,
+        feats: ArrayView2<'_, FeatureValue>,
         ntree_limit: usize,
-    ) -> Result<Vec<usize>> {
-        let treeleft = if ntree_limit == 0 {
-            self.trees.len()
-        } else {
-            ntree_limit
-        };
-        let mut preds: Vec<usize> = vec![];
-        for i in 0..treeleft {
-            preds.push(self.trees[i].get_leaf_index(feat, root_index)?)
+    ) -> Result<Vec<Vec<u32>>> {
+        self.check_num_columns(feats.ncols(), ntree_limit)?;
+        let feats = feats.as_standard_layout();
+        let trees = self.leaf_trees(ntree_limit);
+        Ok(feats
+            .outer_iter()
+            .map(|feat| {
+                let feat = feat.to_slice().unwrap();
+                trees
+                    .iter()
+                    .map(|tree_index| self.trees[*tree_index].flat().leaf_id(feat) as u32)
+                    .collect()
+            })
+            .collect())
+    }
+
+    /// DART trees contribute in proportion to their weight, the bias of each group includes
+    /// `base_score`
+    fn predict_contribs(
+        &self,
+        feats: ArrayView2<'_, FeatureValue>,
+        base_score: f32,
+        ntree_limit: usize,
+    ) -> Result<Vec<Vec<f32>>> {
+        let num_columns = feats.ncols();
+        self.check_num_columns(num_columns, ntree_limit)?;
+        let feats = feats.as_standard_layout();
+        let mean_values = self.mean_values.get_or_init(|| {
+            self.trees
+                .iter()
+                .map(|tree| tree.node_mean_values())
+                .collect()
+        });
+
+        let num_group = self.mparam.num_output_group;
+        let mut tree_contribs = vec![0f32; num_columns + 1];
+        Ok(feats
+            .outer_iter()
+            .map(|feat| {
+                let feat = feat.to_slice().unwrap();
+                let mut row = vec![0f32; num_group * (num_columns + 1)];
+                for (gid, contribs) in row.chunks_mut(num_columns + 1).enumerate() {
+                    for tree_index in self.limited_group_trees(gid, ntree_limit) {
+                        tree_contribs.iter_mut().for_each(|contrib| *contrib = 0f32);
+                        self.trees[*tree_index].add_contributions(
+                            feat,
+                            &mean_values[*tree_index],
+                            &mut tree_contribs,
+                        );
+                        let weight = self.tree_weight(*tree_index);
+                        for (contrib, tree_contrib) in contribs.iter_mut().zip(&tree_contribs) {
+                            *contrib += tree_contrib * weight;
+                        }
+                    }
+                    contribs[num_columns] += base_score;
+                }
+                row
+            })
+            .collect())
+    }
+
+    fn predict_many(
+        &self,
+        feats: ArrayView2<'_, FeatureValue>,
+        base_score: f32,
+        ntree_limit: usize,
+    ) -> Result<Vec<Vec<f32>>> {
+        let (num_rows, num_columns) = feats.dim();
+        self.check_num_columns(num_columns, ntree_limit)?;
+        let feats = feats.as_standard_layout();
+        let data = feats.as_slice().ok_or_else(|| {
+            Error::from_kind(ErrorKind::UnsupportedDataType(String::from(
+                "non contiguous rows",
+            )))
+        })?;
+
+        let num_group = self.mparam.num_output_group;
+        let mut preds = Vec::with_capacity(num_rows);
+        for begin in (0..num_rows).step_by(BLOCK_ROWS) {
+            let end = cmp::min(begin + BLOCK_ROWS, num_rows);
+            let rows = &data[begin * num_columns..end * num_columns];
+            let mut block_preds = vec![vec![base_score; num_group]; end - begin];
+            match &self.quick_scorer {
+                Some(scorer) => self.pred_block_quick_scorer(
+                    scorer,
+                    rows,
+                    num_columns,
+                    ntree_limit,
+                    &mut block_preds,
+                ),
+                None => self.pred_block_traversal(rows, num_columns, ntree_limit, &mut block_preds),
+            }
+            preds.extend(block_preds);
         }
         Ok(preds)
     }
 }
 
-impl GradBooster for GBTree {
-    // fn predict(&self, feat: ArrayView1<'_, f32>, ntree_limit: usize) -> Result<Vec<f32>> {
-    //     let mut data: Vec<f32> = vec![];
-    //     for gid in 0..self.mparam.num_output_group {
-    //         data.push(self.pred(feat, gid, 0, ntree_limit)?)
-    //     }
-    //     Ok(data)
-    // }
-
-    // fn predict_single(&self, feat: ArrayView1<'_, f32>, ntree_limit: usize) -> Result<f32> {
-    //     if self.mparam.num_output_group != 1 {
-    //         return Err(Error::from_kind(ErrorKind::UnsupportedPredictionMethod(
-    //             String::from("predict_single"),
-    //             format!(
-    //                 "Detail: output group must be equal to 1, current value {}",
-    //                 self.mparam.num_output_group
-    //             ),
-    //         )));
-    //     }
-    //     self.pred(feat, 0, 0, ntree_limit)
-    // }
-
-    // fn predict_leaf(&self, feat: ArrayView1<'_, f32>, ntree_limit: usize) -> Result<Vec<usize>> {
-    //     self.pred_path(feat, 0, ntree_limit)
-    // }
-
-    fn predict_many(&self, feats: ArrayView2<'_, f32>, base_score: f32, ntree_limit: usize) -> Result<Vec<Vec<f32>>> {
-        // let mut data: Vec<Vec<f32>> = vec![];
-        // for gid in 0..self.mparam.num_output_group {
-        //     data.push(self.pred_many(feats, gid as usize, 0, ntree_limit)?)
-        // }
-        // Ok(izip!(data).collect())
-        Ok(vec![vec![]])
+#[cfg(test)]
+mod tests {
+    use ndarray::Array2;
+
+    use crate::testing::{
+        gbtree_model, random_matrix, random_trees, skip_to_booster, TestNode, TestTree,
+    };
+    use crate::FeatureValue;
+
+    use super::{use_quick_scorer, GBTree};
+    use crate::gbm::flat_tree::{simd_traversal, FlatTree};
+    use crate::gbm::grad_booster::GradBooster;
+    use crate::gbm::quick_scorer::QuickScorer;
+
+    /// Reads the booster of a model written by `gbtree_model`
+    fn load_gbtree(trees: &[TestTree], weight_drop: Option<&[f32]>) -> GBTree {
+        let bytes = gbtree_model("multi:softprob", 0.5, 8, 2, trees, weight_drop);
+        let mut reader = &bytes[..];
+        skip_to_booster(&mut reader);
+        GBTree::read_from(false, &mut reader, weight_drop.is_some()).unwrap()
+    }
+
+    fn reference_preds(
+        trees: &[TestTree],
+        weight_drop: Option<&[f32]>,
+        feats: &Array2<FeatureValue>,
+    ) -> Vec<Vec<f32>> {
+        feats
+            .outer_iter()
+            .map(|feat| {
+                let mut margins = [0f32; 2];
+                for (i, tree) in trees.iter().enumerate() {
+                    let weight = weight_drop.map_or(1f32, |weights| weights[i]);
+                    margins[tree.group as usize] +=
+                        weight * tree.leaf_value(feat.as_slice().unwrap());
+                }
+                vec![0.5 + margins[0], 0.5 + margins[1]]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_predict_many_matches_reference() {
+        let feats = random_matrix(5, 300, 8, 0.2);
+        // values on the grid of the split conditions
+        let grid_feats = feats.mapv(|value| (value * 16.0).floor() / 16.0);
+        for max_depth in &[6, 9] {
+            let trees = random_trees(3, 8, 2, 30, *max_depth);
+            let weights: Vec<f32> = (0..trees.len()).map(|i| 1f32 / (i + 1) as f32).collect();
+            for weight_drop in &[None, Some(&weights[..])] {
+                let gbtree = load_gbtree(&trees, *weight_drop);
+                assert_eq!(
+                    gbtree.quick_scorer.is_some(),
+                    *max_depth <= 6 && !simd_traversal()
+                );
+                for feats in &[&feats, &grid_feats] {
+                    let preds = gbtree.predict_many(feats.view(), 0.5, 0).unwrap();
+                    assert_eq!(preds, reference_preds(&trees, *weight_drop, feats));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_use_quick_scorer() {
+        for (num_trees, max_depth, with_simd) in &[(20, 5, false), (500, 5, true), (500, 6, false)]
+        {
+            let gbtree = load_gbtree(&random_trees(23, 8, 2, *num_trees, *max_depth), None);
+            let flat_trees: Vec<&FlatTree> = gbtree.trees.iter().map(|tree| tree.flat()).collect();
+            assert_eq!(
+                use_quick_scorer(&flat_trees),
+                !simd_traversal() || *with_simd
+            );
+        }
+        assert!(!use_quick_scorer(&[]));
+    }
+
+    #[test]
+    fn test_quick_scorer_is_bit_exact() {
+        let trees = random_trees(17, 8, 2, 200, 6);
+        let mut gbtree = load_gbtree(&trees, None);
+        let flat_trees: Vec<&FlatTree> = gbtree.trees.iter().map(|tree| tree.flat()).collect();
+        let quick_scorer = QuickScorer::new(&flat_trees);
+        gbtree.quick_scorer = Some(quick_scorer);
+        let feats = random_matrix(19, 500, 8, 0.1);
+        for ntree_limit in &[0, 50] {
+            let quick_scorer = gbtree
+                .predict_many(feats.view(), 0.5, *ntree_limit)
+                .unwrap();
+            let scorer = gbtree.quick_scorer.take();
+            let traversal = gbtree
+                .predict_many(feats.view(), 0.5, *ntree_limit)
+                .unwrap();
+            gbtree.quick_scorer = scorer;
+            let bits = |preds: Vec<Vec<f32>>| -> Vec<u32> {
+                preds.into_iter().flatten().map(f32::to_bits).collect()
+            };
+            assert_eq!(bits(quick_scorer), bits(traversal));
+        }
+    }
+
+    /// Expected value of the tree when only the features of `known` are given, the other
+    /// splits being averaged by cover
+    fn expected_value(tree: &TestTree, nid: usize, feat: &[FeatureValue], known: usize) -> f64 {
+        match tree.nodes[nid] {
+            TestNode::Leaf(value) => f64::from(value),
+            TestNode::Split(index, cond, left, right, default_left) => {
+                let (left, right) = (left as usize, right as usize);
+                if known & (1 << index) != 0 {
+                    let fvalue = feat[index as usize];
+                    let go_left = if fvalue.is_nan() {
+                        default_left
+                    } else {
+                        fvalue < FeatureValue::from(cond)
+                    };
+                    expected_value(tree, if go_left { left } else { right }, feat, known)
+                } else {
+                    (expected_value(tree, left, feat, known) * f64::from(tree.covers[left])
+                        + expected_value(tree, right, feat, known) * f64::from(tree.covers[right]))
+                        / f64::from(tree.covers[nid])
+                }
+            }
+        }
+    }
+
+    /// Exact Shapley values of the features of the tree
+    fn shapley_values(tree: &TestTree, feat: &[FeatureValue]) -> Vec<f64> {
+        let num_feature = feat.len();
+        let factorial = |n: usize| (1..=n).map(|i| i as f64).product::<f64>();
+        (0..num_feature)
+            .map(|fid| {
+                (0..1usize << num_feature)
+                    .filter(|subset| subset & (1 << fid) == 0)
+                    .map(|subset| {
+                        let size = subset.count_ones() as usize;
+                        let weight = factorial(size) * factorial(num_feature - size - 1)
+                            / factorial(num_feature);
+                        weight
+                            * (expected_value(tree, 0, feat, subset | (1 << fid))
+                                - expected_value(tree, 0, feat, subset))
+                    })
+                    .sum()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_predict_contribs_matches_shapley_values() {
+        let trees = random_trees(29, 8, 2, 4, 4);
+        let weights: Vec<f32> = (0..trees.len()).map(|i| 1f32 / (i + 1) as f32).collect();
+        let feats = random_matrix(31, 20, 8, 0.2);
+        for weight_drop in &[None, Some(&weights[..])] {
+            let gbtree = load_gbtree(&trees, *weight_drop);
+            let contribs = gbtree.predict_contribs(feats.view(), 0.5, 0).unwrap();
+            let margins = gbtree.predict_many(feats.view(), 0.5, 0).unwrap();
+            for (rid, feat) in feats.outer_iter().enumerate() {
+                let feat = feat.to_slice().unwrap();
+                let mut expected = vec![vec![0f64; 9]; 2];
+                for (i, tree) in trees.iter().enumerate() {
+                    let weight = f64::from(weight_drop.map_or(1f32, |weights| weights[i]));
+                    let group = &mut expected[tree.group as usize];
+                    for (fid, value) in shapley_values(tree, feat).into_iter().enumerate() {
+                        group[fid] += weight * value;
+                    }
+                    group[8] += weight * expected_value(tree, 0, feat, 0);
+                }
+                for gid in 0..2 {
+                    let row = &contribs[rid][gid * 9..(gid + 1) * 9];
+                    for fid in 0..8 {
+                        assert!((f64::from(row[fid]) - expected[gid][fid]).abs() < 1e-4);
+                    }
+                    assert!((f64::from(row[8]) - 0.5 - expected[gid][8]).abs() < 1e-4);
+                    assert!((row.iter().sum::<f32>() - margins[rid][gid]).abs() < 1e-4);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_predict_leaf() {
+        let trees = random_trees(37, 8, 2, 10, 5);
+        let gbtree = load_gbtree(&trees, None);
+        let feats = random_matrix(41, 50, 8, 0.2);
+        let leaves = gbtree.predict_leaf(feats.view(), 0).unwrap();
+        for (feat, row) in feats.outer_iter().zip(&leaves) {
+            let feat = feat.to_slice().unwrap();
+            assert_eq!(row.len(), trees.len());
+            for (tree, leaf) in trees.iter().zip(row) {
+                match tree.nodes[*leaf as usize] {
+                    TestNode::Leaf(value) => assert_eq!(value, tree.leaf_value(feat)),
+                    TestNode::Split(..) => panic!("leaf {} is a split", leaf),
+                }
+            }
+        }
+        // the first 3 trees of each group, interleaved
+        let limited = gbtree.predict_leaf(feats.view(), 3).unwrap();
+        assert_eq!(gbtree.leaf_trees(3), vec![0, 1, 2, 3, 4, 5]);
+        assert_eq!(limited[0], leaves[0][..6].to_vec());
     }
 }
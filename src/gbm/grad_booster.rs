@@ -1,28 +1,61 @@
-use ndarray::{ArrayView1, ArrayView2};
+use ndarray::ArrayView2;
+use serde_json::Value;
 
 use crate::errors::*;
+use crate::gbm::compact::CompactGBTree;
 use crate::gbm::gblinear::GBLinear;
 use crate::gbm::gbtree::GBTree;
+use crate::json_reader::JsonReader;
+use crate::mapped::MappedBooster;
 use crate::model_reader::ModelReader;
+use crate::FeatureValue;
+
+/// Structure of a gradient boosting model, read by the exporters
+pub enum BoosterModel<'a> {
+    Trees(&'a GBTree),
+    Linear(&'a GBLinear),
+    /// Quantised trees, without node statistics
+    Compact(&'a CompactGBTree),
+    /// Model read in place from a memory map
+    Mapped(&'a MappedBooster),
+}
 
 /// Interface of gradient boosting model
 pub trait GradBooster {
-    /// Generates predictions for given feature vector
-    // fn predict(&self, feat: ArrayView1<'_, f32>, ntree_limit: usize) -> Result<Vec<f32>>;
-    // /// Generates a prediction for given feature vector
-    // fn predict_single(&self, feat: ArrayView1<'_, f32>, ntree_limit: usize) -> Result<f32>;
-    // /// Predicts the leaf index of each tree. This is only valid in gbtree predictor
-    // fn predict_leaf(&self, feat: ArrayView1<'_, f32>, ntree_limit: usize) -> Result<Vec<usize>>;
-    // /// Generates predictions for given vectors of features
-    fn predict_many(&self, feats: ArrayView2<'_, f32>, base_score: f32, ntree_limit: usize)
-        -> Result<Vec<Vec<f32>>>;
+    /// Generates predictions for given vectors of features, one row of output groups per
+    /// input row
+    fn predict_many(
+        &self,
+        feats: ArrayView2<'_, FeatureValue>,
+        base_score: f32,
+        ntree_limit: usize,
+    ) -> Result<Vec<Vec<f32>>>;
+    /// Predicts the id of the leaf reached in each tree used for prediction, in model order.
+    /// This is only valid in gbtree predictor.
+    fn predict_leaf(
+        &self,
+        feats: ArrayView2<'_, FeatureValue>,
+        ntree_limit: usize,
+    ) -> Result<Vec<Vec<u32>>>;
+    /// Predicts the contribution of each feature to the margins, laid out as
+    /// `[group][feature]` with the bias of each group after its features
+    fn predict_contribs(
+        &self,
+        feats: ArrayView2<'_, FeatureValue>,
+        base_score: f32,
+        ntree_limit: usize,
+    ) -> Result<Vec<Vec<f32>>>;
+    /// Number of margins predicted per row
+    fn num_output_group(&self) -> usize;
+    /// Gives access to the structure of the model
+    fn model(&self) -> BoosterModel<'_>;
 }
 
 pub fn load_grad_booster<T: ModelReader>(
     reader: &mut T,
     name_gbm: Vec<u8>,
     with_pbuffer: bool,
-) -> Result<Box<dyn GradBooster + Send>> {
+) -> Result<Box<dyn GradBooster + Send + Sync>> {
     match name_gbm.as_slice() {
         b"gbtree" => Ok(Box::new(GBTree::read_from(with_pbuffer, reader, false)?)),
         b"gblinear" => Ok(Box::new(GBLinear::read_from(with_pbuffer, reader)?)),
@@ -32,3 +65,25 @@ pub fn load_grad_booster<T: ModelReader>(
         ))),
     }
 }
+
+pub fn load_grad_booster_json(
+    gradient_booster: &Value,
+    num_feature: usize,
+    num_output_group: usize,
+) -> Result<Box<dyn GradBooster + Send + Sync>> {
+    match gradient_booster.str_field("name")? {
+        "gbtree" | "dart" => Ok(Box::new(GBTree::from_json(
+            gradient_booster,
+            num_feature,
+            num_output_group,
+        )?)),
+        "gblinear" => Ok(Box::new(GBLinear::from_json(
+            gradient_booster,
+            num_feature,
+            num_output_group,
+        )?)),
+        name => Err(Error::from_kind(ErrorKind::UnsupportedModelType(
+            String::from(name),
+        ))),
+    }
+}
@@ -1,4 +1,7 @@
-mod gblinear;
-mod gbtree;
+pub(crate) mod compact;
+pub(crate) mod flat_tree;
+pub(crate) mod gblinear;
+pub(crate) mod gbtree;
 pub mod grad_booster;
-mod regtree;
+mod quick_scorer;
+pub(crate) mod regtree;
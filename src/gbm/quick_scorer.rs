@@ -0,0 +1,172 @@
+//! QuickScorer evaluation of ensembles of shallow trees (Lucchese et al., "QuickScorer: a Fast
+//! Algorithm to Rank Documents with Additive Ensembles of Regression Trees", SIGIR 2015).
+//!
+//! The leaves of a tree are numbered from left to right and the reachable leaves are kept as a
+//! bitvector. A split whose test sends the row right removes the leaves of its left subtree,
+//! and the reached leaf is the leftmost leaf left. Splits are grouped by feature and sorted by
+//! threshold, so a row only visits the splits it sends right, feature by feature, instead of
+//! walking every tree.
+use crate::gbm::flat_tree::{FlatNode, FlatTree};
+use crate::FeatureValue;
+
+/// Deepest trees handled, their leaves fit in a 64 bit vector
+pub const MAX_DEPTH: usize = 6;
+
+/// Splits of the trees on one feature
+#[derive(Clone, Default)]
+struct FeatureSplits {
+    /// split conditions in ascending order
+    thresholds: Vec<f32>,
+    trees: Vec<u32>,
+    /// reachable leaves when the split sends the row right
+    masks: Vec<u64>,
+    /// splits sending missing values right
+    default_right_trees: Vec<u32>,
+    default_right_masks: Vec<u64>,
+}
+
+#[derive(Clone)]
+pub struct QuickScorer {
+    features: Vec<FeatureSplits>,
+    /// leaf values of the trees from left to right, starting at `leaf_offsets[tree]`
+    leaf_values: Vec<f32>,
+    leaf_offsets: Vec<usize>,
+}
+
+impl QuickScorer {
+    /// Whether the trees can be evaluated: no tree is deeper than `MAX_DEPTH` and splits are
+    /// numerical with ordered conditions. `Param::max_depth` is not kept up to date by
+    /// xgboost, so the depth is measured on the trees.
+    pub fn supports(trees: &[&FlatTree]) -> bool {
+        trees.iter().all(|tree| {
+            tree.depth() <= MAX_DEPTH
+                && (0..tree.num_nodes()).all(|nid| match tree.node(nid) {
+                    FlatNode::Leaf(_) => true,
+                    FlatNode::Split { split_cond, .. } => !split_cond.is_nan(),
+                    FlatNode::CategoricalSplit { .. } => false,
+                })
+        })
+    }
+
+    /// Preprocesses trees accepted by `supports`
+    pub fn new(trees: &[&FlatTree]) -> QuickScorer {
+        let num_feature = trees
+            .iter()
+            .filter_map(|tree| tree.max_split_index())
+            .max()
+            .map_or(0, |split_index| split_index + 1);
+        // (threshold, tree, mask, default right) of each split of each feature
+        let mut splits: Vec<Vec<(f32, u32, u64, bool)>> = vec![vec![]; num_feature];
+        let mut leaf_values = vec![];
+        let mut leaf_offsets = vec![];
+        for (tid, tree) in trees.iter().enumerate() {
+            leaf_offsets.push(leaf_values.len());
+            let mut stack = vec![0usize];
+            // number the leaves in order, splits are given the mask of their left subtree
+            let first_leaf = leaf_values.len();
+            let mut pending: Vec<(usize, usize)> = vec![];
+            while let Some(nid) = stack.pop() {
+                match tree.node(nid) {
+                    FlatNode::Leaf(value) => leaf_values.push(value),
+                    FlatNode::Split { cleft, cright, .. }
+                    | FlatNode::CategoricalSplit { cleft, cright, .. } => {
+                        pending.push((nid, leaf_values.len() - first_leaf));
+                        stack.push(cright);
+                        stack.push(cleft);
+                    }
+                }
+            }
+            for (nid, begin) in pending {
+                if let FlatNode::Split {
+                    cleft,
+                    split_index,
+                    split_cond,
+                    default_left,
+                    ..
+                } = tree.node(nid)
+                {
+                    let end = begin + count_leaves(tree, cleft);
+                    let left_leaves = ones(end) & !ones(begin);
+                    splits[split_index].push((split_cond, tid as u32, !left_leaves, !default_left));
+                }
+            }
+        }
+
+        let features = splits
+            .into_iter()
+            .map(|mut feature_splits| {
+                feature_splits.sort_by(|a, b| a.0.total_cmp(&b.0));
+                let default_right = feature_splits.iter().filter(|split| split.3);
+                FeatureSplits {
+                    thresholds: feature_splits.iter().map(|split| split.0).collect(),
+                    trees: feature_splits.iter().map(|split| split.1).collect(),
+                    masks: feature_splits.iter().map(|split| split.2).collect(),
+                    default_right_trees: default_right.clone().map(|split| split.1).collect(),
+                    default_right_masks: default_right.map(|split| split.2).collect(),
+                }
+            })
+            .collect();
+        QuickScorer {
+            features,
+            leaf_values,
+            leaf_offsets,
+        }
+    }
+
+    /// Writes the value of the leaf reached in each of the first `leaves.len()` trees by the row
+    /// to `values`, using `leaves` as the bitvectors of the trees. The splits of the other trees
+    /// are skipped.
+    pub fn leaf_values(&self, feat: &[FeatureValue], leaves: &mut [u64], values: &mut [f32]) {
+        leaves.iter_mut().for_each(|bits| *bits = !0u64);
+        for (splits, fvalue) in self.features.iter().zip(feat) {
+            if fvalue.is_nan() {
+                for (tid, mask) in splits
+                    .default_right_trees
+                    .iter()
+                    .zip(&splits.default_right_masks)
+                {
+                    if let Some(bits) = leaves.get_mut(*tid as usize) {
+                        *bits &= mask;
+                    }
+                }
+                continue;
+            }
+            // splits whose condition is not above the value send the row right
+            for (threshold, (tid, mask)) in splits
+                .thresholds
+                .iter()
+                .zip(splits.trees.iter().zip(&splits.masks))
+            {
+                if FeatureValue::from(*threshold) > *fvalue {
+                    break;
+                }
+                if let Some(bits) = leaves.get_mut(*tid as usize) {
+                    *bits &= mask;
+                }
+            }
+        }
+        for ((value, bits), offset) in values.iter_mut().zip(leaves.iter()).zip(&self.leaf_offsets)
+        {
+            *value = self.leaf_values[offset + bits.trailing_zeros() as usize];
+        }
+    }
+}
+
+fn count_leaves(tree: &FlatTree, nid: usize) -> usize {
+    match tree.node(nid) {
+        FlatNode::Leaf(_) => 1,
+        FlatNode::Split { cleft, cright, .. }
+        | FlatNode::CategoricalSplit { cleft, cright, .. } => {
+            count_leaves(tree, cleft) + count_leaves(tree, cright)
+        }
+    }
+}
+
+/// Bits below `count` set
+fn ones(count: usize) -> u64 {
+    if count >= 64 {
+        !0u64
+    } else {
+        (1u64 << count) - 1
+    }
+}
@@ -1,10 +1,14 @@
 use std::f32;
 
-use ndarray::ArrayView1;
+use serde_json::Value;
 
 use crate::errors::*;
+use crate::gbm::flat_tree::{FlatNode, FlatTree};
+use crate::json_reader::JsonReader;
 use crate::model_reader::ModelReader;
+use crate::FeatureValue;
 
+#[allow(dead_code)]
 #[derive(Clone, Copy)]
 struct Param {
     /// number of start root
@@ -44,6 +48,17 @@ impl Param {
             size_leaf_vector,
         });
     }
+
+    fn from_json(tree_param: &Value) -> Result<Param> {
+        return Ok(Param {
+            num_roots: 1,
+            num_nodes: tree_param.usize_field("num_nodes")? as i32,
+            num_deleted: tree_param.usize_field("num_deleted").unwrap_or(0) as i32,
+            max_depth: 0,
+            num_feature: tree_param.usize_field("num_feature")? as i32,
+            size_leaf_vector: tree_param.usize_field("size_leaf_vector").unwrap_or(0) as i32,
+        });
+    }
 }
 
 #[derive(Clone, Copy)]
@@ -59,8 +74,18 @@ enum LeafOrSplit {
         default_next: i32,
         split_index: i32,
     },
+    /// categorical split, the feature value goes right when it is one of the tree's
+    /// `categories[categories]` and left otherwise
+    CategoricalSplit {
+        cleft: i32,
+        cright: i32,
+        default_next: i32,
+        split_index: i32,
+        categories: usize,
+    },
 }
 
+#[allow(dead_code)]
 #[derive(Clone, Copy)]
 struct Node {
     /// pointer to parent, highest bit is used to indicate whether it's a left child or not
@@ -84,7 +109,17 @@ impl Node {
             reader.read_i32_le()?,
             reader.read_i32_le()?,
         );
+        return Ok(Node::new(
+            parent,
+            cleft,
+            cright,
+            sindex,
+            reader.read_f32_le()?,
+        ));
+    }
 
+    /// `value` is the leaf value of leaf nodes and the split condition of split nodes
+    fn new(parent: i32, cleft: i32, cright: i32, sindex: i32, value: f32) -> Node {
         let split_index = Node::decode_split_index(sindex);
         let default_next = if Node::is_default_left(sindex) {
             cleft
@@ -95,51 +130,83 @@ impl Node {
         let is_leaf = cleft == -1;
 
         let leaf_or_split = if is_leaf {
-            LeafOrSplit::LeafValue(reader.read_f32_le()?)
+            LeafOrSplit::LeafValue(value)
         } else {
             LeafOrSplit::Split {
                 cleft,
                 cright,
-                split_cond: reader.read_f32_le()?,
+                split_cond: value,
                 default_next,
                 split_index,
             }
         };
 
-        return Ok(Node {
+        return Node {
             parent,
             leaf_or_split,
-        });
+        };
     }
 
-    fn next(&self, feat: ArrayView1<f32>) -> Result<Option<usize>> {
+    /// Turns a split node into a categorical split on the tree's `categories[categories]`
+    fn into_categorical(self, categories: usize) -> Result<Node> {
         return match self.leaf_or_split {
-            LeafOrSplit::LeafValue(_) => Ok(None),
+            LeafOrSplit::Split {
+                cleft,
+                cright,
+                default_next,
+                split_index,
+                ..
+            } => Ok(Node {
+                parent: self.parent,
+                leaf_or_split: LeafOrSplit::CategoricalSplit {
+                    cleft,
+                    cright,
+                    default_next,
+                    split_index,
+                    categories,
+                },
+            }),
+            _ => Err(Error::from_kind(ErrorKind::BrokenModel(String::from(
+                "Detail: broken tree - categories of a leaf node",
+            )))),
+        };
+    }
+
+    /// Children are valid node indices once the tree is checked
+    fn to_flat(self) -> FlatNode {
+        return match self.leaf_or_split {
+            LeafOrSplit::LeafValue(leaf_value) => FlatNode::Leaf(leaf_value),
             LeafOrSplit::Split {
                 cleft,
                 cright,
                 split_cond,
                 default_next,
                 split_index,
-            } => match feat.get(split_index as usize) {
-                None => Err(Error::from_kind(ErrorKind::UnavailableDataIndex(format!(
-                    "cannot get feature value by index: {}",
-                    split_index
-                )))),
-                Some(fvalue) => {
-                    if fvalue.is_nan() {
-                        Ok(Some(default_next as usize))
-                    } else if *fvalue < split_cond {
-                        Ok(Some(cleft as usize))
-                    } else {
-                        Ok(Some(cright as usize))
-                    }
-                }
+            } => FlatNode::Split {
+                cleft: cleft as usize,
+                cright: cright as usize,
+                split_index: split_index as usize,
+                split_cond,
+                default_left: default_next == cleft,
+            },
+            LeafOrSplit::CategoricalSplit {
+                cleft,
+                cright,
+                default_next,
+                split_index,
+                categories,
+            } => FlatNode::CategoricalSplit {
+                cleft: cleft as usize,
+                cright: cright as usize,
+                split_index: split_index as usize,
+                default_left: default_next == cleft,
+                categories,
             },
         };
     }
 }
 
+#[allow(dead_code)]
 #[derive(Clone, Copy)]
 struct RTreeNodeStat {
     /// loss chg caused by current split
@@ -163,14 +230,62 @@ impl RTreeNodeStat {
     }
 }
 
-/// Regression tree
+/// Regression tree. The nodes are only kept in the layout used for traversal, nodes are given
+/// by their positions in it and `FlatTree::node_id` maps them back to the ids of the model.
 pub struct RegTree {
     param: Param,
-    nodes: Vec<Node>,
+    /// statistics of the nodes, in the order of the layout
     stats: Vec<RTreeNodeStat>,
+    flat: FlatTree,
 }
 
 impl RegTree {
+    /// The nodes must have been checked by `check_nodes`
+    fn new(
+        param: Param,
+        nodes: Vec<Node>,
+        stats: Vec<RTreeNodeStat>,
+        categories: Vec<Vec<u32>>,
+    ) -> RegTree {
+        let flat_nodes: Vec<FlatNode> = nodes.iter().map(|node| node.to_flat()).collect();
+        let flat = FlatTree::from_nodes(&flat_nodes, &categories);
+        let stats = (0..flat.num_nodes())
+            .map(|nid| stats[flat.node_id(nid)])
+            .collect();
+        return RegTree { param, stats, flat };
+    }
+
+    /// Instantiates with nodes laid out as `FlatTree::node` gives them, along with their ids in
+    /// the model, loss changes and covers, as `mapped` writes them
+    pub(crate) fn from_layout(
+        num_feature: usize,
+        nodes: &[FlatNode],
+        node_ids: &[u32],
+        loss_changes: &[f32],
+        sum_hessians: &[f32],
+        categories: Vec<Vec<u32>>,
+    ) -> RegTree {
+        let param = Param {
+            num_roots: 1,
+            num_nodes: nodes.len() as i32,
+            num_deleted: 0,
+            max_depth: 0,
+            num_feature: num_feature as i32,
+            size_leaf_vector: 0,
+        };
+        let mut flat = FlatTree::from_nodes(nodes, &categories);
+        let stats = (0..flat.num_nodes())
+            .map(|nid| RTreeNodeStat {
+                loss_chg: loss_changes[flat.node_id(nid)],
+                sum_hess: sum_hessians[flat.node_id(nid)],
+                base_weight: 0f32,
+                leaf_child_cnt: 0,
+            })
+            .collect();
+        flat.renumber(node_ids);
+        return RegTree { param, stats, flat };
+    }
+
     pub fn read_from<T: ModelReader>(reader: &mut T) -> Result<RegTree> {
         let param = Param::read_from(reader)?;
         let nodes: Result<Vec<Node>> = (0..param.num_nodes)
@@ -179,44 +294,415 @@ impl RegTree {
         let stats: Result<Vec<RTreeNodeStat>> = (0..param.num_nodes)
             .map(|_| RTreeNodeStat::read_from(reader))
             .collect();
-        return Ok(RegTree {
-            param,
-            nodes: nodes?,
-            stats: stats?,
-        });
+        let nodes = nodes?;
+        RegTree::check_nodes(&nodes)?;
+        return Ok(RegTree::new(param, nodes, stats?, vec![]));
     }
 
-    pub fn get_leaf_index(&self, feat: ArrayView1<f32>, root_id: usize) -> Result<usize> {
-        let mut pid = root_id;
-        let mut node = self.nodes[pid];
-        loop {
-            match node.next(feat)? {
-                None => return Ok(pid),
-                Some(new_pid) => {
-                    pid = new_pid;
-                    node = self.nodes[pid];
-                }
+    /// Checks that the nodes reachable from the root form a tree: every child index points to
+    /// an existing node and no node is reached twice, so that traversal always ends in a leaf
+    fn check_nodes(nodes: &[Node]) -> Result<()> {
+        if nodes.is_empty() {
+            return Err(Error::from_kind(ErrorKind::BrokenModel(String::from(
+                "Detail: broken tree - no root node",
+            ))));
+        }
+        let mut visited = vec![false; nodes.len()];
+        let mut stack = vec![0usize];
+        while let Some(nid) = stack.pop() {
+            if visited[nid] {
+                return Err(Error::from_kind(ErrorKind::BrokenModel(format!(
+                    "Detail: broken tree - node {} is reached twice",
+                    nid
+                ))));
+            }
+            visited[nid] = true;
+            let children = match nodes[nid].leaf_or_split {
+                LeafOrSplit::LeafValue(_) => continue,
+                LeafOrSplit::Split { cleft, cright, .. }
+                | LeafOrSplit::CategoricalSplit { cleft, cright, .. } => [cleft, cright],
             };
+            for child in children.iter() {
+                if *child < 0 || *child as usize >= nodes.len() {
+                    return Err(Error::from_kind(ErrorKind::BrokenModel(format!(
+                        "Detail: broken tree - node {} has a child {} out of {} nodes",
+                        nid,
+                        child,
+                        nodes.len()
+                    ))));
+                }
+                stack.push(*child as usize);
+            }
         }
+        return Ok(());
+    }
+
+    /// Instantiates with a tree of the JSON model format
+    pub fn from_json(tree: &Value) -> Result<RegTree> {
+        let param = Param::from_json(tree.field("tree_param")?)?;
+        let num_nodes = param.num_nodes as usize;
+        let left_children = tree.i64_vec_field("left_children")?;
+        let right_children = tree.i64_vec_field("right_children")?;
+        let parents = tree.i64_vec_field("parents")?;
+        let split_indices = tree.i64_vec_field("split_indices")?;
+        let split_conditions = tree.f32_vec_field("split_conditions")?;
+        let default_left = tree.i64_vec_field("default_left")?;
+        let base_weights = tree.f32_vec_field("base_weights")?;
+        let loss_changes = tree.f32_vec_field("loss_changes")?;
+        let sum_hessian = tree.f32_vec_field("sum_hessian")?;
+        let lengths = [
+            left_children.len(),
+            right_children.len(),
+            parents.len(),
+            split_indices.len(),
+            split_conditions.len(),
+            default_left.len(),
+            base_weights.len(),
+            loss_changes.len(),
+            sum_hessian.len(),
+        ];
+        if lengths.iter().any(|len| *len != num_nodes) {
+            return Err(Error::from_kind(ErrorKind::BrokenModel(format!(
+                "Detail: broken tree - expected {} nodes",
+                num_nodes
+            ))));
+        }
+
+        let mut nodes: Vec<Node> = (0..num_nodes)
+            .map(|nid| {
+                let sindex = (split_indices[nid] as i32 & i32::MAX)
+                    | if default_left[nid] != 0 { i32::MIN } else { 0 };
+                Node::new(
+                    parents[nid] as i32,
+                    left_children[nid] as i32,
+                    right_children[nid] as i32,
+                    sindex,
+                    split_conditions[nid],
+                )
+            })
+            .collect();
+        let stats = (0..num_nodes)
+            .map(|nid| RTreeNodeStat {
+                loss_chg: loss_changes[nid],
+                sum_hess: sum_hessian[nid],
+                base_weight: base_weights[nid],
+                leaf_child_cnt: 0,
+            })
+            .collect();
+
+        let mut categories = vec![];
+        if tree.get("categories_nodes").is_some() {
+            let all_categories = tree.i64_vec_field("categories")?;
+            let segments = tree.i64_vec_field("categories_segments")?;
+            let sizes = tree.i64_vec_field("categories_sizes")?;
+            for (i, nid) in tree
+                .i64_vec_field("categories_nodes")?
+                .into_iter()
+                .enumerate()
+            {
+                let (begin, size) = match (segments.get(i), sizes.get(i)) {
+                    (Some(begin), Some(size)) => (*begin as usize, *size as usize),
+                    _ => {
+                        return Err(Error::from_kind(ErrorKind::BrokenModel(String::from(
+                            "Detail: broken tree - missing categories segment",
+                        ))))
+                    }
+                };
+                let mut node_categories: Vec<u32> = match all_categories.get(begin..begin + size) {
+                    Some(values) => values.iter().map(|value| *value as u32).collect(),
+                    None => {
+                        return Err(Error::from_kind(ErrorKind::BrokenModel(String::from(
+                            "Detail: broken tree - categories segment out of range",
+                        ))))
+                    }
+                };
+                node_categories.sort_unstable();
+                let node = match nodes.get(nid as usize) {
+                    Some(node) => *node,
+                    None => {
+                        return Err(Error::from_kind(ErrorKind::BrokenModel(format!(
+                            "Detail: broken tree - categorical split of unknown node {}",
+                            nid
+                        ))))
+                    }
+                };
+                nodes[nid as usize] = node.into_categorical(categories.len())?;
+                categories.push(node_categories);
+            }
+        }
+        RegTree::check_nodes(&nodes)?;
+
+        return Ok(RegTree::new(param, nodes, stats, categories));
+    }
+
+    pub fn flat(&self) -> &FlatTree {
+        return &self.flat;
+    }
+
+    /// Loss change of the split of the node at a position of the layout
+    pub fn loss_chg(&self, nid: usize) -> f32 {
+        return self.stats[nid].loss_chg;
+    }
+
+    /// Sum of the hessians of the rows reaching the node at a position of the layout, its cover
+    pub fn sum_hess(&self, nid: usize) -> f32 {
+        return self.stats[nid].sum_hess;
+    }
+
+    /// Mean value of the leaves under each node, weighted by their cover, in the order of the
+    /// layout
+    pub fn node_mean_values(&self) -> Vec<f32> {
+        let mut mean_values = vec![0f32; self.flat.num_nodes()];
+        self.fill_node_mean_value(0, &mut mean_values);
+        return mean_values;
     }
 
-    pub fn get_leaf_value(&self, feat: ArrayView1<f32>, root_id: usize) -> Result<f32> {
-        let leaf_node = self.nodes[self.get_leaf_index(feat, root_id)?];
-        return match leaf_node.leaf_or_split {
-            LeafOrSplit::LeafValue(leaf_value) => Ok(leaf_value),
-            LeafOrSplit::Split { .. } => {
-                Err(Error::from_kind(ErrorKind::BrokenModel(String::from("Detail: broken tree - is not leaf node"))))
+    fn fill_node_mean_value(&self, nid: usize, mean_values: &mut [f32]) -> f32 {
+        let result = match self.flat.node(nid) {
+            FlatNode::Leaf(leaf_value) => leaf_value,
+            FlatNode::Split { cleft, cright, .. }
+            | FlatNode::CategoricalSplit { cleft, cright, .. } => {
+                let mut result =
+                    self.fill_node_mean_value(cleft, mean_values) * self.stats[cleft].sum_hess;
+                result +=
+                    self.fill_node_mean_value(cright, mean_values) * self.stats[cright].sum_hess;
+                result / self.stats[nid].sum_hess
             }
         };
+        mean_values[nid] = result;
+        return result;
     }
+
+    /// Adds the SHAP values of the features of the row to `phi[..feat.len()]` and the expected
+    /// value of the tree to `phi[feat.len()]`, with the path-dependent TreeSHAP algorithm of
+    /// xgboost. `mean_values` are given by `node_mean_values`.
+    pub fn add_contributions(&self, feat: &[FeatureValue], mean_values: &[f32], phi: &mut [f32]) {
+        phi[feat.len()] += mean_values[0];
+        let max_depth = self.flat.depth() + 2;
+        let mut paths = vec![PathElement::default(); max_depth * (max_depth + 1) / 2];
+        self.tree_shap(feat, phi, 0, 0, &mut paths, 1f32, 1f32, None);
+    }
+
+    /// `parent_path` starts with the unique path of the parent node, the path of this node is
+    /// laid out after it
+    #[allow(clippy::too_many_arguments)]
+    fn tree_shap(
+        &self,
+        feat: &[FeatureValue],
+        phi: &mut [f32],
+        nid: usize,
+        unique_depth: usize,
+        parent_path: &mut [PathElement],
+        parent_zero_fraction: f32,
+        parent_one_fraction: f32,
+        parent_feature_index: Option<usize>,
+    ) {
+        let (parent, path) = parent_path.split_at_mut(unique_depth + 1);
+        path[..unique_depth + 1].copy_from_slice(parent);
+        extend_path(
+            path,
+            unique_depth,
+            parent_zero_fraction,
+            parent_one_fraction,
+            parent_feature_index,
+        );
+
+        let (split_index, cleft, cright) = match self.flat.node(nid) {
+            FlatNode::Leaf(leaf_value) => {
+                for i in 1..=unique_depth {
+                    let w = unwound_path_sum(path, unique_depth, i);
+                    let element = path[i];
+                    if let Some(fid) = element.feature_index {
+                        phi[fid] += w * (element.one_fraction - element.zero_fraction) * leaf_value;
+                    }
+                }
+                return;
+            }
+            FlatNode::Split {
+                split_index,
+                cleft,
+                cright,
+                ..
+            }
+            | FlatNode::CategoricalSplit {
+                split_index,
+                cleft,
+                cright,
+                ..
+            } => (split_index, cleft, cright),
+        };
+
+        let hot_index = self.flat.next(nid, feat);
+        let cold_index = if hot_index == cleft { cright } else { cleft };
+        let w = self.stats[nid].sum_hess;
+        let hot_zero_fraction = self.stats[hot_index].sum_hess / w;
+        let cold_zero_fraction = self.stats[cold_index].sum_hess / w;
+        let mut incoming_zero_fraction = 1f32;
+        let mut incoming_one_fraction = 1f32;
+
+        // a feature split on again is taken out of the path before being added back
+        let mut unique_depth = unique_depth;
+        if let Some(path_index) =
+            (0..=unique_depth).find(|i| path[*i].feature_index == Some(split_index))
+        {
+            incoming_zero_fraction = path[path_index].zero_fraction;
+            incoming_one_fraction = path[path_index].one_fraction;
+            unwind_path(path, unique_depth, path_index);
+            unique_depth -= 1;
+        }
+
+        self.tree_shap(
+            feat,
+            phi,
+            hot_index,
+            unique_depth + 1,
+            path,
+            hot_zero_fraction * incoming_zero_fraction,
+            incoming_one_fraction,
+            Some(split_index),
+        );
+        self.tree_shap(
+            feat,
+            phi,
+            cold_index,
+            unique_depth + 1,
+            path,
+            cold_zero_fraction * incoming_zero_fraction,
+            0f32,
+            Some(split_index),
+        );
+    }
+}
+
+/// Feature of the path followed by TreeSHAP, with the fractions of the paths going through it
+/// when the feature is missing (zero) and when it is present (one)
+#[derive(Clone, Copy, Default)]
+struct PathElement {
+    feature_index: Option<usize>,
+    zero_fraction: f32,
+    one_fraction: f32,
+    /// weight of the subsets of the path features
+    pweight: f32,
+}
+
+fn extend_path(
+    path: &mut [PathElement],
+    unique_depth: usize,
+    zero_fraction: f32,
+    one_fraction: f32,
+    feature_index: Option<usize>,
+) {
+    path[unique_depth] = PathElement {
+        feature_index,
+        zero_fraction,
+        one_fraction,
+        pweight: if unique_depth == 0 { 1f32 } else { 0f32 },
+    };
+    let depth = (unique_depth + 1) as f32;
+    for i in (0..unique_depth).rev() {
+        path[i + 1].pweight += one_fraction * path[i].pweight * (i + 1) as f32 / depth;
+        path[i].pweight = zero_fraction * path[i].pweight * (unique_depth - i) as f32 / depth;
+    }
+}
+
+/// Removes `path[path_index]` from the path, undoing `extend_path`
+fn unwind_path(path: &mut [PathElement], unique_depth: usize, path_index: usize) {
+    let one_fraction = path[path_index].one_fraction;
+    let zero_fraction = path[path_index].zero_fraction;
+    let depth = (unique_depth + 1) as f32;
+    let mut next_one_portion = path[unique_depth].pweight;
+    for i in (0..unique_depth).rev() {
+        if one_fraction != 0f32 {
+            let tmp = path[i].pweight;
+            path[i].pweight = next_one_portion * depth / ((i + 1) as f32 * one_fraction);
+            next_one_portion =
+                tmp - path[i].pweight * zero_fraction * (unique_depth - i) as f32 / depth;
+        } else {
+            path[i].pweight = path[i].pweight * depth / (zero_fraction * (unique_depth - i) as f32);
+        }
+    }
+    for i in path_index..unique_depth {
+        path[i].feature_index = path[i + 1].feature_index;
+        path[i].zero_fraction = path[i + 1].zero_fraction;
+        path[i].one_fraction = path[i + 1].one_fraction;
+    }
+}
+
+/// Total weight of the path once `path[path_index]` is removed, without changing it
+fn unwound_path_sum(path: &[PathElement], unique_depth: usize, path_index: usize) -> f32 {
+    let one_fraction = path[path_index].one_fraction;
+    let zero_fraction = path[path_index].zero_fraction;
+    let depth = (unique_depth + 1) as f32;
+    let mut next_one_portion = path[unique_depth].pweight;
+    let mut total = 0f32;
+    for i in (0..unique_depth).rev() {
+        if one_fraction != 0f32 {
+            let tmp = next_one_portion * depth / ((i + 1) as f32 * one_fraction);
+            total += tmp;
+            next_one_portion =
+                path[i].pweight - tmp * zero_fraction * ((unique_depth - i) as f32 / depth);
+        } else if zero_fraction != 0f32 {
+            total += (path[i].pweight / zero_fraction) / ((unique_depth - i) as f32 / depth);
+        }
+    }
+    return total;
 }
 
 impl Clone for RegTree {
     fn clone(&self) -> RegTree {
         return RegTree {
-            param: self.param.clone(),
-            nodes: self.nodes.clone(),
+            param: self.param,
             stats: self.stats.clone(),
+            flat: self.flat.clone(),
         };
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::errors::ErrorKind;
+    use crate::predictor::Predictor;
+    use crate::testing::{gbtree_model, TestNode, TestTree};
+
+    fn load_tree(nodes: Vec<TestNode>) -> Result<Predictor, ErrorKind> {
+        let bytes = gbtree_model("reg:linear", 0.5, 2, 1, &[TestTree::new(0, nodes)], None);
+        Predictor::read_from(&mut &bytes[..]).map_err(|error| error.0)
+    }
+
+    #[test]
+    fn test_read_broken_trees() {
+        let valid = vec![
+            TestNode::Split(0, 0.5, 1, 2, true),
+            TestNode::Leaf(1.0),
+            TestNode::Leaf(2.0),
+        ];
+        assert!(load_tree(valid).is_ok());
+
+        let out_of_range = vec![
+            TestNode::Split(0, 0.5, 1, 3, true),
+            TestNode::Leaf(1.0),
+            TestNode::Leaf(2.0),
+        ];
+        let cycle = vec![
+            TestNode::Split(0, 0.5, 1, 2, true),
+            TestNode::Leaf(1.0),
+            TestNode::Split(1, 0.5, 1, 0, true),
+        ];
+        for nodes in [out_of_range, cycle, vec![]] {
+            match load_tree(nodes) {
+                Err(ErrorKind::BrokenModel(_)) => {}
+                Err(kind) => panic!("unexpected error {:?}", kind),
+                Ok(_) => panic!("broken tree is loaded"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_read_truncated_model() {
+        let nodes = vec![TestNode::Leaf(1.0)];
+        let bytes = gbtree_model("reg:linear", 0.5, 2, 1, &[TestTree::new(0, nodes)], None);
+        for len in (0..bytes.len()).step_by(7) {
+            assert!(Predictor::read_from(&mut &bytes[..len]).is_err());
+        }
+    }
+}
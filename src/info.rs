@@ -0,0 +1,417 @@
+//! Summary of a model file: its format, booster and the shape of its trees.
+use std::collections::BTreeMap;
+use std::fmt;
+
+use byteorder::{ByteOrder, LE};
+use serde_json::{json, Value};
+
+use crate::errors::*;
+use crate::gbm::flat_tree::FlatNode;
+use crate::gbm::grad_booster::BoosterModel;
+use crate::predictor::Predictor;
+
+/// Format a model file was read from
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ModelFormat {
+    /// binary format of xgboost
+    Binary,
+    /// JSON format of xgboost
+    Json,
+    /// memory-mapped format written by `mapped::write_to`
+    Mapped,
+}
+
+impl ModelFormat {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ModelFormat::Binary => "binary",
+            ModelFormat::Json => "json",
+            ModelFormat::Mapped => "mapped",
+        }
+    }
+}
+
+/// Minimum, mean and maximum of the DART weights of the trees
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DartWeights {
+    pub min: f32,
+    pub mean: f32,
+    pub max: f32,
+}
+
+/// Description of a model
+#[derive(Clone, Debug, PartialEq)]
+pub struct ModelInfo {
+    pub format: ModelFormat,
+    /// version of xgboost for its formats, major and minor only in binary models saved by
+    /// xgboost 1.0 or later, or version of the mapped format. `None` when the file has none.
+    pub version: Option<Vec<u32>>,
+    /// `gbtree`, `gblinear` or `dart`
+    pub booster: &'static str,
+    /// name of the objective as written in the model
+    pub objective: String,
+    /// global bias, as a margin
+    pub base_score: f32,
+    pub num_feature: usize,
+    pub num_class: usize,
+    /// number of trees of each output group, empty for linear models
+    pub group_num_trees: Vec<usize>,
+    /// number of trees of each depth, the depth of a single leaf being 0. Depths are measured on
+    /// the nodes, as `max_depth` of the tree parameters is only set by old versions of xgboost.
+    pub depth_histogram: BTreeMap<usize, usize>,
+    /// number of trees of each number of leaves
+    pub leaf_histogram: BTreeMap<usize, usize>,
+    /// whether a prediction buffer was saved with the model
+    pub pbuffer: bool,
+    /// `None` unless the booster is DART and has trees
+    pub dart_weights: Option<DartWeights>,
+}
+
+/// Version of xgboost in the learner parameters of a binary model
+fn binary_version(bytes: &[u8]) -> Option<Vec<u32>> {
+    let offset = if bytes.starts_with(b"binf") { 4 } else { 0 };
+    // after the base score, the number of features and classes and two flags
+    let version = bytes.get(offset + 20..offset + 28)?;
+    match (LE::read_u32(version), LE::read_u32(&version[4..])) {
+        (0, _) => None,
+        (major, minor) => Some(vec![major, minor]),
+    }
+}
+
+fn json_version(bytes: &[u8]) -> Option<Vec<u32>> {
+    let model: Value = serde_json::from_slice(bytes).ok()?;
+    model
+        .get("version")?
+        .as_array()?
+        .iter()
+        .map(|part| part.as_u64().map(|part| part as u32))
+        .collect()
+}
+
+fn histogram<I: Iterator<Item = usize>>(values: I) -> BTreeMap<usize, usize> {
+    let mut histogram = BTreeMap::new();
+    for value in values {
+        *histogram.entry(value).or_insert(0) += 1;
+    }
+    histogram
+}
+
+fn dart_weights(weights: &[f32]) -> Option<DartWeights> {
+    if weights.is_empty() {
+        return None;
+    }
+    let sum: f64 = weights.iter().map(|weight| f64::from(*weight)).sum();
+    Some(DartWeights {
+        min: weights.iter().copied().fold(f32::INFINITY, f32::min),
+        mean: (sum / weights.len() as f64) as f32,
+        max: weights.iter().copied().fold(f32::NEG_INFINITY, f32::max),
+    })
+}
+
+/// JSON number of a `f32`, with the shortest representation of the `f32` rather than of its
+/// widening to `f64`
+fn float(value: f32) -> Value {
+    value
+        .to_string()
+        .parse::<f64>()
+        .map_or(Value::Null, Value::from)
+}
+
+impl ModelInfo {
+    /// Reads a model in any of the formats of `Predictor::read_any` and describes it
+    pub fn from_bytes(bytes: &[u8]) -> Result<ModelInfo> {
+        let (format, version, predictor) = if bytes.starts_with(&crate::mapped::MAGIC) {
+            // the header is checked while loading
+            let predictor = Predictor::from_bytes(bytes)?;
+            let version = LE::read_u32(&bytes[8..12]);
+            (ModelFormat::Mapped, Some(vec![version]), predictor)
+        } else if bytes.first() == Some(&b'{') {
+            (
+                ModelFormat::Json,
+                json_version(bytes),
+                Predictor::from_json(bytes)?,
+            )
+        } else {
+            (
+                ModelFormat::Binary,
+                binary_version(bytes),
+                Predictor::read_from(&mut &bytes[..])?,
+            )
+        };
+
+        // the tree shapes are the depth and number of leaves of each tree
+        let (booster, group_num_trees, tree_shapes, pbuffer, weights) = match predictor.booster() {
+            BoosterModel::Trees(gbtree) => {
+                let group_num_trees: Vec<usize> = (0..gbtree.num_output_group())
+                    .map(|gid| {
+                        gbtree
+                            .tree_info()
+                            .iter()
+                            .filter(|group| **group as usize == gid)
+                            .count()
+                    })
+                    .collect();
+                let tree_shapes: Vec<(usize, usize)> = gbtree
+                    .trees()
+                    .iter()
+                    .map(|tree| {
+                        let tree = tree.flat();
+                        let num_leaves = (0..tree.num_nodes())
+                            .filter(|nid| matches!(tree.node(*nid), FlatNode::Leaf(_)))
+                            .count();
+                        (tree.depth(), num_leaves)
+                    })
+                    .collect();
+                let pbuffer = predictor.saved_with_pbuffer() && gbtree.num_pbuffer() != 0;
+                let weights = gbtree.weight_drop();
+                let booster = if weights.is_some() { "dart" } else { "gbtree" };
+                (booster, group_num_trees, tree_shapes, pbuffer, weights)
+            }
+            BoosterModel::Linear(_) => ("gblinear", vec![], vec![], false, None),
+            BoosterModel::Mapped(mapped) if mapped.is_trees() => {
+                let weights = Some(mapped.weight_drop()).filter(|weights| !weights.is_empty());
+                let booster = if weights.is_some() { "dart" } else { "gbtree" };
+                let shapes = mapped.tree_shapes();
+                (booster, mapped.group_num_trees(), shapes, false, weights)
+            }
+            BoosterModel::Mapped(_) => ("gblinear", vec![], vec![], false, None),
+            BoosterModel::Compact(_) => {
+                return Err(Error::from_kind(ErrorKind::UnsupportedPredictionMethod(
+                    String::from("info"),
+                    String::from("Detail: compact models are not read from files"),
+                )))
+            }
+        };
+
+        Ok(ModelInfo {
+            format,
+            version,
+            booster,
+            objective: String::from(predictor.objective_name()),
+            // the margin of a base score of 0.5 is -0
+            base_score: predictor.base_score() + 0.0,
+            num_feature: predictor.model_num_feature(),
+            num_class: predictor.num_class(),
+            group_num_trees,
+            depth_histogram: histogram(tree_shapes.iter().map(|shape| shape.0)),
+            leaf_histogram: histogram(tree_shapes.iter().map(|shape| shape.1)),
+            pbuffer,
+            dart_weights: weights.and_then(dart_weights),
+        })
+    }
+
+    /// Total number of trees
+    pub fn num_trees(&self) -> usize {
+        self.group_num_trees.iter().sum()
+    }
+
+    /// JSON object of the description, histograms being lists of `{"depth": 3, "trees": 10}`
+    /// and `{"leaves": 8, "trees": 10}` objects
+    pub fn to_json(&self) -> Value {
+        let depth_histogram: Vec<Value> = self
+            .depth_histogram
+            .iter()
+            .map(|(depth, trees)| json!({"depth": depth, "trees": trees}))
+            .collect();
+        let leaf_histogram: Vec<Value> = self
+            .leaf_histogram
+            .iter()
+            .map(|(leaves, trees)| json!({"leaves": leaves, "trees": trees}))
+            .collect();
+        let dart_weights = self.dart_weights.map(|weights| {
+            json!({
+                "min": float(weights.min),
+                "mean": float(weights.mean),
+                "max": float(weights.max),
+            })
+        });
+        json!({
+            "format": self.format.name(),
+            "version": self.version,
+            "booster": self.booster,
+            "objective": self.objective,
+            "base_score": float(self.base_score),
+            "num_feature": self.num_feature,
+            "num_class": self.num_class,
+            "num_trees": self.num_trees(),
+            "group_num_trees": self.group_num_trees,
+            "depth_histogram": depth_histogram,
+            "leaf_histogram": leaf_histogram,
+            "pbuffer": self.pbuffer,
+            "dart_weights": dart_weights,
+        })
+    }
+}
+
+/// Description as `name: value` lines
+impl fmt::Display for ModelInfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let version = match &self.version {
+            Some(version) => {
+                let parts: Vec<String> = version.iter().map(|part| part.to_string()).collect();
+                parts.join(".")
+            }
+            None => String::from("unknown"),
+        };
+        writeln!(f, "format: {}", self.format.name())?;
+        writeln!(f, "version: {}", version)?;
+        writeln!(f, "booster: {}", self.booster)?;
+        writeln!(f, "objective: {}", self.objective)?;
+        writeln!(f, "base_score: {}", self.base_score)?;
+        writeln!(f, "num_feature: {}", self.num_feature)?;
+        writeln!(f, "num_class: {}", self.num_class)?;
+        writeln!(f, "num_trees: {}", self.num_trees())?;
+        for (gid, num_trees) in self.group_num_trees.iter().enumerate() {
+            writeln!(f, "  group {}: {} trees", gid, num_trees)?;
+        }
+        if !self.depth_histogram.is_empty() {
+            writeln!(f, "depths:")?;
+            for (depth, trees) in &self.depth_histogram {
+                writeln!(f, "  {}: {} trees", depth, trees)?;
+            }
+            writeln!(f, "leaves:")?;
+            for (leaves, trees) in &self.leaf_histogram {
+                writeln!(f, "  {}: {} trees", leaves, trees)?;
+            }
+        }
+        writeln!(f, "pbuffer: {}", if self.pbuffer { "yes" } else { "no" })?;
+        if let Some(weights) = self.dart_weights {
+            writeln!(
+                f,
+                "dart weights: min {}, mean {}, max {}",
+                weights.min, weights.mean, weights.max
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::predictor::Predictor;
+    use crate::testing::{gblinear_model, gbtree_model, TestNode, TestTree, JSON_MODEL};
+
+    use super::{DartWeights, ModelFormat, ModelInfo};
+
+    fn stump(group: i32) -> TestTree {
+        TestTree::new(
+            group,
+            vec![
+                TestNode::Split(0, 0.5, 1, 2, true),
+                TestNode::Leaf(-1.0),
+                TestNode::Leaf(1.0),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_json_model() {
+        let info = ModelInfo::from_bytes(JSON_MODEL.as_bytes()).unwrap();
+        assert_eq!(info.format, ModelFormat::Json);
+        assert_eq!(info.version, Some(vec![1, 7, 6]));
+        assert_eq!(
+            (info.booster, info.objective.as_str()),
+            ("gbtree", "binary:logistic")
+        );
+        assert_eq!((info.num_feature, info.num_class), (2, 0));
+        assert_eq!(info.group_num_trees, vec![1]);
+        assert_eq!(
+            info.depth_histogram.into_iter().collect::<Vec<_>>(),
+            [(2, 1)]
+        );
+        assert_eq!(
+            info.leaf_histogram.into_iter().collect::<Vec<_>>(),
+            [(3, 1)]
+        );
+        assert!(!info.pbuffer);
+        assert_eq!(info.dart_weights, None);
+    }
+
+    #[test]
+    fn test_binary_models() {
+        let trees = [
+            stump(0),
+            stump(1),
+            stump(0),
+            TestTree::new(1, vec![TestNode::Leaf(0.5)]),
+        ];
+        let bytes = gbtree_model(
+            "multi:softprob",
+            0.5,
+            3,
+            2,
+            &trees,
+            Some(&[1.0, 0.5, 0.25, 0.25]),
+        );
+        let info = ModelInfo::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            (info.format, info.version.clone()),
+            (ModelFormat::Binary, None)
+        );
+        assert_eq!(info.booster, "dart");
+        assert_eq!(info.group_num_trees, vec![2, 2]);
+        assert_eq!(
+            info.depth_histogram.iter().collect::<Vec<_>>(),
+            [(&0, &1), (&1, &3)]
+        );
+        assert_eq!(
+            info.dart_weights,
+            Some(DartWeights {
+                min: 0.25,
+                mean: 0.5,
+                max: 1.0,
+            })
+        );
+        assert_eq!(
+            info.to_json()["dart_weights"],
+            json!({"min": 0.25, "mean": 0.5, "max": 1.0})
+        );
+
+        // the mapped format keeps the trees and the weights
+        let predictor = Predictor::read_from(&mut &bytes[..]).unwrap();
+        let mut mapped = vec![];
+        crate::mapped::write_to(&predictor, &mut mapped).unwrap();
+        let mapped = ModelInfo::from_bytes(&mapped).unwrap();
+        assert_eq!(mapped.format, ModelFormat::Mapped);
+        assert_eq!(mapped.version, Some(vec![crate::mapped::VERSION]));
+        assert_eq!(
+            (
+                mapped.group_num_trees,
+                mapped.depth_histogram,
+                mapped.leaf_histogram
+            ),
+            (
+                info.group_num_trees,
+                info.depth_histogram,
+                info.leaf_histogram
+            )
+        );
+        assert_eq!(mapped.dart_weights, info.dart_weights);
+
+        // aliases are reported as named in the model, also once converted
+        let bytes = gblinear_model("reg:logistic", 0.5, 1, 1, &[2.0, 1.0]);
+        let info = ModelInfo::from_bytes(&bytes).unwrap();
+        assert_eq!(
+            (info.booster, info.objective.as_str()),
+            ("gblinear", "reg:logistic")
+        );
+        assert!(info.group_num_trees.is_empty() && info.depth_histogram.is_empty());
+        let predictor = Predictor::read_from(&mut &bytes[..]).unwrap();
+        let mapped = ModelInfo::from_bytes(&predictor.to_bytes().unwrap()).unwrap();
+        assert_eq!(mapped.objective, "reg:logistic");
+    }
+
+    #[test]
+    fn test_binary_version() {
+        let mut bytes = gbtree_model("reg:linear", 0.5, 1, 1, &[stump(0)], None);
+        // major and minor versions follow the learner flags
+        bytes[20..24].copy_from_slice(&1u32.to_le_bytes());
+        bytes[24..28].copy_from_slice(&6u32.to_le_bytes());
+        let info = ModelInfo::from_bytes(&bytes).unwrap();
+        assert_eq!(info.version, Some(vec![1, 6]));
+        assert_eq!(info.to_json()["version"], json!([1, 6]));
+        assert!(info.to_string().contains("version: 1.6\n"));
+    }
+}
@@ -0,0 +1,95 @@
+use serde_json::Value;
+
+use crate::errors::*;
+
+fn broken(message: String) -> Error {
+    Error::from_kind(ErrorKind::BrokenModel(message))
+}
+
+/// Accessors of the JSON model format. Scalar parameters are written by xgboost as strings,
+/// e.g. `"num_feature": "126"`, so numbers are accepted in both representations.
+pub trait JsonReader {
+    fn field(&self, key: &str) -> Result<&Value>;
+
+    fn str_field(&self, key: &str) -> Result<&str> {
+        return self
+            .field(key)?
+            .as_str()
+            .ok_or_else(|| broken(format!("field '{}' is not a string", key)));
+    }
+
+    fn f32_field(&self, key: &str) -> Result<f32> {
+        return as_f64(self.field(key)?)
+            .map(|value| value as f32)
+            .ok_or_else(|| broken(format!("field '{}' is not a number", key)));
+    }
+
+    fn usize_field(&self, key: &str) -> Result<usize> {
+        return as_f64(self.field(key)?)
+            .filter(|value| *value >= 0f64)
+            .map(|value| value as usize)
+            .ok_or_else(|| broken(format!("field '{}' is not a non negative integer", key)));
+    }
+
+    fn array_field(&self, key: &str) -> Result<&Vec<Value>> {
+        return self
+            .field(key)?
+            .as_array()
+            .ok_or_else(|| broken(format!("field '{}' is not an array", key)));
+    }
+
+    fn f32_vec_field(&self, key: &str) -> Result<Vec<f32>> {
+        return self
+            .array_field(key)?
+            .iter()
+            .map(|value| {
+                as_f64(value)
+                    .map(|value| value as f32)
+                    .ok_or_else(|| broken(format!("field '{}' holds a non number", key)))
+            })
+            .collect();
+    }
+
+    fn i64_vec_field(&self, key: &str) -> Result<Vec<i64>> {
+        return self
+            .array_field(key)?
+            .iter()
+            .map(|value| match value {
+                Value::Bool(flag) => Some(*flag as i64),
+                _ => as_f64(value).map(|value| value as i64),
+            })
+            .map(|value| {
+                value.ok_or_else(|| broken(format!("field '{}' holds a non integer", key)))
+            })
+            .collect();
+    }
+
+    fn string_vec_field(&self, key: &str) -> Result<Vec<String>> {
+        return self
+            .array_field(key)?
+            .iter()
+            .map(|value| {
+                value
+                    .as_str()
+                    .map(String::from)
+                    .ok_or_else(|| broken(format!("field '{}' holds a non string", key)))
+            })
+            .collect();
+    }
+}
+
+impl JsonReader for Value {
+    fn field(&self, key: &str) -> Result<&Value> {
+        return self
+            .get(key)
+            .ok_or_else(|| broken(format!("missing field '{}'", key)));
+    }
+}
+
+fn as_f64(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(text) => text.parse().ok(),
+        _ => None,
+    }
+}
@@ -1,4 +1,33 @@
+//! Prediction with xgboost models, without xgboost.
+//!
+//! Models in the binary or JSON format of xgboost are read into a [`Predictor`], which predicts
+//! batches of rows in parallel. The crate is usable as a Rust library; the Python extension
+//! module is built with the `python` feature and the C API with the `capi` feature.
+//!
+//! ```no_run
+//! use std::fs::File;
+//!
+//! use xgboost_predictor::ndarray::arr2;
+//! use xgboost_predictor::{FeatureValue, Predictor, Result};
+//!
+//! fn main() -> Result<()> {
+//!     let predictor = Predictor::read_any(&mut File::open("model.json")?)?;
+//!     println!(
+//!         "{} features, objective {}",
+//!         predictor.model_num_feature(),
+//!         predictor.objective_name()
+//!     );
+//!     // one row of predictions per input row, NaN values are missing
+//!     let feats = arr2(&[[1.0, FeatureValue::NAN]]);
+//!     let preds = predictor.predict_many(feats.view(), false, 0)?;
+//!     println!("{:?}", preds);
+//!     Ok(())
+//! }
+//! ```
 #![recursion_limit = "1024"]
+// error_chain emits a `cfg` unknown to recent compilers.
+#![allow(unexpected_cfgs)]
+#![allow(clippy::needless_return)]
 
 #[macro_use]
 extern crate error_chain;
@@ -8,6 +37,8 @@ pub mod errors {
         foreign_links {
             Io(::std::io::Error);
             Utf8Error(::std::string::FromUtf8Error);
+            ThreadPool(::rayon::ThreadPoolBuildError);
+            Arrow(::arrow::error::ArrowError) #[cfg(feature = "arrow")];
         }
         // Define additional `ErrorKind` variants.  Define custom responses with the
         // `description` and `display` calls.
@@ -24,9 +55,9 @@ pub mod errors {
                 description("Unsupported predict method")
                 display("That model does not support: '{}' method. {}", m, d)
             }
-            UnavailableDataIndex(m: String) {
+            UnavailableDataIndex(index: usize) {
                 description("Unavailable data")
-                display("Index out of range: '{}'", m)
+                display("Index out of range: 'cannot get feature value by index: {}'", index)
             }
             UnimplementedFunc(m: String) {
                 description("Unimplemented function")
@@ -36,60 +67,104 @@ pub mod errors {
                 description("Broken model")
                 display("Broken model. {}", m)
             }
+            UnsupportedDataType(t: String) {
+                description("Unsupported data type")
+                display("Unsupported data type: '{}'", t)
+            }
+            InvalidInputShape(expected: usize, actual: usize) {
+                description("Invalid input shape")
+                display("Feature shape mismatch, expected: {} features, got: {} columns", expected, actual)
+            }
+            FeatureMismatch(missing: Vec<String>, unexpected: Vec<String>) {
+                description("Feature mismatch")
+                display("Feature mismatch, missing: {:?}, unexpected: {:?}", missing, unexpected)
+            }
+            InvalidArgument(m: String) {
+                description("Invalid argument")
+                display("Invalid argument: {}", m)
+            }
         }
     }
 }
 
-mod functions;
-mod gbm;
-pub mod model_reader;
-pub mod predictor;
-mod wrapper;
+/// Type of the input feature values, `f64` with the `use_f64` feature so that values such as
+/// timestamps keep their precision. Split conditions, leaf values and predictions are `f32`, as
+/// xgboost stores them, and conditions are widened for comparisons.
+#[cfg(not(feature = "use_f64"))]
+pub type FeatureValue = f32;
+#[cfg(feature = "use_f64")]
+pub type FeatureValue = f64;
 
-use std::{fs, io};
+/// Rounds a value computed in the input precision to a prediction
+#[allow(clippy::unnecessary_cast)]
+#[inline]
+pub(crate) fn to_f32(value: FeatureValue) -> f32 {
+    value as f32
+}
 
-use pyo3::prelude::*;
-use pyo3::{exceptions, wrap_pyfunction, PyErr};
+/// Element type of input arrays, converted to `FeatureValue` block by block while predicting
+pub trait InputValue: Copy + Send + Sync {
+    fn to_feature_value(self) -> FeatureValue;
+}
 
-#[pyfunction]
-fn load_model(model_path: &str) -> PyResult<wrapper::PredictorWrapper> {
-    let mut model_file = match fs::File::open(model_path) {
-        Ok(file) => file,
-        Err(error) => match error.kind() {
-            io::ErrorKind::NotFound => {
-                return Err(PyErr::new::<exceptions::PyFileNotFoundError, _>(format!(
-                    "File not found: {}",
-                    model_path
-                )))
-            }
-            _ => {
-                return Err(PyErr::new::<exceptions::PyOSError, _>(format!(
-                    "Unexpected error, when open file: {}",
-                    error
-                )))
-            }
-        },
-    };
-    match predictor::Predictor::read_from::<fs::File>(&mut model_file) {
-        Ok(predictor) => Ok(wrapper::PredictorWrapper {
-            predictor: predictor,
-        }),
-        Err(error) => match error.kind() {
-            errors::ErrorKind::UnsupportedModelType(message)
-            | errors::ErrorKind::UnsupportedObjFunctionType(message) => {
-                Err(PyErr::new::<exceptions::PyValueError, _>(message.clone()))
-            }
-            _ => Err(PyErr::new::<exceptions::PyValueError, _>(format!(
-                "Unexpected error, when initializing model: {}",
-                error
-            ))),
-        },
+impl InputValue for f32 {
+    #[inline]
+    fn to_feature_value(self) -> FeatureValue {
+        FeatureValue::from(self)
     }
 }
 
-#[pymodule]
-fn xgboost_predictor(py: Python, m: &PyModule) -> PyResult<()> {
-    m.add_function(wrap_pyfunction!(load_model, m)?)?;
+impl InputValue for f64 {
+    #[allow(clippy::unnecessary_cast)]
+    #[inline]
+    fn to_feature_value(self) -> FeatureValue {
+        self as FeatureValue
+    }
+}
 
-    Ok(())
+impl InputValue for i32 {
+    #[inline]
+    fn to_feature_value(self) -> FeatureValue {
+        self as FeatureValue
+    }
 }
+
+/// Large values are rounded to the nearest representable value, as xgboost does
+impl InputValue for i64 {
+    #[inline]
+    fn to_feature_value(self) -> FeatureValue {
+        self as FeatureValue
+    }
+}
+
+pub mod c_export;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod codegen;
+pub mod dump;
+#[cfg(feature = "python")]
+mod estimators;
+#[cfg(feature = "python")]
+mod exceptions;
+mod functions;
+mod gbm;
+pub mod info;
+mod json_reader;
+pub mod mapped;
+pub mod model_reader;
+pub mod predictor;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "arrow")]
+pub mod record_batch;
+mod source_gen;
+#[cfg(any(test, feature = "testing"))]
+#[doc(hidden)]
+pub mod testing;
+#[cfg(feature = "python")]
+mod wrapper;
+
+pub use errors::{Error, ErrorKind, Result};
+pub use functions::FunctionType;
+pub use ndarray;
+pub use predictor::{FeatureType, Predictor, ShapePolicy};
@@ -0,0 +1,998 @@
+//! Model format read in place from a memory map.
+//!
+//! The file starts with a 64 byte header followed by a table of sections, each section being a
+//! little-endian array aligned to 64 bytes from the start of the file. Loading maps the file,
+//! checks the header and the checksums and validates the trees, then predicts straight from
+//! the mapped pages: nothing is decoded nor copied, so processes loading the same file share
+//! its pages.
+//!
+//! | offset | field                                               |
+//! |--------|-----------------------------------------------------|
+//! | 0      | magic `XGBPMAP\0`                                   |
+//! | 8      | version, u32                                        |
+//! | 12     | booster: 0 trees, 1 linear                          |
+//! | 16     | objective                                           |
+//! | 20     | base score as a margin, f32                         |
+//! | 24     | number of features, classes, output groups, trees   |
+//! | 40     | length of the rest of the file, u64                 |
+//! | 48     | CRC-32 of the rest of the file                      |
+//! | 52     | CRC-32 of the first 52 bytes                        |
+//! | 64     | offset and length in bytes of each section, u64     |
+//!
+//! Trees loaded from xgboost are written with the ids and statistics of their nodes, which
+//! prediction from the map ignores, so that `decode` gives back the same trees.
+use std::fs;
+use std::io::Write;
+use std::mem;
+use std::path::Path;
+use std::slice;
+
+use byteorder::{ByteOrder, LE};
+use memmap2::Mmap;
+use ndarray::ArrayView2;
+
+use crate::errors::*;
+use crate::gbm::flat_tree::{is_chosen_category, FlatNode, FlatTree};
+use crate::gbm::gblinear::GBLinear;
+use crate::gbm::gbtree::GBTree;
+use crate::gbm::grad_booster::{BoosterModel, GradBooster};
+use crate::gbm::regtree::RegTree;
+use crate::predictor::Predictor;
+use crate::{to_f32, FeatureValue};
+
+pub const MAGIC: [u8; 8] = *b"XGBPMAP\0";
+pub const VERSION: u32 = 2;
+
+const HEADER_LEN: usize = 64;
+const ALIGN: usize = 64;
+
+const BOOSTER_TREES: u32 = 0;
+const BOOSTER_LINEAR: u32 = 1;
+
+/// Codes of the objectives in the header
+const OBJECTIVES: [&str; 8] = [
+    "rank:pairwise",
+    "binary:logistic",
+    "binary:logitraw",
+    "multi:softmax",
+    "multi:softprob",
+    "reg:linear",
+    "reg:squarederror",
+    "reg:logistic",
+];
+
+/// nodes of all trees, tree by tree, as `Node`
+const NODES: usize = 0;
+/// first node of each tree and total number of nodes, u32
+const TREE_OFFSETS: usize = 1;
+/// output group of each tree, u32
+const TREE_INFO: usize = 2;
+/// DART weights of the trees, f32, empty for other models
+const WEIGHT_DROP: usize = 3;
+/// begin and end in `CATEGORIES` of the categories of each categorical split, u32
+const CATEGORY_RANGES: usize = 4;
+/// sorted categories going right at categorical splits, u32
+const CATEGORIES: usize = 5;
+/// weights of linear models, feature by feature then bias, f32
+const LINEAR_WEIGHTS: usize = 6;
+/// feature names separated by NUL bytes, empty when the model has no names
+const FEATURE_NAMES: usize = 7;
+/// id of each node in the model it was loaded from, u32, empty for compact trees
+const NODE_IDS: usize = 8;
+/// loss change of the split of each node, f32, empty for compact trees
+const LOSS_CHANGES: usize = 9;
+/// sum of the hessians of each node, f32, empty for compact trees
+const SUM_HESSIANS: usize = 10;
+const NUM_SECTIONS: usize = 11;
+/// size of the elements of each section
+const ELEMENT_SIZES: [usize; NUM_SECTIONS] = [16, 4, 4, 4, 4, 4, 4, 1, 4, 4, 4];
+
+/// the node is a leaf, `value` is the leaf value
+const LEAF: u32 = 1;
+/// missing values go to the left child
+const DEFAULT_LEFT: u32 = 2;
+/// `value` holds the bits of the index of the split's range in `CATEGORY_RANGES`
+const CATEGORICAL: u32 = 4;
+
+/// Node of a tree, children are indices in the tree and the right child follows the left one
+#[repr(C)]
+#[derive(Clone, Copy)]
+struct Node {
+    split_index: u32,
+    /// split condition or leaf value
+    value: f32,
+    left: u32,
+    flags: u32,
+}
+
+/// Types read in place from the mapped bytes
+///
+/// # Safety
+///
+/// Any bit pattern must be a valid value and the type must have no padding
+unsafe trait Plain: Copy {}
+unsafe impl Plain for u8 {}
+unsafe impl Plain for u32 {}
+unsafe impl Plain for f32 {}
+unsafe impl Plain for Node {}
+
+fn as_bytes<T: Plain>(values: &[T]) -> &[u8] {
+    // a slice of plain values is readable as bytes
+    unsafe { slice::from_raw_parts(values.as_ptr() as *const u8, mem::size_of_val(values)) }
+}
+
+fn broken(detail: &str) -> Error {
+    Error::from_kind(ErrorKind::BrokenModel(format!("Detail: {}", detail)))
+}
+
+struct Header {
+    booster: u32,
+    objective: u32,
+    base_score: f32,
+    num_feature: u32,
+    num_class: u32,
+    num_output_group: u32,
+    num_trees: u32,
+    payload_len: u64,
+    payload_crc: u32,
+}
+
+impl Header {
+    fn to_bytes(&self) -> [u8; HEADER_LEN] {
+        let mut bytes = [0u8; HEADER_LEN];
+        bytes[..8].copy_from_slice(&MAGIC);
+        LE::write_u32(&mut bytes[8..], VERSION);
+        LE::write_u32(&mut bytes[12..], self.booster);
+        LE::write_u32(&mut bytes[16..], self.objective);
+        LE::write_f32(&mut bytes[20..], self.base_score);
+        LE::write_u32(&mut bytes[24..], self.num_feature);
+        LE::write_u32(&mut bytes[28..], self.num_class);
+        LE::write_u32(&mut bytes[32..], self.num_output_group);
+        LE::write_u32(&mut bytes[36..], self.num_trees);
+        LE::write_u64(&mut bytes[40..], self.payload_len);
+        LE::write_u32(&mut bytes[48..], self.payload_crc);
+        let header_crc = crc32fast::hash(&bytes[..52]);
+        LE::write_u32(&mut bytes[52..], header_crc);
+        bytes
+    }
+
+    fn read_from(bytes: &[u8]) -> Result<Header> {
+        if bytes.len() < HEADER_LEN || bytes[..8] != MAGIC {
+            return Err(broken("not a memory-mapped model"));
+        }
+        let version = LE::read_u32(&bytes[8..]);
+        if version != VERSION {
+            return Err(Error::from_kind(ErrorKind::UnsupportedModelType(format!(
+                "memory-mapped model version {}",
+                version
+            ))));
+        }
+        if crc32fast::hash(&bytes[..52]) != LE::read_u32(&bytes[52..]) {
+            return Err(broken("header checksum mismatch"));
+        }
+        Ok(Header {
+            booster: LE::read_u32(&bytes[12..]),
+            objective: LE::read_u32(&bytes[16..]),
+            base_score: LE::read_f32(&bytes[20..]),
+            num_feature: LE::read_u32(&bytes[24..]),
+            num_class: LE::read_u32(&bytes[28..]),
+            num_output_group: LE::read_u32(&bytes[32..]),
+            num_trees: LE::read_u32(&bytes[36..]),
+            payload_len: LE::read_u64(&bytes[40..]),
+            payload_crc: LE::read_u32(&bytes[48..]),
+        })
+    }
+}
+
+/// Sections of a tree model, from the nodes of each tree and the categories of its categorical
+/// splits
+fn tree_sections<'a, N, C>(
+    num_trees: usize,
+    tree_nodes: N,
+    tree_categories: C,
+    sections: &mut [Vec<u8>],
+) -> Result<()>
+where
+    N: Fn(usize) -> Vec<FlatNode>,
+    C: Fn(usize, usize) -> &'a [u32],
+{
+    let mut nodes: Vec<Node> = vec![];
+    let mut tree_offsets: Vec<u32> = vec![0];
+    let mut category_ranges: Vec<u32> = vec![];
+    let mut categories: Vec<u32> = vec![];
+    for tid in 0..num_trees {
+        for node in tree_nodes(tid) {
+            nodes.push(match node {
+                FlatNode::Leaf(value) => Node {
+                    split_index: 0,
+                    value,
+                    left: 0,
+                    flags: LEAF,
+                },
+                FlatNode::Split {
+                    cleft,
+                    split_index,
+                    split_cond,
+                    default_left,
+                    ..
+                } => Node {
+                    split_index: split_index as u32,
+                    value: split_cond,
+                    left: cleft as u32,
+                    flags: if default_left { DEFAULT_LEFT } else { 0 },
+                },
+                FlatNode::CategoricalSplit {
+                    cleft,
+                    split_index,
+                    default_left,
+                    categories: set,
+                    ..
+                } => {
+                    let range = (category_ranges.len() / 2) as u32;
+                    category_ranges.push(categories.len() as u32);
+                    categories.extend_from_slice(tree_categories(tid, set));
+                    category_ranges.push(categories.len() as u32);
+                    Node {
+                        split_index: split_index as u32,
+                        value: f32::from_bits(range),
+                        left: cleft as u32,
+                        flags: CATEGORICAL | if default_left { DEFAULT_LEFT } else { 0 },
+                    }
+                }
+            });
+        }
+        if nodes.len() > u32::MAX as usize || categories.len() > u32::MAX as usize {
+            return Err(Error::from_kind(ErrorKind::UnsupportedPredictionMethod(
+                String::from("mapped::write_to"),
+                String::from("Detail: more than 2^32 nodes or categories"),
+            )));
+        }
+        tree_offsets.push(nodes.len() as u32);
+    }
+    sections[NODES] = as_bytes(&nodes).to_vec();
+    sections[TREE_OFFSETS] = as_bytes(&tree_offsets).to_vec();
+    sections[CATEGORY_RANGES] = as_bytes(&category_ranges).to_vec();
+    sections[CATEGORIES] = as_bytes(&categories).to_vec();
+    Ok(())
+}
+
+/// Writes the model in the memory-mapped format. Compact models are written with the split
+/// conditions of their bounds and without node statistics.
+pub fn write_to<W: Write>(predictor: &Predictor, writer: &mut W) -> Result<()> {
+    if cfg!(target_endian = "big") {
+        return Err(Error::from_kind(ErrorKind::UnimplementedFunc(
+            String::from("memory-mapped models are little-endian"),
+        )));
+    }
+    let mut sections: Vec<Vec<u8>> = vec![vec![]; NUM_SECTIONS];
+    let (booster, num_output_group, num_trees) = match predictor.booster() {
+        BoosterModel::Trees(gbtree) => {
+            let trees: Vec<&FlatTree> = gbtree.trees().iter().map(|tree| tree.flat()).collect();
+            tree_sections(
+                trees.len(),
+                |tid| {
+                    (0..trees[tid].num_nodes())
+                        .map(|nid| trees[tid].node(nid))
+                        .collect()
+                },
+                |tid, set| trees[tid].categories(set),
+                &mut sections,
+            )?;
+            let tree_info: Vec<u32> = gbtree.tree_info().iter().map(|gid| *gid as u32).collect();
+            sections[TREE_INFO] = as_bytes(&tree_info).to_vec();
+            if let Some(weight_drop) = gbtree.weight_drop() {
+                sections[WEIGHT_DROP] = as_bytes(weight_drop).to_vec();
+            }
+            let (mut node_ids, mut loss_changes, mut sum_hessians) = (vec![], vec![], vec![]);
+            for tree in gbtree.trees() {
+                for nid in 0..tree.flat().num_nodes() {
+                    node_ids.push(tree.flat().node_id(nid) as u32);
+                    loss_changes.push(tree.loss_chg(nid));
+                    sum_hessians.push(tree.sum_hess(nid));
+                }
+            }
+            sections[NODE_IDS] = as_bytes(&node_ids).to_vec();
+            sections[LOSS_CHANGES] = as_bytes(&loss_changes).to_vec();
+            sections[SUM_HESSIANS] = as_bytes(&sum_hessians).to_vec();
+            (BOOSTER_TREES, gbtree.num_output_group(), trees.len())
+        }
+        BoosterModel::Compact(compact) => {
+            // compact trees have no categorical splits
+            tree_sections(
+                compact.num_trees(),
+                |tid| compact.flat_nodes(tid),
+                |_, _| &[],
+                &mut sections,
+            )?;
+            let tree_info: Vec<u32> = compact.tree_info().iter().map(|gid| *gid as u32).collect();
+            sections[TREE_INFO] = as_bytes(&tree_info).to_vec();
+            if let Some(weight_drop) = compact.weight_drop() {
+                sections[WEIGHT_DROP] = as_bytes(weight_drop).to_vec();
+            }
+            (
+                BOOSTER_TREES,
+                compact.num_output_group(),
+                compact.num_trees(),
+            )
+        }
+        BoosterModel::Linear(gblinear) => {
+            let num_group = gblinear.num_output_group();
+            // the bias follows the weights of the features
+            let weights: Vec<f32> = (0..=gblinear.num_feature())
+                .flat_map(|fid| (0..num_group).map(move |gid| gblinear.weight(fid, gid)))
+                .collect();
+            sections[LINEAR_WEIGHTS] = as_bytes(&weights).to_vec();
+            (BOOSTER_LINEAR, num_group, 0)
+        }
+        BoosterModel::Mapped(mapped) => {
+            // the feature names are taken from the predictor, they may have been replaced
+            for (id, section) in sections.iter_mut().enumerate() {
+                if id != FEATURE_NAMES {
+                    *section = mapped.section::<u8>(id).to_vec();
+                }
+            }
+            (mapped.booster, mapped.num_output_group, mapped.num_trees())
+        }
+    };
+    if let Some(names) = predictor.feature_names() {
+        sections[FEATURE_NAMES] = names.join("\0").into_bytes();
+    }
+
+    // the table of sections, then the sections aligned from the start of the file
+    let mut payload = vec![0u8; NUM_SECTIONS * 16];
+    for (id, section) in sections.iter().enumerate() {
+        let padding = (ALIGN - (HEADER_LEN + payload.len()) % ALIGN) % ALIGN;
+        payload.resize(payload.len() + padding, 0);
+        let offset = HEADER_LEN + payload.len();
+        LE::write_u64(&mut payload[id * 16..], offset as u64);
+        LE::write_u64(&mut payload[id * 16 + 8..], section.len() as u64);
+        payload.extend_from_slice(section);
+    }
+    let objective = OBJECTIVES
+        .iter()
+        .position(|objective| *objective == predictor.objective_name())
+        .ok_or_else(|| {
+            Error::from_kind(ErrorKind::UnsupportedObjFunctionType(String::from(
+                predictor.objective_name(),
+            )))
+        })? as u32;
+    let header = Header {
+        booster,
+        objective,
+        base_score: predictor.base_score(),
+        num_feature: predictor.model_num_feature() as u32,
+        num_class: predictor.num_class() as u32,
+        num_output_group: num_output_group as u32,
+        num_trees: num_trees as u32,
+        payload_len: payload.len() as u64,
+        payload_crc: crc32fast::hash(&payload),
+    };
+    writer.write_all(&header.to_bytes())?;
+    writer.write_all(&payload)?;
+    Ok(())
+}
+
+/// Writes the model in the memory-mapped format to the file at `path`
+pub fn save<P: AsRef<Path>>(predictor: &Predictor, path: P) -> Result<()> {
+    let mut writer = std::io::BufWriter::new(fs::File::create(path)?);
+    write_to(predictor, &mut writer)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Bytes of a model, aligned to at least 8 bytes
+enum Storage {
+    Mapped(Mmap),
+    Owned(Vec<u64>, usize),
+}
+
+impl Storage {
+    fn bytes(&self) -> &[u8] {
+        match self {
+            Storage::Mapped(mmap) => mmap,
+            Storage::Owned(words, len) => unsafe {
+                slice::from_raw_parts(words.as_ptr() as *const u8, *len)
+            },
+        }
+    }
+}
+
+/// Booster predicting from the sections of a mapped model
+pub(crate) struct MappedBooster {
+    storage: Storage,
+    /// offset and length in bytes of each section, checked against the file and the size of
+    /// the elements of the section
+    sections: [(usize, usize); NUM_SECTIONS],
+    booster: u32,
+    num_feature: usize,
+    num_output_group: usize,
+    /// largest feature index tested by each tree, plus one
+    tree_num_columns: Vec<usize>,
+    /// indices of the trees of each output group
+    group_trees: Vec<Vec<u32>>,
+}
+
+impl MappedBooster {
+    fn num_trees(&self) -> usize {
+        self.tree_num_columns.len()
+    }
+
+    fn section<T: Plain>(&self, id: usize) -> &[T] {
+        let (offset, len) = self.sections[id];
+        let bytes = &self.storage.bytes()[offset..offset + len];
+        // sections are aligned and hold whole elements, as checked when loading
+        unsafe { slice::from_raw_parts(bytes.as_ptr() as *const T, len / mem::size_of::<T>()) }
+    }
+
+    /// Checks the structure of the trees, so that traversal stays within the sections and
+    /// ends at a leaf, and gathers the trees of each group
+    fn validate_trees(&mut self, num_trees: usize) -> Result<()> {
+        let nodes: &[Node] = self.section(NODES);
+        let tree_offsets: &[u32] = self.section(TREE_OFFSETS);
+        let tree_info: &[u32] = self.section(TREE_INFO);
+        let weight_drop: &[f32] = self.section(WEIGHT_DROP);
+        let category_ranges: &[u32] = self.section(CATEGORY_RANGES);
+        let categories: &[u32] = self.section(CATEGORIES);
+
+        if tree_offsets.len() != num_trees + 1
+            || tree_offsets[0] != 0
+            || tree_offsets[num_trees] as usize != nodes.len()
+            || tree_offsets.windows(2).any(|pair| pair[0] >= pair[1])
+        {
+            return Err(broken("invalid tree offsets"));
+        }
+        if tree_info.len() != num_trees
+            || tree_info
+                .iter()
+                .any(|gid| *gid as usize >= self.num_output_group)
+        {
+            return Err(broken("invalid output groups of the trees"));
+        }
+        if !weight_drop.is_empty() && weight_drop.len() != num_trees {
+            return Err(broken("DART weights do not match the trees"));
+        }
+        if category_ranges.len() % 2 == 1
+            || category_ranges.chunks(2).any(|range| {
+                range[0] > range[1]
+                    || range[1] as usize > categories.len()
+                    || categories[range[0] as usize..range[1] as usize]
+                        .windows(2)
+                        .any(|pair| pair[0] >= pair[1])
+            })
+        {
+            return Err(broken("invalid categories"));
+        }
+        let num_node_ids = self.section::<u32>(NODE_IDS).len();
+        if (num_node_ids != 0 && num_node_ids != nodes.len())
+            || self.section::<f32>(LOSS_CHANGES).len() != num_node_ids
+            || self.section::<f32>(SUM_HESSIANS).len() != num_node_ids
+        {
+            return Err(broken("node statistics do not match the nodes"));
+        }
+
+        let mut tree_num_columns = Vec::with_capacity(num_trees);
+        for offsets in tree_offsets.windows(2) {
+            let tree = &nodes[offsets[0] as usize..offsets[1] as usize];
+            let mut num_columns = 0;
+            for (nid, node) in tree.iter().enumerate() {
+                if node.flags & LEAF != 0 {
+                    continue;
+                }
+                // children follow their parent, so that traversal always moves forward
+                if (node.left as usize) <= nid || node.left as usize + 1 >= tree.len() {
+                    return Err(broken("invalid children of a split"));
+                }
+                if node.flags & CATEGORICAL != 0
+                    && node.value.to_bits() as usize >= category_ranges.len() / 2
+                {
+                    return Err(broken("invalid categories of a split"));
+                }
+                num_columns = num_columns.max(node.split_index as usize + 1);
+            }
+            tree_num_columns.push(num_columns);
+        }
+        let group_trees = (0..self.num_output_group as u32)
+            .map(|gid| {
+                (0..num_trees as u32)
+                    .filter(|tid| tree_info[*tid as usize] == gid)
+                    .collect()
+            })
+            .collect();
+        self.tree_num_columns = tree_num_columns;
+        self.group_trees = group_trees;
+        Ok(())
+    }
+
+    /// Whether the model holds trees rather than linear weights
+    pub(crate) fn is_trees(&self) -> bool {
+        self.booster == BOOSTER_TREES
+    }
+
+    /// DART weights of the trees, empty for other models
+    pub(crate) fn weight_drop(&self) -> &[f32] {
+        self.section(WEIGHT_DROP)
+    }
+
+    /// Number of trees of each output group
+    pub(crate) fn group_num_trees(&self) -> Vec<usize> {
+        self.group_trees.iter().map(|trees| trees.len()).collect()
+    }
+
+    /// Depth and number of leaves of each tree
+    pub(crate) fn tree_shapes(&self) -> Vec<(usize, usize)> {
+        let nodes: &[Node] = self.section(NODES);
+        let tree_offsets: &[u32] = self.section(TREE_OFFSETS);
+        (0..self.num_trees())
+            .map(|tid| {
+                let tree = &nodes[tree_offsets[tid] as usize..tree_offsets[tid + 1] as usize];
+                // children follow their parent
+                let mut depths = vec![0usize; tree.len()];
+                let (mut depth, mut num_leaves) = (0, 0);
+                for (nid, node) in tree.iter().enumerate() {
+                    if node.flags & LEAF != 0 {
+                        depth = depth.max(depths[nid]);
+                        num_leaves += 1;
+                    } else {
+                        depths[node.left as usize] = depths[nid] + 1;
+                        depths[node.left as usize + 1] = depths[nid] + 1;
+                    }
+                }
+                (depth, num_leaves)
+            })
+            .collect()
+    }
+
+    /// Trees or weights decoded into the boosters of the models loaded from xgboost, `None` for
+    /// compact trees, which were written without the ids and statistics of their nodes
+    fn decode(&self) -> Result<Option<Box<dyn GradBooster + Send + Sync>>> {
+        if self.booster == BOOSTER_LINEAR {
+            let weights = self.section::<f32>(LINEAR_WEIGHTS).to_vec();
+            let gblinear = GBLinear::new(self.num_feature, self.num_output_group, weights);
+            return Ok(Some(Box::new(gblinear)));
+        }
+        let nodes: &[Node] = self.section(NODES);
+        let node_ids: &[u32] = self.section(NODE_IDS);
+        if node_ids.len() != nodes.len() {
+            return Ok(None);
+        }
+        let tree_offsets: &[u32] = self.section(TREE_OFFSETS);
+        let category_ranges: &[u32] = self.section(CATEGORY_RANGES);
+        let all_categories: &[u32] = self.section(CATEGORIES);
+        let trees = (0..self.num_trees())
+            .map(|tid| {
+                let (begin, end) = (tree_offsets[tid] as usize, tree_offsets[tid + 1] as usize);
+                let mut categories: Vec<Vec<u32>> = vec![];
+                let flat_nodes: Vec<FlatNode> = nodes[begin..end]
+                    .iter()
+                    .map(|node| {
+                        let (cleft, default_left) =
+                            (node.left as usize, node.flags & DEFAULT_LEFT != 0);
+                        if node.flags & LEAF != 0 {
+                            FlatNode::Leaf(node.value)
+                        } else if node.flags & CATEGORICAL != 0 {
+                            let range = node.value.to_bits() as usize * 2;
+                            let (first, last) =
+                                (category_ranges[range], category_ranges[range + 1]);
+                            categories.push(all_categories[first as usize..last as usize].to_vec());
+                            FlatNode::CategoricalSplit {
+                                cleft,
+                                cright: cleft + 1,
+                                split_index: node.split_index as usize,
+                                default_left,
+                                categories: categories.len() - 1,
+                            }
+                        } else {
+                            FlatNode::Split {
+                                cleft,
+                                cright: cleft + 1,
+                                split_index: node.split_index as usize,
+                                split_cond: node.value,
+                                default_left,
+                            }
+                        }
+                    })
+                    .collect();
+                RegTree::from_layout(
+                    self.num_feature,
+                    &flat_nodes,
+                    &node_ids[begin..end],
+                    &self.section::<f32>(LOSS_CHANGES)[begin..end],
+                    &self.section::<f32>(SUM_HESSIANS)[begin..end],
+                    categories,
+                )
+            })
+            .collect();
+        let tree_info = self
+            .section::<u32>(TREE_INFO)
+            .iter()
+            .map(|gid| *gid as i32)
+            .collect();
+        let weight_drop = Some(self.weight_drop().to_vec()).filter(|weights| !weights.is_empty());
+        let gbtree = GBTree::from_trees(
+            trees,
+            tree_info,
+            weight_drop,
+            self.num_feature,
+            self.num_output_group,
+        )?;
+        Ok(Some(Box::new(gbtree)))
+    }
+
+    fn leaf_value(&self, tree: &[Node], feat: &[FeatureValue]) -> f32 {
+        let category_ranges: &[u32] = self.section(CATEGORY_RANGES);
+        let categories: &[u32] = self.section(CATEGORIES);
+        let mut node = tree[0];
+        while node.flags & LEAF == 0 {
+            let fvalue = feat[node.split_index as usize];
+            let go_left = if fvalue.is_nan() {
+                node.flags & DEFAULT_LEFT != 0
+            } else if node.flags & CATEGORICAL != 0 {
+                let range = node.value.to_bits() as usize * 2;
+                let (begin, end) = (category_ranges[range], category_ranges[range + 1]);
+                !is_chosen_category(&categories[begin as usize..end as usize], fvalue)
+            } else {
+                fvalue < FeatureValue::from(node.value)
+            };
+            node = tree[node.left as usize + !go_left as usize];
+        }
+        node.value
+    }
+
+    fn predict_trees(
+        &self,
+        feats: ArrayView2<'_, FeatureValue>,
+        base_score: f32,
+        ntree_limit: usize,
+    ) -> Result<Vec<Vec<f32>>> {
+        let limited_group_trees = |gid: usize| {
+            let trees = &self.group_trees[gid];
+            let limit = if ntree_limit == 0 {
+                trees.len()
+            } else {
+                ntree_limit.min(trees.len())
+            };
+            &trees[..limit]
+        };
+        let num_columns = feats.ncols();
+        for gid in 0..self.num_output_group {
+            for tid in limited_group_trees(gid) {
+                let tree_num_columns = self.tree_num_columns[*tid as usize];
+                if tree_num_columns > num_columns {
+                    return Err(Error::from_kind(ErrorKind::UnavailableDataIndex(
+                        tree_num_columns - 1,
+                    )));
+                }
+            }
+        }
+
+        let nodes: &[Node] = self.section(NODES);
+        let tree_offsets: &[u32] = self.section(TREE_OFFSETS);
+        let weight_drop: &[f32] = self.section(WEIGHT_DROP);
+        let feats = feats.as_standard_layout();
+        // margins are summed in the same order as `GBTree`
+        Ok(feats
+            .outer_iter()
+            .map(|feat| {
+                let feat = feat.to_slice().unwrap();
+                (0..self.num_output_group)
+                    .map(|gid| {
+                        let mut margin = 0f32;
+                        for tid in limited_group_trees(gid) {
+                            let tid = *tid as usize;
+                            let tree =
+                                &nodes[tree_offsets[tid] as usize..tree_offsets[tid + 1] as usize];
+                            let weight = weight_drop.get(tid).copied().unwrap_or(1f32);
+                            margin += weight * self.leaf_value(tree, feat);
+                        }
+                        base_score + margin
+                    })
+                    .collect()
+            })
+            .collect())
+    }
+
+    /// Same computations as `GBLinear`
+    fn predict_linear(
+        &self,
+        feats: ArrayView2<'_, FeatureValue>,
+        base_score: f32,
+    ) -> Vec<Vec<f32>> {
+        let weights: &[f32] = self.section(LINEAR_WEIGHTS);
+        let num_group = self.num_output_group;
+        feats
+            .outer_iter()
+            .map(|feat| {
+                (0..num_group)
+                    .map(|gid| {
+                        let bias = weights[self.num_feature * num_group + gid] + base_score;
+                        to_f32(
+                            feat.iter()
+                                .zip(weights[gid..].iter().step_by(num_group))
+                                .take(self.num_feature)
+                                .filter(|(fvalue, _)| !fvalue.is_nan())
+                                .map(|(fvalue, weight)| fvalue * FeatureValue::from(*weight))
+                                .sum::<FeatureValue>(),
+                        ) + bias
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+}
+
+impl GradBooster for MappedBooster {
+    fn num_output_group(&self) -> usize {
+        self.num_output_group
+    }
+
+    fn model(&self) -> BoosterModel<'_> {
+        BoosterModel::Mapped(self)
+    }
+
+    fn predict_many(
+        &self,
+        feats: ArrayView2<'_, FeatureValue>,
+        base_score: f32,
+        ntree_limit: usize,
+    ) -> Result<Vec<Vec<f32>>> {
+        if self.booster == BOOSTER_TREES {
+            self.predict_trees(feats, base_score, ntree_limit)
+        } else {
+            Ok(self.predict_linear(feats, base_score))
+        }
+    }
+
+    fn predict_leaf(
+        &self,
+        _feats: ArrayView2<'_, FeatureValue>,
+        _ntree_limit: usize,
+    ) -> Result<Vec<Vec<u32>>> {
+        Err(Error::from_kind(ErrorKind::UnsupportedPredictionMethod(
+            String::from("predict_leaf"),
+            String::from("Detail: mapped models keep no node ids"),
+        )))
+    }
+
+    fn predict_contribs(
+        &self,
+        _feats: ArrayView2<'_, FeatureValue>,
+        _base_score: f32,
+        _ntree_limit: usize,
+    ) -> Result<Vec<Vec<f32>>> {
+        Err(Error::from_kind(ErrorKind::UnsupportedPredictionMethod(
+            String::from("predict_contribs"),
+            String::from("Detail: mapped models keep no node statistics"),
+        )))
+    }
+}
+
+/// Reads the model in `storage`, predicting from it in place unless `decode` is set and the
+/// booster can be decoded
+fn from_storage(storage: Storage, decode: bool) -> Result<Predictor> {
+    if cfg!(target_endian = "big") {
+        return Err(Error::from_kind(ErrorKind::UnimplementedFunc(
+            String::from("memory-mapped models are little-endian"),
+        )));
+    }
+    let bytes = storage.bytes();
+    let header = Header::read_from(bytes)?;
+    let payload = &bytes[HEADER_LEN..];
+    if header.payload_len != payload.len() as u64 || payload.len() < NUM_SECTIONS * 16 {
+        return Err(broken("truncated memory-mapped model"));
+    }
+    if crc32fast::hash(payload) != header.payload_crc {
+        return Err(broken("checksum mismatch"));
+    }
+    let objective = *OBJECTIVES
+        .get(header.objective as usize)
+        .ok_or_else(|| broken("unknown objective"))?;
+    if header.booster != BOOSTER_TREES && header.booster != BOOSTER_LINEAR {
+        return Err(broken("unknown booster"));
+    }
+
+    let mut sections = [(0usize, 0usize); NUM_SECTIONS];
+    for (id, section) in sections.iter_mut().enumerate() {
+        let offset = LE::read_u64(&payload[id * 16..]);
+        let len = LE::read_u64(&payload[id * 16 + 8..]);
+        if offset % ALIGN as u64 != 0
+            || len % ELEMENT_SIZES[id] as u64 != 0
+            || !matches!(offset.checked_add(len), Some(end) if end <= bytes.len() as u64)
+        {
+            return Err(broken("invalid section table"));
+        }
+        *section = (offset as usize, len as usize);
+    }
+    let (offset, len) = sections[FEATURE_NAMES];
+    let feature_names = if len == 0 {
+        None
+    } else {
+        let names = std::str::from_utf8(&bytes[offset..offset + len])
+            .map_err(|_| broken("feature names are not UTF-8"))?;
+        Some(names.split('\0').map(String::from).collect::<Vec<String>>())
+    };
+
+    let num_feature = header.num_feature as usize;
+    let num_output_group = header.num_output_group as usize;
+    let mut booster = MappedBooster {
+        storage,
+        sections,
+        booster: header.booster,
+        num_feature,
+        num_output_group,
+        tree_num_columns: vec![],
+        group_trees: vec![],
+    };
+    if header.booster == BOOSTER_TREES {
+        booster.validate_trees(header.num_trees as usize)?;
+    } else if booster.section::<f32>(LINEAR_WEIGHTS).len() != (num_feature + 1) * num_output_group {
+        return Err(broken("weights do not match the features"));
+    }
+
+    let decoded = if decode { booster.decode()? } else { None };
+    let gbm = decoded.unwrap_or_else(|| Box::new(booster));
+    let mut predictor = Predictor::from_booster(
+        header.base_score,
+        num_feature,
+        header.num_class as usize,
+        objective,
+        gbm,
+    )?;
+    if let Some(names) = feature_names {
+        predictor.set_feature_names(names)?;
+    }
+    Ok(predictor)
+}
+
+/// Maps the model file at `path` and predicts from the mapped pages. As for any memory map,
+/// the file must not be modified while the model is in use.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Predictor> {
+    let file = fs::File::open(path)?;
+    let mmap = unsafe { Mmap::map(&file)? };
+    from_storage(Storage::Mapped(mmap), false)
+}
+
+/// Copies the bytes into words, so that they are aligned
+fn owned_storage(bytes: &[u8]) -> Storage {
+    let mut words = vec![0u64; bytes.len().div_ceil(8)];
+    // u64 words are readable as bytes
+    unsafe { slice::from_raw_parts_mut(words.as_mut_ptr() as *mut u8, bytes.len()) }
+        .copy_from_slice(bytes);
+    Storage::Owned(words, bytes.len())
+}
+
+/// Loads a model in the memory-mapped format from bytes, which are copied to be aligned
+pub fn from_bytes(bytes: &[u8]) -> Result<Predictor> {
+    from_storage(owned_storage(bytes), false)
+}
+
+/// Loads a model in the memory-mapped format from bytes into the boosters of the models loaded
+/// from xgboost, giving back the model written. Compact trees are predicted from the bytes.
+pub fn decode(bytes: &[u8]) -> Result<Predictor> {
+    from_storage(owned_storage(bytes), true)
+}
+
+#[cfg(test)]
+mod tests {
+    use ndarray::Array2;
+
+    use crate::errors::ErrorKind;
+    use crate::predictor::Predictor;
+    use crate::testing::{
+        gblinear_model, gbtree_model, load_model, random_gbtree_model, random_matrix, random_trees,
+        JSON_MODEL,
+    };
+    use crate::FeatureValue;
+
+    use super::{from_bytes, load, save, write_to};
+
+    fn check_same_predictions(
+        predictor: &Predictor,
+        mapped: &Predictor,
+        feats: &Array2<FeatureValue>,
+    ) {
+        for ntree_limit in &[0, 3] {
+            for margin in &[true, false] {
+                let expected = predictor
+                    .predict_many(feats.view(), *margin, *ntree_limit)
+                    .unwrap();
+                let preds = mapped
+                    .predict_many(feats.view(), *margin, *ntree_limit)
+                    .unwrap();
+                let bits = |preds: Vec<Vec<f32>>| -> Vec<u32> {
+                    preds.into_iter().flatten().map(f32::to_bits).collect()
+                };
+                assert_eq!(bits(preds), bits(expected));
+            }
+        }
+    }
+
+    #[test]
+    fn test_mapped_model_predicts_as_loaded_model() {
+        let dir = tempfile::tempdir().unwrap();
+        let feats = random_matrix(41, 300, 6, 0.2);
+        let models = [
+            load_model(&random_gbtree_model(1, "multi:softprob", 6, 3, 10, 7)),
+            load_model(&gbtree_model(
+                "binary:logistic",
+                0.25,
+                6,
+                1,
+                &random_trees(3, 6, 1, 20, 6),
+                Some(&[0.5; 20]),
+            )),
+            load_model(&gblinear_model(
+                "reg:linear",
+                0.5,
+                6,
+                2,
+                &(0..14)
+                    .map(|i| i as f32 / 7f32 - 1f32)
+                    .collect::<Vec<f32>>(),
+            )),
+        ];
+        for (i, predictor) in models.iter().enumerate() {
+            let path = dir.path().join(format!("model_{}.bin", i));
+            save(predictor, &path).unwrap();
+            let mapped = load(&path).unwrap();
+            assert_eq!(mapped.num_class(), predictor.num_class());
+            check_same_predictions(predictor, &mapped, &feats);
+        }
+
+        let categorical = load_model(JSON_MODEL.as_bytes());
+        let mut bytes = vec![];
+        write_to(&categorical, &mut bytes).unwrap();
+        let mapped = from_bytes(&bytes).unwrap();
+        assert_eq!(mapped.feature_names(), categorical.feature_names());
+        let feats = Array2::from_shape_vec(
+            (4, 2),
+            vec![20.0, 1.0, 40.0, 3.0, 40.0, 2.0, 40.0, FeatureValue::NAN],
+        )
+        .unwrap();
+        check_same_predictions(&categorical, &mapped, &feats);
+    }
+
+    #[test]
+    fn test_compact_and_mapped_models_are_written() {
+        let feats = random_matrix(43, 300, 6, 0.2);
+        let trees = random_trees(5, 6, 3, 30, 7);
+        let weights = vec![0.75; trees.len()];
+        let model = gbtree_model("multi:softprob", 0.5, 6, 3, &trees, Some(&weights));
+        let predictor = load_model(&model);
+        let mut compact = load_model(&model);
+        compact.compact().unwrap();
+        let mut bytes = vec![];
+        write_to(&compact, &mut bytes).unwrap();
+        let from_compact = from_bytes(&bytes).unwrap();
+        check_same_predictions(&predictor, &from_compact, &feats);
+
+        let mut from_compact = from_compact;
+        from_compact
+            .set_feature_names((0..6).map(|fid| format!("f{}", fid)).collect())
+            .unwrap();
+        let mut rewritten = vec![];
+        write_to(&from_compact, &mut rewritten).unwrap();
+        let from_mapped = from_bytes(&rewritten).unwrap();
+        assert_eq!(from_mapped.feature_names(), from_compact.feature_names());
+        check_same_predictions(&predictor, &from_mapped, &feats);
+    }
+
+    #[test]
+    fn test_corrupted_model() {
+        let predictor = load_model(&random_gbtree_model(2, "binary:logistic", 4, 1, 5, 4));
+        let mut bytes = vec![];
+        write_to(&predictor, &mut bytes).unwrap();
+        assert!(from_bytes(&bytes).is_ok());
+
+        let broken = |bytes: &[u8]| match from_bytes(bytes) {
+            Err(error) => matches!(error.kind(), ErrorKind::BrokenModel(_)),
+            Ok(_) => false,
+        };
+        assert!(broken(&bytes[..bytes.len() - 1]));
+        assert!(broken(&bytes[..10]));
+        for position in &[0, 20, 100, bytes.len() - 1] {
+            let mut corrupted = bytes.clone();
+            corrupted[*position] ^= 1;
+            assert!(broken(&corrupted), "byte {}", position);
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use std::io::Read;
+use std::io::{self, Read};
 
 use byteorder::{ReadBytesExt, BE, LE};
 
@@ -26,6 +26,32 @@ pub trait ModelReader: ReadBytesExt {
             .chain_err(|| "failed to read f32 from model");
     }
 
+    /// Reads an i32 size or count, which must not be negative
+    #[inline]
+    fn read_i32_as_usize(&mut self) -> Result<usize> {
+        let value = self.read_i32_le()?;
+        if value < 0 {
+            return Err(Error::from_kind(ErrorKind::BrokenModel(format!(
+                "Detail: negative size {}",
+                value
+            ))));
+        }
+        return Ok(value as usize);
+    }
+
+    /// Reads an i64 size or count, which must not be negative
+    #[inline]
+    fn read_i64_as_usize(&mut self) -> Result<usize> {
+        let value = self.read_i64_le()?;
+        if value < 0 {
+            return Err(Error::from_kind(ErrorKind::BrokenModel(format!(
+                "Detail: negative size {}",
+                value
+            ))));
+        }
+        return Ok(value as usize);
+    }
+
     #[inline]
     fn read_byte_as_i32(&mut self) -> Result<i32> {
         let byte = self.read_u8()?;
@@ -67,20 +93,28 @@ pub trait ModelReader: ReadBytesExt {
     }
 
     fn skip(&mut self, num_bytes: usize) -> Result<()> {
-        let mut vec: Vec<u8> = vec![0u8; num_bytes];
-        return self
-            .read_exact(vec.as_mut_slice())
-            .chain_err(|| "failed to read u8 slice from model");
+        let skipped = io::copy(&mut self.take(num_bytes as u64), &mut io::sink())?;
+        if skipped != num_bytes as u64 {
+            return Err(Error::from(io::Error::from(io::ErrorKind::UnexpectedEof)))
+                .chain_err(|| "failed to read u8 slice from model");
+        }
+        return Ok(());
     }
 
+    /// Reads `size` bytes, growing the buffer as data arrives so that a corrupted size does
+    /// not allocate more than the model holds
     fn read_u8_vec(&mut self, size: usize) -> Result<Vec<u8>> {
-        let mut vec: Vec<u8> = vec![0u8; size];
-        self.read_exact(vec.as_mut_slice())?;
+        let mut vec: Vec<u8> = vec![];
+        self.take(size as u64).read_to_end(&mut vec)?;
+        if vec.len() != size {
+            return Err(Error::from(io::Error::from(io::ErrorKind::UnexpectedEof)))
+                .chain_err(|| "failed to read u8 slice from model");
+        }
         return Ok(vec);
     }
 
     fn read_u8_vec_len(&mut self) -> Result<Vec<u8>> {
-        let len = self.read_i64_le()? as usize;
+        let len = self.read_i64_as_usize()?;
         return self.read_u8_vec(len);
     }
 }
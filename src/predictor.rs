@@ -1,11 +1,64 @@
+use std::collections::{HashMap, HashSet};
+use std::io::Read;
+
 use byteorder::{ByteOrder, LE};
-use ndarray::{ArrayView1, ArrayView2};
+use ndarray::{s, Array2, ArrayView2, Axis, CowArray, Ix2};
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use serde_json::Value;
 
 use crate::errors::*;
-use crate::functions::{get_classify_func_type, get_classify_function, ObjFunction};
-use crate::gbm::grad_booster::GradBooster;
+use crate::functions::{
+    get_classify_func_type, get_classify_function, prob_to_margin, FunctionType, ObjFunction,
+};
+use crate::gbm::compact::CompactGBTree;
+use crate::gbm::grad_booster::{BoosterModel, GradBooster};
+use crate::json_reader::JsonReader;
 use crate::model_reader::ModelReader;
+use crate::{FeatureValue, InputValue};
+
+/// Type of a feature, as named in the `feature_types` of xgboost
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum FeatureType {
+    /// quantitative value, "q"
+    Quantitative,
+    /// indicator, "i"
+    Indicator,
+    /// integer value, "int"
+    Int,
+    /// float value, "float"
+    Float,
+    /// categorical value given by its category code, "c"
+    Categorical,
+}
+
+impl FeatureType {
+    pub fn from_name(name: &str) -> Result<FeatureType> {
+        match name {
+            "q" => Ok(FeatureType::Quantitative),
+            "i" => Ok(FeatureType::Indicator),
+            "int" => Ok(FeatureType::Int),
+            "float" => Ok(FeatureType::Float),
+            "c" => Ok(FeatureType::Categorical),
+            _ => Err(Error::from_kind(ErrorKind::UnsupportedDataType(format!(
+                "feature type {}",
+                name
+            )))),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            FeatureType::Quantitative => "q",
+            FeatureType::Indicator => "i",
+            FeatureType::Int => "int",
+            FeatureType::Float => "float",
+            FeatureType::Categorical => "c",
+        }
+    }
+}
 
+#[allow(dead_code)]
 struct ModelParam {
     /// global bias
     base_score: f32,
@@ -39,47 +92,104 @@ impl ModelParam {
     }
 }
 
+/// How input whose number of columns differs from the model's number of features is handled
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShapePolicy {
+    /// the number of columns must be equal to the number of features
+    Strict,
+    /// extra trailing columns are ignored
+    AllowExtra,
+    /// missing trailing columns are treated as missing values
+    PadMissing,
+}
+
+impl ShapePolicy {
+    pub fn from_name(name: &str) -> Result<ShapePolicy> {
+        match name {
+            "strict" => Ok(ShapePolicy::Strict),
+            "allow_extra" => Ok(ShapePolicy::AllowExtra),
+            "pad_missing" => Ok(ShapePolicy::PadMissing),
+            _ => Err(Error::from_kind(ErrorKind::InvalidArgument(format!(
+                "shape policy {}, expected strict, allow_extra or pad_missing",
+                name
+            )))),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ShapePolicy::Strict => "strict",
+            ShapePolicy::AllowExtra => "allow_extra",
+            ShapePolicy::PadMissing => "pad_missing",
+        }
+    }
+}
+
+/// Number of rows scored together by a thread
+const ROW_BLOCK_SIZE: usize = 256;
+
 /// Predicts using the Xgboost model
 pub struct Predictor {
     mparam: ModelParam,
-    //SparkModelParam sparkModelParam;
+    /// name of the objective as written in the model, e.g. reg:logistic for `BinaryLogistic`
+    name_obj: String,
     obj_func: ObjFunction,
-    gbm: Box<dyn GradBooster + Send>,
+    gbm: Box<dyn GradBooster + Send + Sync>,
+    feature_names: Option<Vec<String>>,
+    feature_types: Option<Vec<FeatureType>>,
+    shape_policy: ShapePolicy,
+    /// number of threads of batch prediction, 0 means the global rayon pool
+    nthread: usize,
+    pool: Option<ThreadPool>,
 }
 
 impl Predictor {
-    fn read_model_params<T: ModelReader>(reader: &mut T) -> Result<ModelParam> {
-        let mut first4bytes = [0u8; 4];
+    fn read_model_params<T: ModelReader>(
+        first4bytes: [u8; 4],
+        reader: &mut T,
+    ) -> Result<ModelParam> {
         let mut next4bytes = [0u8; 4];
-        reader.read_exact(&mut first4bytes)?;
         reader.read_exact(&mut next4bytes)?;
 
-        let (base_score, num_feature) = if &first4bytes == b"binf" {
-            (LE::read_f32(&next4bytes), reader.read_i32_le()? as usize)
-        } else if &first4bytes[..3] == [0x00u8, 0x05, 0x5f] {
+        let (base_score, num_feature) = if first4bytes == *b"binf" {
+            (LE::read_f32(&next4bytes), reader.read_i32_as_usize()?)
+        } else if first4bytes[..3] == [0x00u8, 0x05, 0x5f] {
             // Model generated by xgboost4j-spark?
             return Err(Error::from_kind(ErrorKind::UnsupportedModelType(
                 String::from("xgboost4j-spark"),
             )));
         } else {
-            (
-                LE::read_f32(&first4bytes),
-                LE::read_i32(&next4bytes) as usize,
-            )
+            let num_feature = LE::read_i32(&next4bytes);
+            if num_feature < 0 {
+                return Err(Error::from_kind(ErrorKind::BrokenModel(format!(
+                    "Detail: negative number of features {}",
+                    num_feature
+                ))));
+            }
+            (LE::read_f32(&first4bytes), num_feature as usize)
         };
 
         return ModelParam::read_from(base_score, num_feature, reader);
     }
 
-    /// Instantiates with the Xgboost model
+    /// Instantiates with the Xgboost model, either in the binary or in the JSON format
     pub fn read_from<T: ModelReader>(reader: &mut T) -> Result<Predictor> {
-        let mparam = Predictor::read_model_params(reader)?;
+        let mut first4bytes = [0u8; 4];
+        reader.read_exact(&mut first4bytes)?;
+        if first4bytes[0] == b'{' {
+            let mut json = first4bytes.to_vec();
+            reader.read_to_end(&mut json)?;
+            return Predictor::from_json(&json);
+        }
+
+        let mparam = Predictor::read_model_params(first4bytes, reader)?;
 
         let name_obj = reader.read_u8_vec_len()?;
         let name_gbm = reader.read_u8_vec_len()?;
 
-        let obj_func_type = get_classify_func_type(name_obj)?;
+        let obj_func_type = get_classify_func_type(name_obj.clone())?;
         let obj_func = get_classify_function(obj_func_type);
+        let name_obj = String::from_utf8(name_obj)?;
         let gbm = crate::gbm::grad_booster::load_grad_booster(
             reader,
             name_gbm,
@@ -88,83 +198,993 @@ impl Predictor {
 
         return Ok(Predictor {
             mparam,
+            name_obj,
             obj_func,
             gbm,
+            feature_names: None,
+            feature_types: None,
+            shape_policy: ShapePolicy::Strict,
+            nthread: 0,
+            pool: None,
         });
     }
 
+    /// Instantiates with a model in the binary or JSON format of xgboost, or in the format of
+    /// `mapped`
+    pub fn read_any<T: ModelReader>(reader: &mut T) -> Result<Predictor> {
+        let mut magic = [0u8; 8];
+        reader.read_exact(&mut magic)?;
+        if magic == crate::mapped::MAGIC {
+            let mut bytes = magic.to_vec();
+            reader.read_to_end(&mut bytes)?;
+            return Predictor::from_bytes(&bytes);
+        }
+        Predictor::read_from(&mut (&magic[..]).chain(reader))
+    }
+
+    /// Instantiates with the Xgboost model saved in the JSON format
+    pub fn from_json(json: &[u8]) -> Result<Predictor> {
+        let model: Value = serde_json::from_slice(json).map_err(|error| {
+            Error::from_kind(ErrorKind::BrokenModel(format!(
+                "Detail: invalid JSON, {}",
+                error
+            )))
+        })?;
+        let learner = model.field("learner")?;
+
+        let learner_param = learner.field("learner_model_param")?;
+        let num_feature = learner_param.usize_field("num_feature")?;
+        let num_class = learner_param.usize_field("num_class")?;
+
+        let name_obj = learner.field("objective")?.str_field("name")?;
+        let obj_func_type = get_classify_func_type(name_obj.as_bytes().to_vec())?;
+        let base_score = prob_to_margin(&obj_func_type, learner_param.f32_field("base_score")?);
+        let obj_func = get_classify_function(obj_func_type);
+
+        let gbm = crate::gbm::grad_booster::load_grad_booster_json(
+            learner.field("gradient_booster")?,
+            num_feature,
+            num_class.max(1),
+        )?;
+
+        let feature_names = match learner.get("feature_names") {
+            Some(_) => Some(learner.string_vec_field("feature_names")?),
+            None => None,
+        };
+        let feature_types = match learner.get("feature_types") {
+            Some(_) => Some(
+                learner
+                    .string_vec_field("feature_types")?
+                    .iter()
+                    .map(|name| FeatureType::from_name(name))
+                    .collect::<Result<Vec<FeatureType>>>()?,
+            ),
+            None => None,
+        };
+
+        let mut predictor = Predictor {
+            mparam: ModelParam {
+                base_score,
+                num_feature,
+                num_class: num_class as i32,
+                saved_with_pbuffer: 0,
+            },
+            name_obj: String::from(name_obj),
+            obj_func,
+            gbm,
+            feature_names: None,
+            feature_types: None,
+            shape_policy: ShapePolicy::Strict,
+            nthread: 0,
+            pool: None,
+        };
+        // xgboost writes empty lists when the booster was trained without names or types
+        if let Some(names) = feature_names.filter(|names| !names.is_empty()) {
+            predictor.set_feature_names(names)?;
+        }
+        if let Some(types) = feature_types.filter(|types| !types.is_empty()) {
+            predictor.set_feature_types(types)?;
+        }
+        return Ok(predictor);
+    }
+
+    /// Instantiates with a booster built from another representation of the model
+    pub(crate) fn from_booster(
+        base_score: f32,
+        num_feature: usize,
+        num_class: usize,
+        name_obj: &str,
+        gbm: Box<dyn GradBooster + Send + Sync>,
+    ) -> Result<Predictor> {
+        let objective = get_classify_func_type(name_obj.as_bytes().to_vec())?;
+        Ok(Predictor {
+            mparam: ModelParam {
+                base_score,
+                num_feature,
+                num_class: num_class as i32,
+                saved_with_pbuffer: 0,
+            },
+            name_obj: String::from(name_obj),
+            obj_func: get_classify_function(objective),
+            gbm,
+            feature_names: None,
+            feature_types: None,
+            shape_policy: ShapePolicy::Strict,
+            nthread: 0,
+            pool: None,
+        })
+    }
+
+    /// Global bias, as a margin
+    pub fn base_score(&self) -> f32 {
+        self.mparam.base_score
+    }
+
+    /// Objective function the model was trained with
+    pub fn objective(&self) -> FunctionType {
+        self.obj_func.tp
+    }
+
+    /// Name of the objective in the model, which may be an alias of the name of `objective`
+    pub fn objective_name(&self) -> &str {
+        &self.name_obj
+    }
+
+    pub(crate) fn booster(&self) -> BoosterModel<'_> {
+        self.gbm.model()
+    }
+
+    /// Whether the learner parameters announce a prediction buffer after the trees
+    pub(crate) fn saved_with_pbuffer(&self) -> bool {
+        self.mparam.saved_with_pbuffer != 0
+    }
+
     pub fn model_num_feature(&self) -> usize {
         self.mparam.num_feature()
     }
 
-    // fn predict_raw(&self, feat: ArrayView1<'_, f32>, ntree_limit: usize) -> Result<Vec<f32>> {
-    //     let mut preds = self.gbm.predict(feat, ntree_limit)?;
-    //     for i in 0..preds.len() {
-    //         preds[i] += self.mparam.base_score as f32;
-    //     }
-    //     Ok(preds)
-    // }
-
-    // /// Generates predictions for given feature vector
-    // pub fn predict(
-    //     &self,
-    //     feat: ArrayView1<'_, f32>,
-    //     output_margin: bool,
-    //     ntree_limit: usize,
-    // ) -> Result<Vec<f32>> {
-    //     let preds = self.predict_raw(feat, ntree_limit)?;
-
-    //     return if !output_margin {
-    //         Ok((self.obj_func.vector)(&preds))
-    //     } else {
-    //         Ok(preds)
-    //     };
-    // }
-
-    // fn predict_single_raw(&self, feat: ArrayView1<'_, f32>, ntree_limit: usize) -> Result<f32> {
-    //     Ok(self.gbm.predict_single(feat, ntree_limit)? + self.mparam.base_score as f32)
-    // }
-
-    // /// Generates a prediction for given feature vector
-    // pub fn predict_single(
-    //     &self,
-    //     feat: ArrayView1<'_, f32>,
-    //     output_margin: bool,
-    //     ntree_limit: usize,
-    // ) -> Result<f32> {
-    //     let pred = self.predict_single_raw(feat, ntree_limit)?;
-    //     return if !output_margin {
-    //         (self.obj_func.scalar)(pred)
-    //     } else {
-    //         Ok(pred)
-    //     };
-    // }
-
-    // /// Predicts leaf index of each tree.
-    // pub fn predict_leaf(
-    //     &self,
-    //     feat: ArrayView1<'_, f32>,
-    //     ntree_limit: usize,
-    // ) -> Result<Vec<usize>> {
-    //     self.gbm.predict_leaf(feat, ntree_limit)
-    // }
+    /// Number of classes of multi-class models, 0 otherwise
+    pub fn num_class(&self) -> usize {
+        self.mparam.num_class.max(0) as usize
+    }
 
-    /// Generates a prediction for given vectors of features
-    pub fn predict_many(
+    /// Number of margins predicted per row, the number of classes of multi-class models
+    pub fn num_output_group(&self) -> usize {
+        self.gbm.num_output_group()
+    }
+
+    /// Number of values predicted per row: one per output group, or the class index of
+    /// multi:softmax models when the margins are transformed
+    pub fn num_output(&self, output_margin: bool) -> usize {
+        if !output_margin && self.obj_func.tp == FunctionType::MultiSoftmax {
+            return 1;
+        }
+        self.num_output_group()
+    }
+
+    /// Names of the features in model order, if the model carries them
+    pub fn feature_names(&self) -> Option<&[String]> {
+        self.feature_names.as_deref()
+    }
+
+    /// Types of the features in model order, if the model carries them
+    pub fn feature_types(&self) -> Option<&[FeatureType]> {
+        self.feature_types.as_deref()
+    }
+
+    /// Sets the names of the features, e.g. for binary models which do not store them
+    pub fn set_feature_names(&mut self, names: Vec<String>) -> Result<()> {
+        self.check_feature_info_len("feature_names", names.len())?;
+        let mut unique: HashSet<&str> = HashSet::with_capacity(names.len());
+        for name in names.iter() {
+            if !unique.insert(name) {
+                return Err(Error::from_kind(ErrorKind::InvalidArgument(format!(
+                    "duplicate feature name '{}'",
+                    name
+                ))));
+            }
+        }
+        self.feature_names = Some(names);
+        Ok(())
+    }
+
+    /// Sets the types of the features
+    pub fn set_feature_types(&mut self, types: Vec<FeatureType>) -> Result<()> {
+        self.check_feature_info_len("feature_types", types.len())?;
+        self.feature_types = Some(types);
+        Ok(())
+    }
+
+    pub fn shape_policy(&self) -> ShapePolicy {
+        self.shape_policy
+    }
+
+    /// Sets how input with a number of columns other than the number of features is handled
+    pub fn set_shape_policy(&mut self, policy: ShapePolicy) {
+        self.shape_policy = policy;
+    }
+
+    pub fn nthread(&self) -> usize {
+        self.nthread
+    }
+
+    /// Sets the number of threads used by batch prediction: 0 uses the global thread pool,
+    /// which has one thread per core, 1 predicts on the calling thread
+    pub fn set_nthread(&mut self, nthread: usize) -> Result<()> {
+        self.pool = if nthread > 1 {
+            Some(ThreadPoolBuilder::new().num_threads(nthread).build()?)
+        } else {
+            None
+        };
+        self.nthread = nthread;
+        Ok(())
+    }
+
+    /// Replaces the trees by a compact representation with quantised split conditions and
+    /// without node statistics, giving the same predictions. The model can no longer be
+    /// exported afterwards. Linear models are left as they are.
+    pub fn compact(&mut self) -> Result<()> {
+        if let BoosterModel::Trees(gbtree) = self.gbm.model() {
+            self.gbm = Box::new(CompactGBTree::new(gbtree)?);
+        }
+        Ok(())
+    }
+
+    /// Serialises the model and its feature names in the format of `mapped`, with the node ids
+    /// and statistics of the trees. The other settings of the predictor are not kept.
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut bytes = vec![];
+        crate::mapped::write_to(self, &mut bytes)?;
+        Ok(bytes)
+    }
+
+    /// Reads a model serialised by `to_bytes`, giving back the trees or the weights of the model
+    /// serialised. Compact trees, written without their node ids and statistics, are predicted
+    /// from the bytes as a mapped model.
+    pub fn from_bytes(bytes: &[u8]) -> Result<Predictor> {
+        crate::mapped::decode(bytes)
+    }
+
+    /// Whether the trees are held in the compact representation
+    pub fn is_compact(&self) -> bool {
+        matches!(self.gbm.model(), BoosterModel::Compact(_))
+    }
+
+    /// Checks the number of columns against the number of features and the shape policy
+    fn check_input_shape(&self, num_columns: usize) -> Result<()> {
+        let num_feature = self.model_num_feature();
+        match self.shape_policy {
+            _ if num_columns == num_feature => Ok(()),
+            ShapePolicy::AllowExtra if num_columns > num_feature => Ok(()),
+            ShapePolicy::PadMissing if num_columns < num_feature => Ok(()),
+            _ => Err(Error::from_kind(ErrorKind::InvalidInputShape(
+                num_feature,
+                num_columns,
+            ))),
+        }
+    }
+
+    /// Checks the number of columns against the number of features and, depending on the shape
+    /// policy, drops extra columns or pads missing ones with NaN
+    pub fn shape_input<'a>(
+        &self,
+        feats: ArrayView2<'a, FeatureValue>,
+    ) -> Result<CowArray<'a, FeatureValue, Ix2>> {
+        self.check_input_shape(feats.ncols())?;
+        let num_feature = self.model_num_feature();
+        let num_columns = feats.ncols();
+        if num_columns >= num_feature {
+            return Ok(CowArray::from(feats.slice_move(s![.., ..num_feature])));
+        }
+        let mut padded = Array2::from_elem((feats.nrows(), num_feature), FeatureValue::NAN);
+        padded.slice_mut(s![.., ..num_columns]).assign(&feats);
+        Ok(CowArray::from(padded))
+    }
+
+    fn check_feature_info_len(&self, name: &str, len: usize) -> Result<()> {
+        if len != self.model_num_feature() {
+            return Err(Error::from_kind(ErrorKind::BrokenModel(format!(
+                "Detail: {} {} given for {} features",
+                len,
+                name,
+                self.model_num_feature()
+            ))));
+        }
+        Ok(())
+    }
+
+    /// Reorders named feature values into rows in model order. Every feature of the model must
+    /// be given, a NaN value marks it as missing.
+    pub fn named_to_matrix(
         &self,
-        feats: ArrayView2<'_, f32>,
+        rows: &[HashMap<&str, FeatureValue>],
+    ) -> Result<Array2<FeatureValue>> {
+        let names = match &self.feature_names {
+            Some(names) => names,
+            None => {
+                return Err(Error::from_kind(ErrorKind::UnsupportedPredictionMethod(
+                    String::from("predict_named"),
+                    String::from("Detail: the model has no feature names"),
+                )))
+            }
+        };
+        let known: HashSet<&str> = names.iter().map(|name| name.as_str()).collect();
+        let mut feats = Array2::from_elem((rows.len(), names.len()), FeatureValue::NAN);
+        for (i, row) in rows.iter().enumerate() {
+            let missing: Vec<String> = names
+                .iter()
+                .filter(|name| !row.contains_key(name.as_str()))
+                .cloned()
+                .collect();
+            let mut unexpected: Vec<String> = row
+                .keys()
+                .filter(|name| !known.contains(*name))
+                .map(|name| String::from(*name))
+                .collect();
+            if !missing.is_empty() || !unexpected.is_empty() {
+                unexpected.sort();
+                return Err(Error::from_kind(ErrorKind::FeatureMismatch(
+                    missing, unexpected,
+                )));
+            }
+            for (fid, name) in names.iter().enumerate() {
+                feats[[i, fid]] = row[name.as_str()];
+            }
+        }
+        Ok(feats)
+    }
+
+    /// Maps every feature of the model to the index of its column among `columns`, whose
+    /// names must be unique. Models without feature names take the columns in order, under the
+    /// shape policy.
+    pub fn column_order(&self, columns: &[&str]) -> Result<Vec<Option<usize>>> {
+        let names = match &self.feature_names {
+            Some(names) => names,
+            None => {
+                self.check_input_shape(columns.len())?;
+                return Ok((0..self.model_num_feature())
+                    .map(|fid| if fid < columns.len() { Some(fid) } else { None })
+                    .collect());
+            }
+        };
+        let mut positions: HashMap<&str, usize> = HashMap::with_capacity(columns.len());
+        for (index, column) in columns.iter().enumerate() {
+            if positions.insert(*column, index).is_some() {
+                return Err(Error::from_kind(ErrorKind::InvalidArgument(format!(
+                    "duplicate column '{}'",
+                    column
+                ))));
+            }
+        }
+        let known: HashSet<&str> = names.iter().map(|name| name.as_str()).collect();
+        let missing: Vec<String> = names
+            .iter()
+            .filter(|name| !positions.contains_key(name.as_str()))
+            .cloned()
+            .collect();
+        let unexpected: Vec<String> = columns
+            .iter()
+            .filter(|column| !known.contains(*column))
+            .map(|column| String::from(*column))
+            .collect();
+        if !missing.is_empty() || !unexpected.is_empty() {
+            return Err(Error::from_kind(ErrorKind::FeatureMismatch(
+                missing, unexpected,
+            )));
+        }
+        Ok(names
+            .iter()
+            .map(|name| Some(positions[name.as_str()]))
+            .collect())
+    }
+
+    /// Generates predictions for rows of named feature values
+    pub fn predict_named(
+        &self,
+        rows: &[HashMap<&str, FeatureValue>],
         output_margin: bool,
         ntree_limit: usize,
     ) -> Result<Vec<Vec<f32>>> {
-        let preds = self.gbm.predict_many(feats, self.mparam.base_score as f32,  ntree_limit)?;
+        let feats = self.named_to_matrix(rows)?;
+        self.predict_many(feats.view(), output_margin, ntree_limit)
+    }
 
+    /// Predicts blocks of rows in parallel, rows are scored independently so the output does
+    /// not depend on the number of threads
+    fn predict_blocks<T, O, F>(
+        &self,
+        feats: ArrayView2<'_, T>,
+        predict_block: F,
+    ) -> Result<Vec<Vec<O>>>
+    where
+        T: Sync,
+        O: Send,
+        F: Fn(&ArrayView2<'_, T>) -> Result<Vec<Vec<O>>> + Sync,
+    {
+        let blocks: Vec<ArrayView2<'_, T>> =
+            feats.axis_chunks_iter(Axis(0), ROW_BLOCK_SIZE).collect();
+        let block_preds = if self.nthread == 1 || blocks.len() < 2 {
+            blocks
+                .iter()
+                .map(&predict_block)
+                .collect::<Result<Vec<_>>>()?
+        } else {
+            match &self.pool {
+                Some(pool) => pool.install(|| {
+                    blocks
+                        .par_iter()
+                        .map(&predict_block)
+                        .collect::<Result<Vec<_>>>()
+                })?,
+                None => blocks
+                    .par_iter()
+                    .map(&predict_block)
+                    .collect::<Result<Vec<_>>>()?,
+            }
+        };
+        Ok(block_preds.into_iter().flatten().collect())
+    }
+
+    fn transform(&self, preds: Vec<Vec<f32>>, output_margin: bool) -> Vec<Vec<f32>> {
         if !output_margin {
-            Ok(preds
+            preds
                 .into_iter()
                 .map(|row| (self.obj_func.vector)(&row))
-                .collect())
+                .collect()
+        } else {
+            preds
+        }
+    }
+
+    /// Generates a prediction for given vectors of features
+    pub fn predict_many(
+        &self,
+        feats: ArrayView2<'_, FeatureValue>,
+        output_margin: bool,
+        ntree_limit: usize,
+    ) -> Result<Vec<Vec<f32>>> {
+        let feats = self.shape_input(feats)?;
+        let preds = self.predict_blocks(feats.view(), |block| {
+            self.gbm
+                .predict_many(block.view(), self.mparam.base_score, ntree_limit)
+        })?;
+        Ok(self.transform(preds, output_margin))
+    }
+
+    /// Predicts the id of the leaf reached in each tree, for the trees limited by `ntree_limit`
+    /// in model order. Only gbtree and dart models support it.
+    pub fn predict_leaf(
+        &self,
+        feats: ArrayView2<'_, FeatureValue>,
+        ntree_limit: usize,
+    ) -> Result<Vec<Vec<u32>>> {
+        let feats = self.shape_input(feats)?;
+        self.predict_blocks(feats.view(), |block| {
+            self.gbm.predict_leaf(block.view(), ntree_limit)
+        })
+    }
+
+    /// Number of leaf ids per row given by `predict_leaf`
+    pub fn num_leaf_output(&self, ntree_limit: usize) -> Result<usize> {
+        match self.gbm.model() {
+            BoosterModel::Trees(gbtree) => Ok(gbtree.leaf_trees(ntree_limit).len()),
+            _ => {
+                // the other boosters give the reason why they do not predict leaves
+                self.gbm
+                    .predict_leaf(Array2::zeros((0, 0)).view(), ntree_limit)?;
+                Ok(0)
+            }
+        }
+    }
+
+    /// Predicts the SHAP values of the features, which sum to the margins. Each row holds the
+    /// values of every output group in turn, made of `model_num_feature()` contributions
+    /// followed by the bias.
+    pub fn predict_contribs(
+        &self,
+        feats: ArrayView2<'_, FeatureValue>,
+        ntree_limit: usize,
+    ) -> Result<Vec<Vec<f32>>> {
+        let feats = self.shape_input(feats)?;
+        self.predict_blocks(feats.view(), |block| {
+            self.gbm
+                .predict_contribs(block.view(), self.mparam.base_score, ntree_limit)
+        })
+    }
+
+    /// Number of contributions per row given by `predict_contribs`
+    pub fn num_contribs_output(&self) -> usize {
+        self.num_output_group() * (self.model_num_feature() + 1)
+    }
+
+    /// Generates a prediction for vectors of features of another type, or laid out with any
+    /// strides. Each block of rows is converted to `FeatureValue` and shaped by the shape policy
+    /// just before being predicted, the input is never copied as a whole.
+    pub fn predict_many_converted<T: InputValue>(
+        &self,
+        feats: ArrayView2<'_, T>,
+        output_margin: bool,
+        ntree_limit: usize,
+    ) -> Result<Vec<Vec<f32>>> {
+        self.check_input_shape(feats.ncols())?;
+        let num_feature = self.model_num_feature();
+        let preds = self.predict_blocks(feats, |block| {
+            let num_columns = block.ncols();
+            let converted = Array2::from_shape_fn((block.nrows(), num_feature), |(row, col)| {
+                if col < num_columns {
+                    block[[row, col]].to_feature_value()
+                } else {
+                    FeatureValue::NAN
+                }
+            });
+            self.gbm
+                .predict_many(converted.view(), self.mparam.base_score, ntree_limit)
+        })?;
+        Ok(self.transform(preds, output_margin))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use assert_approx_eq::assert_approx_eq;
+    use ndarray::{arr2, s, Array2};
+
+    use crate::errors::ErrorKind;
+    use crate::gbm::grad_booster::BoosterModel;
+    use crate::testing::{
+        gblinear_model, gbtree_model, load_model, random_gbtree_model, random_matrix, TestNode,
+        TestTree, JSON_MODEL,
+    };
+
+    use crate::FeatureValue;
+
+    use super::{FeatureType, Predictor, ShapePolicy};
+
+    fn stump(group: i32, left: f32, right: f32) -> TestTree {
+        TestTree::new(
+            group,
+            vec![
+                TestNode::Split(0, 0.5, 1, 2, false),
+                TestNode::Leaf(left),
+                TestNode::Leaf(right),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_predict_many_gbtree() {
+        let trees = [stump(0, -1.0, 1.0), stump(0, 0.25, 0.5)];
+        let predictor = load_model(&gbtree_model("binary:logistic", 0.0, 1, 1, &trees, None));
+        let feats = arr2(&[[0.0], [1.0], [FeatureValue::NAN]]);
+
+        let margins = predictor.predict_many(feats.view(), true, 0).unwrap();
+        assert_eq!(margins, vec![vec![-0.75], vec![1.5], vec![1.5]]);
+        let limited = predictor.predict_many(feats.view(), true, 1).unwrap();
+        assert_eq!(limited, vec![vec![-1.0], vec![1.0], vec![1.0]]);
+        let preds = predictor.predict_many(feats.view(), false, 0).unwrap();
+        assert_approx_eq!(preds[0][0], 1.0 / (1.0 + 0.75f32.exp()));
+    }
+
+    #[test]
+    fn test_predict_many_multiclass() {
+        let trees = [stump(0, 1.0, 0.0), stump(1, 0.0, 1.0), stump(2, 0.5, 0.5)];
+        let predictor = load_model(&gbtree_model("multi:softmax", 0.5, 1, 3, &trees, None));
+        let feats = arr2(&[[0.0], [1.0]]);
+        let preds = predictor.predict_many(feats.view(), false, 0).unwrap();
+        assert_eq!(preds, vec![vec![0.0], vec![1.0]]);
+        let margins = predictor.predict_many(feats.view(), true, 0).unwrap();
+        assert_eq!(margins, vec![vec![1.5, 0.5, 1.0], vec![0.5, 1.5, 1.0]]);
+        assert_eq!(
+            (predictor.num_output(true), predictor.num_output(false)),
+            (3, 1)
+        );
+    }
+
+    #[test]
+    fn test_predict_many_dart() {
+        let trees = [stump(0, -1.0, 1.0), stump(0, 1.0, 2.0)];
+        let bytes = gbtree_model("reg:linear", 0.5, 1, 1, &trees, Some(&[0.5, 0.25]));
+        let predictor = load_model(&bytes);
+        let preds = predictor
+            .predict_many(arr2(&[[1.0]]).view(), false, 0)
+            .unwrap();
+        assert_eq!(preds, vec![vec![1.5]]);
+    }
+
+    #[test]
+    fn test_predict_many_gblinear() {
+        // two features and two groups, bias last
+        let weights = [1.0, -1.0, 2.0, 0.5, 0.25, 0.0];
+        let bytes = gblinear_model("reg:linear", 0.5, 2, 2, &weights);
+        let predictor = load_model(&bytes);
+        let preds = predictor
+            .predict_many(arr2(&[[1.0, 1.0], [0.0, 2.0]]).view(), false, 0)
+            .unwrap();
+        assert_eq!(preds, vec![vec![3.75, 0.0], vec![4.75, 1.5]]);
+    }
+
+    #[test]
+    fn test_read_json_model() {
+        let predictor = load_model(JSON_MODEL.as_bytes());
+        assert_eq!(predictor.model_num_feature(), 2);
+        assert_eq!(
+            predictor.feature_names().unwrap(),
+            &[String::from("age"), String::from("color")]
+        );
+        assert_eq!(
+            predictor.feature_types().unwrap(),
+            &[FeatureType::Float, FeatureType::Categorical]
+        );
+
+        let feats = arr2(&[
+            [20.0, 1.0],
+            [40.0, 1.0],
+            [40.0, 3.0],
+            [40.0, 2.0],
+            [40.0, -1.0],
+            [40.0, FeatureValue::NAN],
+        ]);
+        let margins = predictor.predict_many(feats.view(), true, 0).unwrap();
+        // base score 0.5 is a probability, i.e. a margin of 0
+        assert_eq!(
+            margins,
+            vec![
+                vec![0.25],
+                vec![1.0],
+                vec![1.0],
+                vec![-1.0],
+                vec![-1.0],
+                vec![-1.0]
+            ]
+        );
+    }
+
+    #[test]
+    fn test_predict_leaf_and_contribs() {
+        let predictor = load_model(JSON_MODEL.as_bytes());
+        let feats = arr2(&[[20.0, 1.0], [40.0, 1.0], [40.0, 2.0]]);
+        let leaves = predictor.predict_leaf(feats.view(), 0).unwrap();
+        assert_eq!(leaves, vec![vec![1], vec![4], vec![3]]);
+        assert_eq!(predictor.num_leaf_output(0).unwrap(), 1);
+
+        // the expected value of the tree is 0.1, the margin of the base score is 0
+        let contribs = predictor.predict_contribs(feats.view(), 0).unwrap();
+        let expected = [[-0.15, 0.3, 0.1], [0.1, 0.8, 0.1], [-0.3, -0.8, 0.1]];
+        assert_eq!(predictor.num_contribs_output(), 3);
+        for (row, expected_row) in contribs.iter().zip(expected.iter()) {
+            for (contrib, expected_contrib) in row.iter().zip(expected_row.iter()) {
+                assert_approx_eq!(contrib, expected_contrib);
+            }
+        }
+
+        let mut compact = load_model(&gbtree_model(
+            "reg:linear",
+            0.5,
+            1,
+            1,
+            &[stump(0, 1.0, 2.0)],
+            None,
+        ));
+        compact.compact().unwrap();
+        let feats = arr2(&[[1.0]]);
+        for error in [
+            compact.predict_leaf(feats.view(), 0).map(|_| ()),
+            compact.predict_contribs(feats.view(), 0).map(|_| ()),
+            compact.num_leaf_output(0).map(|_| ()),
+        ] {
+            match error.map_err(|error| error.0) {
+                Err(ErrorKind::UnsupportedPredictionMethod(..)) => {}
+                _ => panic!("compact models do not keep node ids and statistics"),
+            }
+        }
+
+        let weights = [1.0, -1.0, 2.0, 0.5, 0.25, 0.0];
+        let linear = load_model(&gblinear_model("reg:linear", 0.5, 2, 2, &weights));
+        let feats = arr2(&[[1.0, FeatureValue::NAN]]);
+        let contribs = linear.predict_contribs(feats.view(), 0).unwrap();
+        assert_eq!(contribs, vec![vec![1.0, 0.0, 0.75, -1.0, 0.0, 0.5]]);
+        assert!(linear.predict_leaf(feats.view(), 0).is_err());
+    }
+
+    #[test]
+    fn test_read_any() {
+        let json = load_model(JSON_MODEL.as_bytes());
+        let trees = [stump(0, -1.0, 1.0)];
+        let binary = gbtree_model("binary:logistic", 0.5, 1, 1, &trees, None);
+        let mut mapped = vec![];
+        crate::mapped::write_to(&json, &mut mapped).unwrap();
+        for (bytes, num_feature) in &[
+            (JSON_MODEL.as_bytes(), 2),
+            (&binary[..], 1),
+            (&mapped[..], 2),
+        ] {
+            let predictor = Predictor::read_any(&mut &bytes[..]).unwrap();
+            assert_eq!(predictor.model_num_feature(), *num_feature);
+        }
+        let feats = arr2(&[[40.0, 2.0]]);
+        assert_eq!(
+            Predictor::read_any(&mut &mapped[..])
+                .unwrap()
+                .predict_many(feats.view(), true, 0)
+                .unwrap(),
+            json.predict_many(feats.view(), true, 0).unwrap()
+        );
+        assert!(Predictor::read_any(&mut &mapped[..4]).is_err());
+    }
+
+    #[test]
+    fn test_to_bytes() {
+        let feats = random_matrix(5, 20, 4, 0.2);
+        let dart = gbtree_model(
+            "binary:logistic",
+            0.5,
+            4,
+            1,
+            &[stump(0, -1.0, 1.0), stump(0, 0.5, -0.5)],
+            Some(&[0.5, 0.25]),
+        );
+        let models = [
+            load_model(&random_gbtree_model(6, "binary:logistic", 4, 1, 8, 4)),
+            load_model(&random_gbtree_model(7, "multi:softprob", 4, 3, 9, 5)),
+            load_model(&dart),
+            load_model(&gblinear_model(
+                "reg:linear",
+                0.5,
+                4,
+                1,
+                &[1.0, -1.0, 2.0, 0.5, 0.25],
+            )),
+        ];
+        let json = load_model(JSON_MODEL.as_bytes());
+        let json_feats = arr2(&[[20.0, 1.0], [40.0, 2.0], [40.0, FeatureValue::NAN]]);
+        let cases = models
+            .iter()
+            .map(|predictor| (predictor, feats.view()))
+            .chain(Some((&json, json_feats.view())));
+        for (predictor, feats) in cases {
+            let copy = Predictor::from_bytes(&predictor.to_bytes().unwrap()).unwrap();
+            assert_eq!(copy.feature_names(), predictor.feature_names());
+            assert_eq!(
+                copy.predict_many(feats, false, 0).unwrap(),
+                predictor.predict_many(feats, false, 0).unwrap()
+            );
+            assert_eq!(
+                copy.predict_contribs(feats, 0).unwrap(),
+                predictor.predict_contribs(feats, 0).unwrap()
+            );
+            assert_eq!(
+                crate::codegen::generate(&copy).unwrap(),
+                crate::codegen::generate(predictor).unwrap()
+            );
+            if let BoosterModel::Linear(_) = predictor.booster() {
+                assert!(matches!(copy.booster(), BoosterModel::Linear(_)));
+                continue;
+            }
+            assert!(matches!(copy.booster(), BoosterModel::Trees(_)));
+            assert_eq!(
+                copy.predict_leaf(feats, 0).unwrap(),
+                predictor.predict_leaf(feats, 0).unwrap()
+            );
+            let dump = |predictor: &Predictor| {
+                crate::dump::dump_model(predictor, None, true, crate::dump::DumpFormat::Text)
+                    .unwrap()
+            };
+            assert_eq!(dump(&copy), dump(predictor));
+        }
+
+        // mapped models keep the node ids and statistics, compact models are read back as
+        // mapped models
+        let bytes = json.to_bytes().unwrap();
+        let mapped = crate::mapped::from_bytes(&bytes).unwrap();
+        assert_eq!(mapped.to_bytes().unwrap(), bytes);
+        let mut compact = load_model(&dart);
+        compact.compact().unwrap();
+        let copy = Predictor::from_bytes(&compact.to_bytes().unwrap()).unwrap();
+        assert!(matches!(copy.booster(), BoosterModel::Mapped(_)));
+        assert_eq!(
+            copy.predict_many(feats.view(), true, 0).unwrap(),
+            compact.predict_many(feats.view(), true, 0).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_predict_named() {
+        let predictor = load_model(JSON_MODEL.as_bytes());
+        let row: HashMap<&str, FeatureValue> =
+            [("color", 2.0), ("age", 40.0)].iter().cloned().collect();
+        let preds = predictor.predict_named(&[row], true, 0).unwrap();
+        assert_eq!(preds, vec![vec![-1.0]]);
+
+        let row: HashMap<&str, FeatureValue> = [("colour", 2.0)].iter().cloned().collect();
+        match predictor.predict_named(&[row], true, 0).unwrap_err().kind() {
+            ErrorKind::FeatureMismatch(missing, unexpected) => {
+                assert_eq!(missing, &[String::from("age"), String::from("color")]);
+                assert_eq!(unexpected, &[String::from("colour")]);
+            }
+            kind => panic!("unexpected error {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn test_column_order() {
+        let mut predictor = load_model(JSON_MODEL.as_bytes());
+        assert_eq!(
+            predictor.column_order(&["color", "age"]).unwrap(),
+            vec![Some(1), Some(0)]
+        );
+        match predictor
+            .column_order(&["weight", "age", "colour"])
+            .unwrap_err()
+            .kind()
+        {
+            ErrorKind::FeatureMismatch(missing, unexpected) => {
+                assert_eq!(missing, &[String::from("color")]);
+                assert_eq!(
+                    unexpected,
+                    &[String::from("weight"), String::from("colour")]
+                );
+            }
+            kind => panic!("unexpected error {:?}", kind),
+        }
+        match predictor
+            .column_order(&["age", "color", "age"])
+            .unwrap_err()
+            .kind()
+        {
+            ErrorKind::InvalidArgument(message) => assert_eq!(message, "duplicate column 'age'"),
+            kind => panic!("unexpected error {:?}", kind),
+        }
+        let names = vec![String::from("age"), String::from("age")];
+        match predictor.set_feature_names(names).unwrap_err().kind() {
+            ErrorKind::InvalidArgument(message) => {
+                assert_eq!(message, "duplicate feature name 'age'")
+            }
+            kind => panic!("unexpected error {:?}", kind),
+        }
+
+        let weights = [1.0, 2.0, 0.5];
+        let mut predictor = load_model(&gblinear_model("reg:linear", 0.0, 2, 1, &weights));
+        assert_eq!(
+            predictor.column_order(&["b", "a"]).unwrap(),
+            vec![Some(0), Some(1)]
+        );
+        assert!(predictor.column_order(&["a"]).is_err());
+        predictor.set_shape_policy(ShapePolicy::PadMissing);
+        assert_eq!(predictor.column_order(&["a"]).unwrap(), vec![Some(0), None]);
+    }
+
+    #[test]
+    fn test_shape_policy() {
+        let weights = [1.0, 2.0, 0.5];
+        let mut predictor = load_model(&gblinear_model("reg:linear", 0.0, 2, 1, &weights));
+        let narrow = arr2(&[[1.0]]);
+        let wide = arr2(&[[1.0, 1.0, 1.0]]);
+
+        for feats in &[narrow.view(), wide.view()] {
+            match predictor.predict_many(*feats, true, 0).unwrap_err().kind() {
+                ErrorKind::InvalidInputShape(expected, actual) => {
+                    assert_eq!((*expected, *actual), (2, feats.ncols()))
+                }
+                kind => panic!("unexpected error {:?}", kind),
+            }
+        }
+
+        predictor.set_shape_policy(ShapePolicy::AllowExtra);
+        assert_eq!(
+            predictor.predict_many(wide.view(), true, 0).unwrap(),
+            vec![vec![3.5]]
+        );
+        assert!(predictor.predict_many(narrow.view(), true, 0).is_err());
+
+        predictor.set_shape_policy(ShapePolicy::PadMissing);
+        // padded columns are missing, which gblinear skips
+        let padded = predictor.predict_many(narrow.view(), true, 0).unwrap();
+        assert_eq!(padded, vec![vec![1.5]]);
+        assert!(predictor.predict_many(wide.view(), true, 0).is_err());
+
+        assert_eq!(
+            ShapePolicy::from_name("pad_missing").unwrap(),
+            ShapePolicy::PadMissing
+        );
+        match ShapePolicy::from_name("pad").unwrap_err().kind() {
+            ErrorKind::InvalidArgument(message) => assert_eq!(
+                message,
+                "shape policy pad, expected strict, allow_extra or pad_missing"
+            ),
+            kind => panic!("unexpected error {:?}", kind),
+        }
+    }
+
+    #[test]
+    fn test_predict_many_threads() {
+        let mut predictor = load_model(&random_gbtree_model(7, "multi:softprob", 6, 3, 20, 5));
+        let feats = random_matrix(11, 1000, 6, 0.1);
+        predictor.set_nthread(1).unwrap();
+        let serial = predictor.predict_many(feats.view(), false, 0).unwrap();
+        for nthread in &[0, 2, 4] {
+            predictor.set_nthread(*nthread).unwrap();
+            assert_eq!(predictor.nthread(), *nthread);
+            assert_eq!(
+                predictor.predict_many(feats.view(), false, 0).unwrap(),
+                serial
+            );
+        }
+    }
+
+    #[test]
+    fn test_f64_input_precision() {
+        // epoch milliseconds one millisecond before and after a split condition, the f32 values
+        // are 131072 ms apart at this magnitude
+        let threshold = 1.7e12f32;
+        let trees = [TestTree::new(
+            0,
+            vec![
+                TestNode::Split(0, threshold, 1, 2, false),
+                TestNode::Leaf(-1.0),
+                TestNode::Leaf(1.0),
+            ],
+        )];
+        let predictor = load_model(&gbtree_model("reg:linear", 0.0, 1, 1, &trees, None));
+        let feats = arr2(&[[f64::from(threshold) - 1.0], [f64::from(threshold) + 1.0]]);
+        let preds = predictor
+            .predict_many_converted(feats.view(), true, 0)
+            .unwrap();
+        if cfg!(feature = "use_f64") {
+            assert_eq!(preds, vec![vec![-1.0], vec![1.0]]);
         } else {
-            Ok(preds)
+            // both values round to the condition
+            assert_eq!(preds, vec![vec![1.0], vec![1.0]]);
         }
     }
+
+    #[test]
+    fn test_predict_many_converted() {
+        let mut predictor = load_model(&random_gbtree_model(13, "multi:softprob", 6, 3, 20, 5));
+        // integral values on both sides of the split conditions
+        let feats = random_matrix(17, 700, 6, 0.0).mapv(|value| (value * 4.0).floor());
+        let expected = predictor.predict_many(feats.view(), true, 0).unwrap();
+
+        let wide = feats.mapv(f64::from);
+        assert_eq!(
+            predictor
+                .predict_many_converted(wide.view(), true, 0)
+                .unwrap(),
+            expected
+        );
+        let integers = feats.mapv(|value| value as i64);
+        assert_eq!(
+            predictor
+                .predict_many_converted(integers.view(), true, 0)
+                .unwrap(),
+            expected
+        );
+        let fortran = feats.t().as_standard_layout().into_owned();
+        assert_eq!(
+            predictor
+                .predict_many_converted(fortran.t(), true, 0)
+                .unwrap(),
+            expected
+        );
+        let mut strided = Array2::from_elem((700, 12), -1i32);
+        strided
+            .slice_mut(s![.., ..;2])
+            .assign(&feats.mapv(|value| value as i32));
+        assert_eq!(
+            predictor
+                .predict_many_converted(strided.slice(s![.., ..;2]), true, 0)
+                .unwrap(),
+            expected
+        );
+
+        assert!(predictor
+            .predict_many_converted(strided.view(), true, 0)
+            .is_err());
+        predictor.set_shape_policy(ShapePolicy::AllowExtra);
+        let extra = predictor
+            .predict_many_converted(strided.view(), true, 0)
+            .unwrap();
+        assert_eq!(extra.len(), 700);
+    }
 }
@@ -0,0 +1,90 @@
+//! Python module, built with the `python` feature
+use std::{fs, io};
+
+use pyo3::prelude::*;
+use pyo3::{wrap_pyfunction, PyErr};
+
+use crate::predictor::Predictor;
+use crate::{estimators, exceptions, mapped, wrapper};
+
+/// Loads a model in the binary or JSON format of xgboost, or in the format of `save_mmap`, from
+/// a path or from a file-like object with a `read` method
+#[pyfunction]
+fn load_model(model_path: &PyAny) -> PyResult<wrapper::PredictorWrapper> {
+    if model_path.hasattr("read")? {
+        let mut reader = io::BufReader::new(wrapper::PyFileReader { file: model_path });
+        return match Predictor::read_any(&mut reader) {
+            Ok(predictor) => Ok(wrapper::PredictorWrapper { predictor }),
+            Err(error) => Err(exceptions::to_py_err(error)),
+        };
+    }
+    // str or os.PathLike
+    let model_path: String = if model_path.hasattr("__fspath__")? {
+        model_path.call_method0("__fspath__")?.extract()?
+    } else {
+        model_path.extract()?
+    };
+    let mut model_file = match fs::File::open(&model_path) {
+        Ok(file) => file,
+        Err(error) => match error.kind() {
+            io::ErrorKind::NotFound => {
+                return Err(PyErr::new::<pyo3::exceptions::PyFileNotFoundError, _>(
+                    format!("File not found: {}", model_path),
+                ))
+            }
+            _ => {
+                return Err(PyErr::new::<pyo3::exceptions::PyOSError, _>(format!(
+                    "Unexpected error, when open file: {}",
+                    error
+                )))
+            }
+        },
+    };
+    match Predictor::read_any::<fs::File>(&mut model_file) {
+        Ok(predictor) => Ok(wrapper::PredictorWrapper { predictor }),
+        Err(error) => Err(exceptions::to_py_err(error)),
+    }
+}
+
+/// Loads a model from bytes in any of the formats read by `load_model`
+#[pyfunction]
+fn load_model_from_bytes(data: &[u8]) -> PyResult<wrapper::PredictorWrapper> {
+    match Predictor::read_any(&mut &data[..]) {
+        Ok(predictor) => Ok(wrapper::PredictorWrapper { predictor }),
+        Err(error) => Err(exceptions::to_py_err(error)),
+    }
+}
+
+/// Maps a model written by `PredictorWrapper.save_mmap`, processes mapping the same file share
+/// its memory
+#[pyfunction]
+fn load_mmap_model(model_path: &str) -> PyResult<wrapper::PredictorWrapper> {
+    match mapped::load(model_path) {
+        Ok(predictor) => Ok(wrapper::PredictorWrapper { predictor }),
+        Err(error) => Err(exceptions::to_py_err(error)),
+    }
+}
+
+/// Unpickles a predictor, see `PredictorWrapper.__reduce__`
+#[pyfunction]
+#[name = "_restore_predictor"]
+fn restore_predictor(
+    bytes: &[u8],
+    settings: wrapper::PickledSettings,
+) -> PyResult<wrapper::PredictorWrapper> {
+    wrapper::from_state(bytes, settings).map_err(exceptions::to_py_err)
+}
+
+#[pymodule]
+fn xgboost_predictor(py: Python, m: &PyModule) -> PyResult<()> {
+    m.add_function(wrap_pyfunction!(load_model, m)?)?;
+    m.add_function(wrap_pyfunction!(load_model_from_bytes, m)?)?;
+    m.add_function(wrap_pyfunction!(load_mmap_model, m)?)?;
+    m.add_function(wrap_pyfunction!(restore_predictor, m)?)?;
+    m.add_class::<wrapper::PredictorWrapper>()?;
+    m.add_class::<estimators::XGBPredictorClassifier>()?;
+    m.add_class::<estimators::XGBPredictorRegressor>()?;
+    exceptions::register(py, m)?;
+
+    Ok(())
+}
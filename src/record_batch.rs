@@ -0,0 +1,249 @@
+//! Prediction over Apache Arrow record batches
+use std::sync::Arc;
+
+use arrow::array::{Array, ArrayRef, AsArray, FixedSizeListArray, Float32Array};
+use arrow::compute::cast;
+#[cfg(not(feature = "use_f64"))]
+use arrow::datatypes::Float32Type as ValueType;
+#[cfg(feature = "use_f64")]
+use arrow::datatypes::Float64Type as ValueType;
+use arrow::datatypes::{ArrowPrimitiveType, DataType, Field};
+use arrow::record_batch::RecordBatch;
+use ndarray::Array2;
+
+use crate::errors::*;
+use crate::predictor::Predictor;
+use crate::FeatureValue;
+
+/// Converts a column to the type of `FeatureValue`. Dictionary columns are replaced by their keys, i.e. the
+/// category codes, numeric and boolean columns are cast.
+fn column_values(column: &ArrayRef) -> Result<ArrayRef> {
+    let values = match column.data_type() {
+        DataType::Dictionary(_, _) => {
+            cast(column.as_any_dictionary().keys(), &ValueType::DATA_TYPE)?
+        }
+        data_type if data_type.is_numeric() || *data_type == DataType::Boolean => {
+            cast(column, &ValueType::DATA_TYPE)?
+        }
+        data_type => {
+            return Err(Error::from_kind(ErrorKind::UnsupportedDataType(format!(
+                "{}",
+                data_type
+            ))))
+        }
+    };
+    Ok(values)
+}
+
+/// Copies the columns of the batch into a dense row-major matrix, in the order of the batch.
+/// Null values are treated as missing.
+pub fn batch_to_matrix(batch: &RecordBatch) -> Result<Array2<FeatureValue>> {
+    let columns: Vec<Option<usize>> = (0..batch.num_columns()).map(Some).collect();
+    batch_columns_to_matrix(batch, &columns)
+}
+
+/// Copies the given columns of the batch into a dense row-major matrix, `None` giving a column
+/// of missing values, as `Predictor::column_order` maps the features of a model to columns
+pub fn batch_columns_to_matrix(
+    batch: &RecordBatch,
+    columns: &[Option<usize>],
+) -> Result<Array2<FeatureValue>> {
+    let mut feats = Array2::from_elem((batch.num_rows(), columns.len()), FeatureValue::NAN);
+    for (fid, column) in columns.iter().enumerate() {
+        let column = match column {
+            Some(column) => batch.column(*column),
+            None => continue,
+        };
+        let values = column_values(column)?;
+        let values = values.as_primitive::<ValueType>();
+        for (row, value) in values.iter().enumerate() {
+            if let Some(value) = value {
+                feats[[row, fid]] = value;
+            }
+        }
+    }
+    Ok(feats)
+}
+
+/// Builds an Arrow array from the output of `Predictor::predict_many`, rows holding `width`
+/// values as given by `Predictor::num_output`: a `Float32Array` when the width is 1, a
+/// `FixedSizeListArray` of the output groups otherwise
+pub fn preds_to_array(preds: Vec<Vec<f32>>, width: usize) -> Result<ArrayRef> {
+    let values = Float32Array::from(preds.into_iter().flatten().collect::<Vec<f32>>());
+    if width == 1 {
+        return Ok(Arc::new(values));
+    }
+    let field = Arc::new(Field::new("item", DataType::Float32, false));
+    let array = FixedSizeListArray::try_new(field, width as i32, Arc::new(values), None)?;
+    Ok(Arc::new(array))
+}
+
+impl Predictor {
+    /// Copies the columns of the batch into a matrix of the features of the model. Columns are
+    /// matched to the feature names when the model has them, and taken in order otherwise.
+    pub fn record_batch_to_matrix(&self, batch: &RecordBatch) -> Result<Array2<FeatureValue>> {
+        let schema = batch.schema();
+        let names: Vec<&str> = schema
+            .fields()
+            .iter()
+            .map(|field| field.name().as_str())
+            .collect();
+        batch_columns_to_matrix(batch, &self.column_order(&names)?)
+    }
+
+    /// Generates predictions for the rows of a record batch, with its columns aligned as by
+    /// `record_batch_to_matrix`
+    pub fn predict_record_batch(
+        &self,
+        batch: &RecordBatch,
+        output_margin: bool,
+        ntree_limit: usize,
+    ) -> Result<ArrayRef> {
+        let feats = self.record_batch_to_matrix(batch)?;
+        let preds = self.predict_many(feats.view(), output_margin, ntree_limit)?;
+        preds_to_array(preds, self.num_output(output_margin))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use arrow::array::{
+        Array, AsArray, DictionaryArray, Float32Array, Float64Array, Int32Array, Int64Array,
+        StringArray,
+    };
+    use arrow::datatypes::{DataType, Float32Type, Int8Type};
+    use arrow::record_batch::RecordBatch;
+
+    use crate::errors::ErrorKind;
+    use crate::predictor::Predictor;
+    use crate::testing::{gbtree_model, load_model, TestNode, TestTree, JSON_MODEL};
+
+    use super::batch_to_matrix;
+
+    #[test]
+    fn test_batch_to_matrix() {
+        let batch = RecordBatch::try_from_iter(vec![
+            (
+                "a",
+                Arc::new(Float64Array::from(vec![Some(1.5), None, Some(3.0)])) as _,
+            ),
+            ("b", Arc::new(Int64Array::from(vec![1, 2, 3])) as _),
+            (
+                "c",
+                Arc::new(
+                    vec![Some("x"), Some("y"), None]
+                        .into_iter()
+                        .collect::<DictionaryArray<Int8Type>>(),
+                ) as _,
+            ),
+        ])
+        .unwrap();
+        let feats = batch_to_matrix(&batch).unwrap();
+        assert_eq!(feats.shape(), &[3, 3]);
+        assert_eq!(feats[[0, 0]], 1.5);
+        assert!(feats[[1, 0]].is_nan());
+        assert_eq!(feats[[2, 1]], 3.0);
+        assert_eq!(feats[[0, 2]], 0.0);
+        assert_eq!(feats[[1, 2]], 1.0);
+        assert!(feats[[2, 2]].is_nan());
+
+        let batch =
+            RecordBatch::try_from_iter(vec![("s", Arc::new(StringArray::from(vec!["x"])) as _)])
+                .unwrap();
+        assert!(batch_to_matrix(&batch).is_err());
+    }
+
+    #[test]
+    fn test_predict_record_batch() {
+        let tree = TestTree::new(
+            0,
+            vec![
+                TestNode::Split(0, 0.5, 1, 2, true),
+                TestNode::Leaf(-1.0),
+                TestNode::Leaf(1.0),
+            ],
+        );
+        let predictor = load_model(&gbtree_model("reg:linear", 0.5, 1, 1, &[tree], None));
+        let batch = RecordBatch::try_from_iter(vec![(
+            "f0",
+            Arc::new(Int32Array::from(vec![Some(0), Some(1), None])) as _,
+        )])
+        .unwrap();
+        let preds = predictor.predict_record_batch(&batch, false, 0).unwrap();
+        let preds = preds.as_primitive::<Float32Type>();
+        assert_eq!(preds, &Float32Array::from(vec![-0.5, 1.5, -0.5]));
+        assert_eq!(preds.null_count(), 0);
+    }
+
+    #[test]
+    fn test_predict_empty_record_batch() {
+        let trees: Vec<TestTree> = (0..2)
+            .map(|group| TestTree::new(group, vec![TestNode::Leaf(1.0)]))
+            .collect();
+        let batch = RecordBatch::try_from_iter(vec![(
+            "f0",
+            Arc::new(Int32Array::from(Vec::<i32>::new())) as _,
+        )])
+        .unwrap();
+        for (obj, output_margin, data_type) in &[
+            (
+                "multi:softprob",
+                false,
+                DataType::new_fixed_size_list(DataType::Float32, 2, false),
+            ),
+            ("multi:softmax", false, DataType::Float32),
+            (
+                "multi:softmax",
+                true,
+                DataType::new_fixed_size_list(DataType::Float32, 2, false),
+            ),
+        ] {
+            let predictor = load_model(&gbtree_model(obj, 0.5, 1, 2, &trees, None));
+            let preds = predictor
+                .predict_record_batch(&batch, *output_margin, 0)
+                .unwrap();
+            assert_eq!(preds.len(), 0);
+            assert_eq!(preds.data_type(), data_type);
+        }
+    }
+
+    #[test]
+    fn test_record_batch_columns_by_name() {
+        let predictor = Predictor::from_json(JSON_MODEL.as_bytes()).unwrap();
+        let age = Arc::new(Float64Array::from(vec![20.0, 40.0, 40.0])) as _;
+        let color = Arc::new(Int32Array::from(vec![1, 1, 2])) as _;
+        let ordered = RecordBatch::try_from_iter(vec![
+            ("age", Arc::clone(&age)),
+            ("color", Arc::clone(&color)),
+        ])
+        .unwrap();
+        let reordered = RecordBatch::try_from_iter(vec![("color", color), ("age", age)]).unwrap();
+        let expected = predictor.predict_record_batch(&ordered, true, 0).unwrap();
+        let preds = predictor.predict_record_batch(&reordered, true, 0).unwrap();
+        assert_eq!(
+            preds.as_primitive::<Float32Type>(),
+            &Float32Array::from(vec![0.25, 1.0, -1.0])
+        );
+        assert_eq!(&preds, &expected);
+
+        let renamed = RecordBatch::try_from_iter(vec![
+            ("colour", Arc::new(Int32Array::from(vec![1])) as _),
+            ("age", Arc::new(Float64Array::from(vec![20.0])) as _),
+        ])
+        .unwrap();
+        match predictor.predict_record_batch(&renamed, true, 0) {
+            Err(error) => match error.kind() {
+                ErrorKind::FeatureMismatch(missing, unexpected) => {
+                    assert_eq!(
+                        (missing, unexpected),
+                        (&vec![String::from("color")], &vec![String::from("colour")])
+                    );
+                }
+                kind => panic!("unexpected error {:?}", kind),
+            },
+            Ok(_) => panic!("the batch is predicted"),
+        }
+    }
+}
@@ -0,0 +1,258 @@
+//! Source generation shared by `codegen` and `c_export`: the walk of the trees into nested
+//! `if` statements returning the leaf values, and the parts of the generated programs which
+//! only differ by the syntax of the language.
+use std::fmt::Write;
+
+use crate::errors::*;
+use crate::gbm::flat_tree::{FlatNode, FlatTree};
+use crate::gbm::gbtree::GBTree;
+use crate::gbm::grad_booster::BoosterModel;
+use crate::predictor::Predictor;
+
+/// Syntax of a generated language
+pub trait Syntax {
+    /// Condition which always holds
+    const TRUE: &'static str;
+
+    /// Float literal giving back the same bits
+    fn f32_literal(value: f32) -> String;
+
+    /// Opening line of an `if` statement
+    fn if_statement(condition: &str) -> String;
+
+    /// Expression testing whether the feature value is missing
+    fn is_nan(fvalue: &str) -> String;
+
+    /// Expression testing whether the feature value is one of the categories of the split
+    /// `nid` of tree `tid`, which are not empty
+    fn is_category(
+        fvalue: &str,
+        tree: &FlatTree,
+        tid: usize,
+        nid: usize,
+        categories: usize,
+    ) -> String;
+}
+
+fn indent(depth: usize) -> String {
+    "    ".repeat(depth)
+}
+
+/// Number of output groups of a model whose trees or weights can be written
+pub fn num_output_group(predictor: &Predictor, method: &str) -> Result<usize> {
+    match predictor.booster() {
+        BoosterModel::Trees(gbtree) => Ok(gbtree.num_output_group()),
+        BoosterModel::Linear(gblinear) => Ok(gblinear.num_output_group()),
+        BoosterModel::Compact(_) | BoosterModel::Mapped(_) => {
+            Err(Error::from_kind(ErrorKind::UnsupportedPredictionMethod(
+                String::from(method),
+                String::from("Detail: compact and mapped models can not be exported"),
+            )))
+        }
+    }
+}
+
+/// Whether a tree has a categorical split with categories, which needs an `is_category` helper
+pub fn has_categorical(gbtree: &GBTree) -> bool {
+    gbtree.trees().iter().any(|tree| {
+        let tree = tree.flat();
+        (0..tree.num_nodes()).any(|nid| match tree.node(nid) {
+            FlatNode::CategoricalSplit { categories, .. } => {
+                !tree.categories(categories).is_empty()
+            }
+            _ => false,
+        })
+    })
+}
+
+/// Checks that the trees only test the `num_feature` features of the generated functions
+pub fn check_split_indices(gbtree: &GBTree, num_feature: usize) -> Result<()> {
+    for tree in gbtree.trees() {
+        if let Some(split_index) = tree
+            .flat()
+            .max_split_index()
+            .filter(|index| *index >= num_feature)
+        {
+            return Err(Error::from_kind(ErrorKind::UnavailableDataIndex(
+                split_index,
+            )));
+        }
+    }
+    Ok(())
+}
+
+/// Output group and term of each tree in the sum of the margins, in model order as the
+/// interpreter sums them
+pub fn margin_terms<S: Syntax>(gbtree: &GBTree) -> Vec<(i32, String)> {
+    gbtree
+        .tree_info()
+        .iter()
+        .enumerate()
+        .map(|(tid, group)| {
+            let term = match gbtree.weight_drop() {
+                Some(weight_drop) => {
+                    format!(
+                        "{} * tree_{}(features)",
+                        S::f32_literal(weight_drop[tid]),
+                        tid
+                    )
+                }
+                None => format!("tree_{}(features)", tid),
+            };
+            (*group, term)
+        })
+        .collect()
+}
+
+/// Writes the subtree of `nid` as statements returning the leaf value
+pub fn write_node<S: Syntax>(
+    out: &mut String,
+    tree: &FlatTree,
+    tid: usize,
+    nid: usize,
+    depth: usize,
+) {
+    let (cleft, cright, go_left) = match tree.node(nid) {
+        FlatNode::Leaf(value) => {
+            writeln!(out, "{}return {};", indent(depth), S::f32_literal(value)).unwrap();
+            return;
+        }
+        FlatNode::Split {
+            cleft,
+            cright,
+            split_index,
+            split_cond,
+            default_left,
+        } => {
+            let fvalue = format!("features[{}]", split_index);
+            let condition = if default_left {
+                format!(
+                    "{} < {} || {}",
+                    fvalue,
+                    S::f32_literal(split_cond),
+                    S::is_nan(&fvalue)
+                )
+            } else {
+                format!("{} < {}", fvalue, S::f32_literal(split_cond))
+            };
+            (cleft, cright, condition)
+        }
+        FlatNode::CategoricalSplit {
+            cleft,
+            cright,
+            split_index,
+            default_left,
+            categories,
+        } => {
+            let fvalue = format!("features[{}]", split_index);
+            let condition = if tree.categories(categories).is_empty() {
+                // no category goes right, only missing values can
+                if default_left {
+                    String::from(S::TRUE)
+                } else {
+                    format!("!{}", S::is_nan(&fvalue))
+                }
+            } else {
+                let is_category = S::is_category(&fvalue, tree, tid, nid, categories);
+                if default_left {
+                    format!("{} || !{}", S::is_nan(&fvalue), is_category)
+                } else {
+                    format!("!{} && !{}", S::is_nan(&fvalue), is_category)
+                }
+            };
+            (cleft, cright, condition)
+        }
+    };
+    writeln!(out, "{}{}", indent(depth), S::if_statement(&go_left)).unwrap();
+    write_node::<S>(out, tree, tid, cleft, depth + 1);
+    writeln!(out, "{}}} else {{", indent(depth)).unwrap();
+    write_node::<S>(out, tree, tid, cright, depth + 1);
+    writeln!(out, "{}}}", indent(depth)).unwrap();
+}
+
+#[cfg(test)]
+pub mod fixtures {
+    use ndarray::Array2;
+
+    use crate::predictor::Predictor;
+    use crate::testing::{
+        gbtree_model, load_model, random_gbtree_model, random_matrix, random_trees, JSON_MODEL,
+    };
+    use crate::FeatureValue;
+
+    /// Models covered by the generators with their input: multiclass, dart, linear and
+    /// categorical models
+    pub fn models() -> Vec<(&'static str, Predictor, Array2<FeatureValue>)> {
+        let feats = random_matrix(31, 50, 6, 0.2);
+        let mut models = vec![
+            (
+                "softprob",
+                load_model(&random_gbtree_model(1, "multi:softprob", 6, 3, 10, 5)),
+                feats.clone(),
+            ),
+            (
+                "softmax",
+                load_model(&random_gbtree_model(2, "multi:softmax", 6, 3, 10, 8)),
+                feats.clone(),
+            ),
+            (
+                "dart",
+                load_model(&gbtree_model(
+                    "binary:logistic",
+                    0.25,
+                    6,
+                    1,
+                    &random_trees(3, 6, 1, 20, 6),
+                    Some(&[0.5; 20]),
+                )),
+                feats.clone(),
+            ),
+        ];
+        // linear products are summed in f64 by the interpreter
+        #[cfg(not(feature = "use_f64"))]
+        models.push((
+            "gblinear",
+            load_model(&crate::testing::gblinear_model(
+                "reg:linear",
+                0.5,
+                6,
+                2,
+                &(0..14)
+                    .map(|i| i as f32 / 7f32 - 1f32)
+                    .collect::<Vec<f32>>(),
+            )),
+            feats,
+        ));
+        let nan = FeatureValue::NAN;
+        models.push((
+            "categorical",
+            load_model(JSON_MODEL.as_bytes()),
+            Array2::from_shape_vec(
+                (6, 2),
+                vec![
+                    20.0, 1.0, 40.0, 1.0, 40.0, 3.0, 40.0, 2.0, 40.0, -1.0, 40.0, nan,
+                ],
+            )
+            .unwrap(),
+        ));
+        models
+    }
+
+    /// Bits of the margins followed by the predictions of each row, in hexadecimal, as the
+    /// programs compiled by the tests print them
+    pub fn expected_output(predictor: &Predictor, feats: &Array2<FeatureValue>) -> Vec<String> {
+        let margins = predictor.predict_many(feats.view(), true, 0).unwrap();
+        let preds = predictor.predict_many(feats.view(), false, 0).unwrap();
+        margins
+            .iter()
+            .zip(&preds)
+            .map(|(margins, preds)| {
+                margins
+                    .iter()
+                    .chain(preds)
+                    .map(|value| format!("{:08x} ", value.to_bits()))
+                    .collect()
+            })
+            .collect()
+    }
+}
@@ -0,0 +1,368 @@
+//! Builders of xgboost binary models and input data used by the unit tests and, with the
+//! `testing` feature, by the benchmarks.
+#![allow(dead_code)]
+
+use byteorder::{WriteBytesExt, LE};
+use ndarray::Array2;
+
+use crate::model_reader::ModelReader;
+use crate::predictor::Predictor;
+use crate::FeatureValue;
+
+#[derive(Clone, Copy)]
+pub enum TestNode {
+    Leaf(f32),
+    /// split feature index, split condition, left child, right child, default left
+    Split(u32, f32, i32, i32, bool),
+}
+
+pub struct TestTree {
+    /// output group of the tree
+    pub group: i32,
+    pub nodes: Vec<TestNode>,
+    /// sum of hessian of each node
+    pub covers: Vec<f32>,
+}
+
+impl TestTree {
+    pub fn new(group: i32, nodes: Vec<TestNode>) -> TestTree {
+        let covers = vec![1f32; nodes.len()];
+        TestTree {
+            group,
+            nodes,
+            covers,
+        }
+    }
+
+    /// Reference traversal of the tree, missing values follow the default direction
+    pub fn leaf_value(&self, feat: &[FeatureValue]) -> f32 {
+        let mut nid = 0;
+        loop {
+            match self.nodes[nid] {
+                TestNode::Leaf(value) => return value,
+                TestNode::Split(index, cond, left, right, default_left) => {
+                    let fvalue = feat[index as usize];
+                    let go_left = if fvalue.is_nan() {
+                        default_left
+                    } else {
+                        fvalue < FeatureValue::from(cond)
+                    };
+                    nid = if go_left { left } else { right } as usize;
+                }
+            }
+        }
+    }
+}
+
+/// Deterministic pseudo random numbers for the generated models
+pub struct Lcg(u64);
+
+impl Lcg {
+    pub fn new(seed: u64) -> Lcg {
+        Lcg(seed
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407))
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        self.0 = self
+            .0
+            .wrapping_mul(6364136223846793005)
+            .wrapping_add(1442695040888963407);
+        (self.0 >> 33) as u32
+    }
+
+    /// uniform value in [0, 1)
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1u32 << 23) as f32
+    }
+}
+
+fn write_str(buf: &mut Vec<u8>, value: &str) {
+    buf.write_i64::<LE>(value.len() as i64).unwrap();
+    buf.extend_from_slice(value.as_bytes());
+}
+
+/// Size of the learner parameters starting a binary model: base_score, num_feature, num_class,
+/// saved_with_pbuffer and 30 reserved fields
+pub const LEARNER_PARAM_SIZE: usize = 136;
+
+fn write_header(buf: &mut Vec<u8>, obj: &str, gbm: &str, base_score: f32, num_feature: usize) {
+    buf.write_f32::<LE>(base_score).unwrap();
+    buf.write_i32::<LE>(num_feature as i32).unwrap();
+    // num_class, saved_with_pbuffer and reserved fields
+    for _ in 0..32 {
+        buf.write_i32::<LE>(0).unwrap();
+    }
+    debug_assert_eq!(buf.len(), LEARNER_PARAM_SIZE);
+    write_str(buf, obj);
+    write_str(buf, gbm);
+}
+
+/// Moves the reader of a binary model past the learner parameters and the objective and
+/// booster names, to the booster
+pub fn skip_to_booster(reader: &mut &[u8]) {
+    reader.skip(LEARNER_PARAM_SIZE).unwrap();
+    for _ in 0..2 {
+        let len = reader.read_i64_as_usize().unwrap();
+        reader.skip(len).unwrap();
+    }
+}
+
+fn write_tree(buf: &mut Vec<u8>, tree: &TestTree, num_feature: usize) {
+    let nodes = &tree.nodes;
+    let mut parents = vec![-1i32; nodes.len()];
+    for (nid, node) in nodes.iter().enumerate() {
+        if let TestNode::Split(_, _, left, right, _) = *node {
+            // broken trees may point outside of the nodes
+            if let Some(parent) = parents.get_mut(left as usize) {
+                *parent = nid as i32 | (1 << 31);
+            }
+            if let Some(parent) = parents.get_mut(right as usize) {
+                *parent = nid as i32;
+            }
+        }
+    }
+    for value in &[1, nodes.len() as i32, 0, 0, num_feature as i32, 0] {
+        buf.write_i32::<LE>(*value).unwrap();
+    }
+    for _ in 0..31 {
+        buf.write_i32::<LE>(0).unwrap();
+    }
+    for (nid, node) in nodes.iter().enumerate() {
+        buf.write_i32::<LE>(parents[nid]).unwrap();
+        match *node {
+            TestNode::Leaf(value) => {
+                buf.write_i32::<LE>(-1).unwrap();
+                buf.write_i32::<LE>(-1).unwrap();
+                buf.write_i32::<LE>(0).unwrap();
+                buf.write_f32::<LE>(value).unwrap();
+            }
+            TestNode::Split(index, cond, left, right, default_left) => {
+                buf.write_i32::<LE>(left).unwrap();
+                buf.write_i32::<LE>(right).unwrap();
+                let sindex = if default_left {
+                    index | (1 << 31)
+                } else {
+                    index
+                };
+                buf.write_u32::<LE>(sindex).unwrap();
+                buf.write_f32::<LE>(cond).unwrap();
+            }
+        }
+    }
+    for (nid, node) in nodes.iter().enumerate() {
+        let (loss_chg, base_weight) = match *node {
+            TestNode::Leaf(value) => (0f32, value),
+            TestNode::Split(_, cond, _, _, _) => (cond.abs() + 1f32, 0f32),
+        };
+        buf.write_f32::<LE>(loss_chg).unwrap();
+        buf.write_f32::<LE>(tree.covers[nid]).unwrap();
+        buf.write_f32::<LE>(base_weight).unwrap();
+        buf.write_i32::<LE>(0).unwrap();
+    }
+}
+
+/// Serializes a gbtree (or dart, when `weight_drop` is given) model
+pub fn gbtree_model(
+    obj: &str,
+    base_score: f32,
+    num_feature: usize,
+    num_output_group: usize,
+    trees: &[TestTree],
+    weight_drop: Option<&[f32]>,
+) -> Vec<u8> {
+    let mut buf = vec![];
+    let gbm = if weight_drop.is_some() {
+        "dart"
+    } else {
+        "gbtree"
+    };
+    write_header(&mut buf, obj, gbm, base_score, num_feature);
+    buf.write_i32::<LE>(trees.len() as i32).unwrap();
+    buf.write_i32::<LE>(1).unwrap();
+    buf.write_i32::<LE>(num_feature as i32).unwrap();
+    buf.write_i32::<LE>(0).unwrap();
+    buf.write_i64::<LE>(0).unwrap();
+    buf.write_i32::<LE>(num_output_group as i32).unwrap();
+    buf.write_i32::<LE>(0).unwrap();
+    for _ in 0..32 {
+        buf.write_i32::<LE>(0).unwrap();
+    }
+    for tree in trees {
+        write_tree(&mut buf, tree, num_feature);
+    }
+    for tree in trees {
+        buf.write_i32::<LE>(tree.group).unwrap();
+    }
+    if let Some(weight_drop) = weight_drop {
+        buf.write_i64::<LE>(weight_drop.len() as i64).unwrap();
+        for weight in weight_drop {
+            buf.write_f32::<LE>(*weight).unwrap();
+        }
+    }
+    buf
+}
+
+/// Serializes a gblinear model, `weights` are laid out as `[feature][group]` followed by the
+/// bias of each group
+pub fn gblinear_model(
+    obj: &str,
+    base_score: f32,
+    num_feature: usize,
+    num_output_group: usize,
+    weights: &[f32],
+) -> Vec<u8> {
+    assert_eq!(weights.len(), (num_feature + 1) * num_output_group);
+    let mut buf = vec![];
+    write_header(&mut buf, obj, "gblinear", base_score, num_feature);
+    buf.write_i32::<LE>(num_feature as i32).unwrap();
+    buf.write_i32::<LE>(num_output_group as i32).unwrap();
+    for _ in 0..34 {
+        buf.write_i32::<LE>(0).unwrap();
+    }
+    for weight in weights {
+        buf.write_f32::<LE>(*weight).unwrap();
+    }
+    buf
+}
+
+/// Generates a random tree of at most `max_depth` levels of splits
+pub fn random_tree(rng: &mut Lcg, group: i32, num_feature: usize, max_depth: usize) -> TestTree {
+    fn grow(
+        rng: &mut Lcg,
+        nodes: &mut Vec<TestNode>,
+        covers: &mut Vec<f32>,
+        nid: usize,
+        depth: usize,
+        num_feature: usize,
+        max_depth: usize,
+    ) -> f32 {
+        if depth == max_depth || (depth > 0 && rng.next_f32() < 0.2) {
+            nodes[nid] = TestNode::Leaf(rng.next_f32() - 0.5);
+            covers[nid] = 1f32 + (rng.next_u32() % 16) as f32;
+            return covers[nid];
+        }
+        let (left, right) = (nodes.len(), nodes.len() + 1);
+        nodes.push(TestNode::Leaf(0f32));
+        nodes.push(TestNode::Leaf(0f32));
+        covers.push(0f32);
+        covers.push(0f32);
+        let feature = rng.next_u32() % num_feature as u32;
+        // thresholds on a coarse grid, so that features share split conditions
+        let cond = (rng.next_u32() % 16) as f32 / 16f32;
+        let default_left = rng.next_u32() & 1 == 0;
+        nodes[nid] = TestNode::Split(feature, cond, left as i32, right as i32, default_left);
+        let cover = grow(rng, nodes, covers, left, depth + 1, num_feature, max_depth)
+            + grow(rng, nodes, covers, right, depth + 1, num_feature, max_depth);
+        covers[nid] = cover;
+        cover
+    }
+
+    let mut nodes = vec![TestNode::Leaf(0f32)];
+    let mut covers = vec![0f32];
+    grow(rng, &mut nodes, &mut covers, 0, 0, num_feature, max_depth);
+    TestTree {
+        group,
+        nodes,
+        covers,
+    }
+}
+
+/// Generates `num_trees` random trees per output group, groups alternating
+pub fn random_trees(
+    seed: u64,
+    num_feature: usize,
+    num_output_group: usize,
+    num_trees: usize,
+    max_depth: usize,
+) -> Vec<TestTree> {
+    let mut rng = Lcg::new(seed);
+    (0..num_trees * num_output_group)
+        .map(|i| {
+            random_tree(
+                &mut rng,
+                (i % num_output_group) as i32,
+                num_feature,
+                max_depth,
+            )
+        })
+        .collect()
+}
+
+/// Generates a random gbtree model with `num_trees` trees per output group
+pub fn random_gbtree_model(
+    seed: u64,
+    obj: &str,
+    num_feature: usize,
+    num_output_group: usize,
+    num_trees: usize,
+    max_depth: usize,
+) -> Vec<u8> {
+    let trees = random_trees(seed, num_feature, num_output_group, num_trees, max_depth);
+    gbtree_model(obj, 0.5, num_feature, num_output_group, &trees, None)
+}
+
+/// Generates a random matrix in [0, 1) where about `nan_ratio` of the values are missing
+pub fn random_matrix(seed: u64, rows: usize, cols: usize, nan_ratio: f32) -> Array2<FeatureValue> {
+    let mut rng = Lcg::new(seed);
+    Array2::from_shape_fn((rows, cols), |_| {
+        if rng.next_f32() < nan_ratio {
+            FeatureValue::NAN
+        } else {
+            FeatureValue::from(rng.next_f32())
+        }
+    })
+}
+
+/// JSON model of two features, "age" and "color", the latter categorical, with a single tree:
+/// 0.25 when age < 30, otherwise 1.0 for colors 1 and 3 and -1.0 for other or missing colors
+pub const JSON_MODEL: &str = r#"{
+    "learner": {
+        "attributes": {},
+        "feature_names": ["age", "color"],
+        "feature_types": ["float", "c"],
+        "gradient_booster": {
+            "model": {
+                "gbtree_model_param": {"num_parallel_tree": "1", "num_trees": "1"},
+                "tree_info": [0],
+                "trees": [{
+                    "base_weights": [0, 0.25, 0, 1, -1],
+                    "categories": [3, 1],
+                    "categories_nodes": [2],
+                    "categories_segments": [0],
+                    "categories_sizes": [2],
+                    "default_left": [0, 0, 1, 0, 0],
+                    "id": 0,
+                    "left_children": [1, -1, 3, -1, -1],
+                    "loss_changes": [2, 0, 1, 0, 0],
+                    "parents": [2147483647, 0, 0, 2, 2],
+                    "right_children": [2, -1, 4, -1, -1],
+                    "split_conditions": [30, 0.25, 0, -1, 1],
+                    "split_indices": [0, 0, 1, 0, 0],
+                    "split_type": [0, 0, 1, 0, 0],
+                    "sum_hessian": [10, 4, 6, 3, 3],
+                    "tree_param": {
+                        "num_deleted": "0",
+                        "num_feature": "2",
+                        "num_nodes": "5",
+                        "size_leaf_vector": "1"
+                    }
+                }]
+            },
+            "name": "gbtree"
+        },
+        "learner_model_param": {
+            "base_score": "5E-1",
+            "num_class": "0",
+            "num_feature": "2",
+            "num_target": "1"
+        },
+        "objective": {"name": "binary:logistic", "reg_loss_param": {"scale_pos_weight": "1"}}
+    },
+    "version": [1, 7, 6]
+}"#;
+
+pub fn load_model(bytes: &[u8]) -> Predictor {
+    Predictor::read_from(&mut &bytes[..]).unwrap()
+}
@@ -1,142 +1,634 @@
-use ndarray::{ArrayView1, ArrayView2};
-use numpy::{PyArray1, PyArray2, PyReadonlyArray1, PyReadonlyArray2};
+use std::collections::HashMap;
+use std::io;
+
+use ndarray::Array2;
+use numpy::{IntoPyArray, PyArray1, PyReadonlyArray1, PyReadonlyArray2};
 use pyo3::prelude::*;
-use pyo3::{exceptions, PyErr};
-
-use crate::errors::*;
-use crate::predictor::Predictor;
-
-use std::time::{Duration, Instant};
-
-// fn check_input_1d(predictor: &Predictor, data: &ArrayView1<'_, f32>) -> PyResult<()> {
-//     let model_num_features = predictor.model_num_feature();
-//     let data_num_features = data.shape()[0];
-//     if model_num_features != data_num_features {
-//         return Err(PyErr::new::<exceptions::PyValueError, _>(format!(
-//             "Num of features is not equal to model's features count: {} != {}.",
-//             data_num_features, model_num_features,
-//         )));
-//     }
-//     Ok(())
-// }
-
-// fn check_input_2d(predictor: &Predictor, data: &ArrayView2<'_, f32>) -> PyResult<()> {
-//     let model_num_features = predictor.model_num_feature();
-//     let data_num_features = data.shape()[1];
-//     if model_num_features != data_num_features {
-//         return Err(PyErr::new::<exceptions::PyValueError, _>(format!(
-//             "Num of features is not equal to model's features count: {} != {}.",
-//             data_num_features, model_num_features,
-//         )));
-//     }
-//     Ok(())
-// }
-
-#[pyclass]
+use pyo3::types::{PyBytes, PyDict};
+use pyo3::{exceptions, PyErr, PyNativeType};
+
+use crate::errors::{Error, ErrorKind};
+use crate::exceptions::to_py_err;
+use crate::predictor::{FeatureType, Predictor, ShapePolicy};
+use crate::{FeatureValue, InputValue};
+
+/// Names the dtype and dimensions of an unsupported array, or the type of other objects
+fn input_type_name(data: &PyAny) -> PyResult<String> {
+    if data.hasattr("dtype")? && data.hasattr("ndim")? {
+        let ndim: usize = data.getattr("ndim")?.extract()?;
+        return Ok(format!("{}d array of {}", ndim, data.getattr("dtype")?));
+    }
+    Ok(data.get_type().name().to_string())
+}
+
+/// Converts predictions to a float32 array of shape `(n_rows, num_output)`, or `(n_rows,)` for a
+/// single output
+pub(crate) fn preds_to_numpy(
+    py: Python,
+    preds: Vec<Vec<f32>>,
+    num_output: usize,
+) -> PyResult<PyObject> {
+    let num_rows = preds.len();
+    let values: Vec<f32> = preds.into_iter().flatten().collect();
+    if num_output == 1 {
+        return Ok(PyArray1::from_vec(py, values).to_object(py));
+    }
+    let array = Array2::from_shape_vec((num_rows, num_output), values)
+        .map_err(|error| PyErr::new::<exceptions::PyValueError, _>(format!("{}", error)))?;
+    Ok(array.into_pyarray(py).to_object(py))
+}
+
+/// Values of a `pandas.Series` as float64, NaN for nulls. Categorical series give their category
+/// codes, -1 marking nulls.
+fn series_values(series: &PyAny) -> PyResult<Vec<f64>> {
+    let py = series.py();
+    let kwargs = PyDict::new(py);
+    kwargs.set_item("dtype", "float64")?;
+    let is_categorical = series
+        .getattr("dtype")?
+        .getattr("name")?
+        .extract::<&str>()?
+        == "category";
+    if is_categorical {
+        let codes = series
+            .getattr("cat")?
+            .getattr("codes")?
+            .call_method("to_numpy", (), Some(kwargs))?
+            .extract::<PyReadonlyArray1<f64>>()?;
+        return Ok(codes
+            .as_array()
+            .iter()
+            .map(|code| if *code < 0.0 { f64::NAN } else { *code })
+            .collect());
+    }
+    kwargs.set_item("na_value", f64::NAN)?;
+    let values = match series.call_method("to_numpy", (), Some(kwargs)) {
+        Ok(values) => values.extract::<PyReadonlyArray1<f64>>()?,
+        Err(_) => {
+            let dtype = format!("{}", series.getattr("dtype")?);
+            return Err(to_py_err(Error::from_kind(ErrorKind::UnsupportedDataType(
+                dtype,
+            ))));
+        }
+    };
+    Ok(values.as_array().to_vec())
+}
+
+/// Copies the columns of a `pandas.DataFrame` into rows in model order, matching them to the
+/// feature names; duplicate column names are rejected
+fn frame_to_matrix(predictor: &Predictor, data: &PyAny) -> PyResult<Array2<FeatureValue>> {
+    let mut columns: Vec<String> = vec![];
+    let mut series: Vec<&PyAny> = vec![];
+    for item in data.call_method0("items")?.iter()? {
+        let (name, values): (&PyAny, &PyAny) = item?.extract()?;
+        columns.push(format!("{}", name));
+        series.push(values);
+    }
+    let column_names: Vec<&str> = columns.iter().map(|name| name.as_str()).collect();
+    let order = predictor.column_order(&column_names).map_err(to_py_err)?;
+
+    let num_rows: usize = data.len()?;
+    let mut feats = Array2::from_elem((num_rows, order.len()), FeatureValue::NAN);
+    for (fid, index) in order.iter().enumerate() {
+        if let Some(index) = index {
+            let values = series_values(series[*index])?;
+            for (row, value) in values.into_iter().enumerate() {
+                feats[[row, fid]] = value.to_feature_value();
+            }
+        }
+    }
+    Ok(feats)
+}
+
+/// Reads a Python file-like object through its `read` method
+pub(crate) struct PyFileReader<'p> {
+    pub file: &'p PyAny,
+}
+
+impl io::Read for PyFileReader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let chunk = self
+            .file
+            .call_method1("read", (buf.len(),))
+            .and_then(|chunk| chunk.extract::<&[u8]>())
+            .map_err(|error| io::Error::other(format!("{}", error)))?;
+        if chunk.len() > buf.len() {
+            return Err(io::Error::other("read returned more bytes than requested"));
+        }
+        buf[..chunk.len()].copy_from_slice(chunk);
+        Ok(chunk.len())
+    }
+}
+
+#[pyclass(module = "xgboost_predictor")]
 pub struct PredictorWrapper {
     pub predictor: Predictor,
 }
 
+/// Settings of the predictor kept along the model bytes when pickling: the feature types, the
+/// shape policy and the number of threads
+pub(crate) type PickledSettings = (Option<Vec<String>>, String, usize);
+/// Function rebuilding a pickled predictor and its arguments
+type PickledState = (PyObject, (PyObject, PickledSettings));
+
+/// Rebuilds a predictor from its serialised model and its settings
+pub(crate) fn from_state(
+    bytes: &[u8],
+    settings: PickledSettings,
+) -> Result<PredictorWrapper, Error> {
+    let (feature_types, shape_policy, nthread) = settings;
+    let mut predictor = Predictor::from_bytes(bytes)?;
+    if let Some(types) = feature_types {
+        let types = types
+            .iter()
+            .map(|name| FeatureType::from_name(name))
+            .collect::<Result<Vec<FeatureType>, Error>>()?;
+        predictor.set_feature_types(types)?;
+    }
+    predictor.set_shape_policy(ShapePolicy::from_name(&shape_policy)?);
+    predictor.set_nthread(nthread)?;
+    Ok(PredictorWrapper { predictor })
+}
+
 #[pymethods]
 impl PredictorWrapper {
-    // #[args(ntree_limit = "0")]
-    // pub fn predict_leaf<'py>(
-    //     &self,
-    //     py: Python<'py>,
-    //     data: PyReadonlyArray1<f32>,
-    //     ntree_limit: usize,
-    // ) -> PyResult<&'py PyArray1<usize>> {
-    //     let data_array = data.as_array();
-    //     check_input_1d(&self.predictor, &data_array)?;
-    //     match self.predictor.predict_leaf(data_array, ntree_limit) {
-    //         Ok(preds) => Ok(PyArray1::from_vec(py, preds)),
-    //         Err(error) => match error.kind() {
-    //             _ => Err(PyErr::new::<exceptions::PyValueError, _>("")),
-    //         },
-    //     }
-    // }
-
-    // #[args(ntree_limit = "0", margin = "false")]
-    // pub fn predict_single(
-    //     &self,
-    //     data: PyReadonlyArray1<f32>,
-    //     ntree_limit: usize,
-    //     margin: bool,
-    // ) -> PyResult<f32> {
-    //     let data_array = data.as_array();
-    //     check_input_1d(&self.predictor, &data_array)?;
-    //     match self
-    //         .predictor
-    //         .predict_single(data_array, margin, ntree_limit)
-    //     {
-    //         Ok(pred) => Ok(pred),
-    //         Err(error) => match error.kind() {
-    //             _ => Err(PyErr::new::<exceptions::PyValueError, _>("")),
-    //         },
-    //     }
-    // }
-
-    // #[args(ntree_limit = "0", margin = "false")]
-    // pub fn predict<'py>(
-    //     &self,
-    //     py: Python<'py>,
-    //     data: PyReadonlyArray1<f32>,
-    //     ntree_limit: usize,
-    //     margin: bool,
-    // ) -> PyResult<&'py PyArray1<f32>> {
-    //     let data_array = data.as_array();
-    //     check_input_1d(&self.predictor, &data_array)?;
-    //     match self.predictor.predict(data_array, margin, ntree_limit) {
-    //         Ok(preds) => Ok(PyArray1::from_vec(py, preds)),
-    //         Err(error) => match error.kind() {
-    //             _ => Err(PyErr::new::<exceptions::PyValueError, _>("")),
-    //         },
-    //     }
-    // }
-
+    /// Generates predictions for a 2d numpy array of float32, float64, int32 or int64 values in
+    /// any memory layout. Arrays of other types than the input type, or with other strides than
+    /// contiguous rows, are converted block by block while predicting.
+    ///
+    /// Returns a float32 array of shape `(n_rows, n_groups)` in row-major order, or of shape
+    /// `(n_rows,)` for models of a single output group and for the class indices predicted by
+    /// multi:softmax models, like `xgboost.Booster.predict`. DataFrames are predicted by
+    /// `predict_frame`.
     #[args(ntree_limit = "0", margin = "false")]
     pub fn predict_many(
         &self,
-        data: PyReadonlyArray2<f32>,
+        py: Python,
+        data: &PyAny,
+        ntree_limit: usize,
+        margin: bool,
+    ) -> PyResult<PyObject> {
+        let preds = self.predict_rows(py, data, ntree_limit, margin)?;
+        preds_to_numpy(py, preds, self.predictor.num_output(margin))
+    }
+
+    /// Generates predictions for a `pandas.DataFrame`, returned as by `predict_many`. Columns
+    /// are matched to the feature names of the model, and taken in order when the model has
+    /// none. Categorical columns are replaced by their category codes, and null values of
+    /// nullable columns are missing.
+    #[args(ntree_limit = "0", margin = "false")]
+    pub fn predict_frame(
+        &self,
+        py: Python,
+        data: &PyAny,
+        ntree_limit: usize,
+        margin: bool,
+    ) -> PyResult<PyObject> {
+        let preds = self.predict_frame_rows(py, data, ntree_limit, margin)?;
+        preds_to_numpy(py, preds, self.predictor.num_output(margin))
+    }
+
+    /// Number of threads of batch prediction, 0 means one thread per core
+    #[getter]
+    pub fn nthread(&self) -> usize {
+        self.predictor.nthread()
+    }
+
+    #[setter]
+    pub fn set_nthread(&mut self, nthread: usize) -> PyResult<()> {
+        self.predictor.set_nthread(nthread).map_err(to_py_err)
+    }
+
+    /// Names of the features in model order, `None` when the model has no names
+    #[getter]
+    pub fn feature_names(&self) -> Option<Vec<String>> {
+        self.predictor.feature_names().map(|names| names.to_vec())
+    }
+
+    #[setter]
+    pub fn set_feature_names(&mut self, names: Vec<String>) -> PyResult<()> {
+        self.predictor.set_feature_names(names).map_err(to_py_err)
+    }
+
+    /// Types of the features in model order, as named by xgboost ("q", "i", "int", "float"
+    /// or "c"), `None` when the model has no types
+    #[getter]
+    pub fn feature_types(&self) -> Option<Vec<String>> {
+        self.predictor
+            .feature_types()
+            .map(|types| types.iter().map(|tp| String::from(tp.name())).collect())
+    }
+
+    #[setter]
+    pub fn set_feature_types(&mut self, types: Vec<String>) -> PyResult<()> {
+        let types = types
+            .iter()
+            .map(|name| FeatureType::from_name(name))
+            .collect::<Result<Vec<FeatureType>, Error>>()
+            .map_err(to_py_err)?;
+        self.predictor.set_feature_types(types).map_err(to_py_err)
+    }
+
+    /// How input with a number of columns other than the model's number of features is
+    /// handled: "strict" (default), "allow_extra" trailing columns or "pad_missing" columns
+    #[getter]
+    pub fn shape_policy(&self) -> &'static str {
+        self.predictor.shape_policy().name()
+    }
+
+    #[setter]
+    pub fn set_shape_policy(&mut self, policy: &str) -> PyResult<()> {
+        let policy = ShapePolicy::from_name(policy)
+            .map_err(|error| PyErr::new::<exceptions::PyValueError, _>(format!("{}", error)))?;
+        self.predictor.set_shape_policy(policy);
+        Ok(())
+    }
+
+    /// Replaces the trees by a compact representation giving the same predictions with less
+    /// memory. Models with categorical splits can not be compacted.
+    pub fn compact(&mut self) -> PyResult<()> {
+        self.predictor.compact().map_err(to_py_err)
+    }
+
+    #[getter]
+    pub fn is_compact(&self) -> bool {
+        self.predictor.is_compact()
+    }
+
+    /// Serialises the model and its feature names, see `load_model_from_bytes`
+    pub fn to_bytes(&self, py: Python) -> PyResult<PyObject> {
+        let bytes = self.predictor.to_bytes().map_err(to_py_err)?;
+        Ok(PyBytes::new(py, &bytes).to_object(py))
+    }
+
+    /// Pickles the model bytes and the settings of the predictor
+    pub fn __reduce__(&self, py: Python) -> PyResult<PickledState> {
+        let restore = py
+            .import("xgboost_predictor")?
+            .getattr("_restore_predictor")?
+            .to_object(py);
+        Ok((restore, (self.to_bytes(py)?, self.settings())))
+    }
+
+    pub fn __copy__(&self) -> PyResult<PredictorWrapper> {
+        let bytes = self.predictor.to_bytes().map_err(to_py_err)?;
+        from_state(&bytes, self.settings()).map_err(to_py_err)
+    }
+
+    pub fn __deepcopy__(&self, _memo: &PyAny) -> PyResult<PredictorWrapper> {
+        self.__copy__()
+    }
+
+    /// Writes the model in the format read in place by `load_mmap_model`
+    pub fn save_mmap(&self, path: &str) -> PyResult<()> {
+        crate::mapped::save(&self.predictor, path).map_err(to_py_err)
+    }
+
+    /// Generates predictions for a dict of feature values by name, or for a list of such dicts.
+    /// `None` or NaN values are missing.
+    #[args(ntree_limit = "0", margin = "false")]
+    pub fn predict_named(
+        &self,
+        py: Python,
+        data: &PyAny,
+        ntree_limit: usize,
+        margin: bool,
+    ) -> PyResult<PyObject> {
+        let is_single = data.downcast::<PyDict>().is_ok();
+        let records: Vec<HashMap<String, Option<FeatureValue>>> = if is_single {
+            vec![data.extract()?]
+        } else {
+            data.extract()?
+        };
+        let rows: Vec<HashMap<&str, FeatureValue>> = records
+            .iter()
+            .map(|record| {
+                record
+                    .iter()
+                    .map(|(name, value)| (name.as_str(), value.unwrap_or(FeatureValue::NAN)))
+                    .collect()
+            })
+            .collect();
+        let mut preds = self
+            .predictor
+            .predict_named(&rows, margin, ntree_limit)
+            .map_err(to_py_err)?;
+        if is_single {
+            Ok(preds.remove(0).to_object(py))
+        } else {
+            Ok(preds.to_object(py))
+        }
+    }
+}
+
+impl PredictorWrapper {
+    fn settings(&self) -> PickledSettings {
+        (
+            self.feature_types(),
+            String::from(self.predictor.shape_policy().name()),
+            self.predictor.nthread(),
+        )
+    }
+
+    fn predict_frame_rows(
+        &self,
+        py: Python,
+        data: &PyAny,
         ntree_limit: usize,
         margin: bool,
     ) -> PyResult<Vec<Vec<f32>>> {
-        // let data_array = data.as_array();
-        // check_input_2d(&self.predictor, &data_array)?;
-        let now = Instant::now();
-        let preds = self.predictor.predict_many(data.as_array(), margin, ntree_limit);
-        println!("{:?}", now.elapsed());
-        Ok(preds.unwrap())
-        // match self.predictor.predict_many(data.as_slice().unwrap(), margin, ntree_limit) {
-        //     Ok(preds) => Ok(preds),
-        //     Err(error) => match error.kind() {
-        //         _ => Err(PyErr::new::<exceptions::PyValueError, _>("")),
-        //     },
-        // }
-    }
-
-    // #[args(ntree_limit = "0", margin = "false")]
-    // pub fn predict_many(
-    //     &self,
-    //     data: PyReadonlyArray2<f32>,
-    //     ntree_limit: usize,
-    //     margin: bool,
-    // ) -> PyResult<Vec<Vec<f32>>> {
-    //     let data_array = data.as_array();
-
-    //     let now = Instant::now();
-    //     check_input_2d(&self.predictor, &data_array)?;
-    //     println!("{:?}", now.elapsed());
-
-    //     let now = Instant::now();
-    //     let data = self.predictor.predict_many(data_array, margin, ntree_limit);
-    //     println!("{:?}", now.elapsed());
-
-    //     match self.predictor.predict_many(data_array, margin, ntree_limit) {
-    //         Ok(preds) => Ok(preds),
-    //         Err(error) => match error.kind() {
-    //             _ => Err(PyErr::new::<exceptions::PyValueError, _>("")),
-    //         },
-    //     }
-    // }
+        let feats = frame_to_matrix(&self.predictor, data)?;
+        let predictor = &self.predictor;
+        py.allow_threads(|| predictor.predict_many(feats.view(), margin, ntree_limit))
+            .map_err(to_py_err)
+    }
+
+    /// Predicts the rows of a DataFrame or of a 2d numpy array, see `predict_many`
+    pub(crate) fn predict_rows(
+        &self,
+        py: Python,
+        data: &PyAny,
+        ntree_limit: usize,
+        margin: bool,
+    ) -> PyResult<Vec<Vec<f32>>> {
+        if data.hasattr("columns")? && data.hasattr("dtypes")? {
+            return self.predict_frame_rows(py, data, ntree_limit, margin);
+        }
+        let predictor = &self.predictor;
+        // the GIL is released so that other Python threads can run while predicting
+        let preds = if let Ok(data) = data.extract::<PyReadonlyArray2<FeatureValue>>() {
+            let feats = data.as_array();
+            if feats.is_standard_layout() {
+                py.allow_threads(|| predictor.predict_many(feats, margin, ntree_limit))
+            } else {
+                py.allow_threads(|| predictor.predict_many_converted(feats, margin, ntree_limit))
+            }
+        } else if let Ok(data) = data.extract::<PyReadonlyArray2<f32>>() {
+            let feats = data.as_array();
+            py.allow_threads(|| predictor.predict_many_converted(feats, margin, ntree_limit))
+        } else if let Ok(data) = data.extract::<PyReadonlyArray2<f64>>() {
+            let feats = data.as_array();
+            py.allow_threads(|| predictor.predict_many_converted(feats, margin, ntree_limit))
+        } else if let Ok(data) = data.extract::<PyReadonlyArray2<i32>>() {
+            let feats = data.as_array();
+            py.allow_threads(|| predictor.predict_many_converted(feats, margin, ntree_limit))
+        } else if let Ok(data) = data.extract::<PyReadonlyArray2<i64>>() {
+            let feats = data.as_array();
+            py.allow_threads(|| predictor.predict_many_converted(feats, margin, ntree_limit))
+        } else {
+            Err(Error::from_kind(ErrorKind::UnsupportedDataType(
+                input_type_name(data)?,
+            )))
+        };
+        preds.map_err(to_py_err)
+    }
+}
+
+#[cfg(feature = "arrow")]
+mod arrow_methods {
+    use arrow::array::{Array, ArrayRef, StructArray};
+    use arrow::ffi::{from_ffi, to_ffi, FFI_ArrowArray, FFI_ArrowSchema};
+    use arrow::record_batch::RecordBatch;
+    use pyo3::prelude::*;
+    use pyo3::types::PyList;
+
+    use crate::errors::Result;
+    use crate::exceptions::to_py_err;
+    use crate::record_batch::preds_to_array;
+
+    use super::PredictorWrapper;
+
+    /// Imports a `pyarrow.RecordBatch` through the Arrow C data interface
+    fn import_record_batch(batch: &PyAny) -> PyResult<RecordBatch> {
+        let array = FFI_ArrowArray::empty();
+        let schema = FFI_ArrowSchema::empty();
+        batch.call_method1(
+            "_export_to_c",
+            (
+                &array as *const FFI_ArrowArray as usize,
+                &schema as *const FFI_ArrowSchema as usize,
+            ),
+        )?;
+        let data = unsafe { from_ffi(array, &schema) }.map_err(|error| to_py_err(error.into()))?;
+        Ok(RecordBatch::from(StructArray::from(data)))
+    }
+
+    /// Exports an array to a `pyarrow.Array` through the Arrow C data interface
+    fn export_array(py: Python, array: ArrayRef) -> PyResult<PyObject> {
+        let (array, schema) = to_ffi(&array.to_data()).map_err(|error| to_py_err(error.into()))?;
+        let result = py.import("pyarrow")?.getattr("Array")?.call_method1(
+            "_import_from_c",
+            (
+                &array as *const FFI_ArrowArray as usize,
+                &schema as *const FFI_ArrowSchema as usize,
+            ),
+        )?;
+        Ok(result.to_object(py))
+    }
+
+    #[pymethods]
+    impl PredictorWrapper {
+        /// Generates predictions for a `pyarrow.Table` or `pyarrow.RecordBatch` and returns them
+        /// as a `pyarrow.Array`
+        #[args(ntree_limit = "0", margin = "false")]
+        pub fn predict_arrow(
+            &self,
+            py: Python,
+            data: &PyAny,
+            ntree_limit: usize,
+            margin: bool,
+        ) -> PyResult<PyObject> {
+            // a table is made of record batches, a record batch is passed through as is
+            let batches: Vec<&PyAny> = if data.hasattr("to_batches")? {
+                data.call_method0("to_batches")?
+                    .downcast::<PyList>()?
+                    .iter()
+                    .collect()
+            } else {
+                vec![data]
+            };
+
+            let batches = batches
+                .into_iter()
+                .map(import_record_batch)
+                .collect::<PyResult<Vec<RecordBatch>>>()?;
+            let predictor = &self.predictor;
+            let preds = py
+                .allow_threads(|| -> Result<Vec<Vec<f32>>> {
+                    let mut preds = vec![];
+                    for batch in batches.iter() {
+                        let feats = predictor.record_batch_to_matrix(batch)?;
+                        preds.extend(predictor.predict_many(feats.view(), margin, ntree_limit)?);
+                    }
+                    Ok(preds)
+                })
+                .map_err(to_py_err)?;
+            let array =
+                preds_to_array(preds, self.predictor.num_output(margin)).map_err(to_py_err)?;
+            export_array(py, array)
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::ptr;
+        use std::sync::Arc;
+
+        use arrow::array::{Array, ArrayRef, AsArray, Float32Array, Int32Array, StructArray};
+        use arrow::datatypes::Float32Type;
+        use arrow::ffi::{from_ffi, to_ffi, FFI_ArrowArray, FFI_ArrowSchema};
+        use arrow::record_batch::RecordBatch;
+        use ndarray::arr2;
+        use pyo3::prelude::*;
+        use pyo3::types::{PyList, PyModule};
+
+        use crate::testing::{load_model, random_gbtree_model};
+
+        use super::PredictorWrapper;
+
+        /// Record batch exported as pyarrow does, the tests do not depend on pyarrow
+        #[pyclass]
+        struct TestBatch {
+            batch: RecordBatch,
+        }
+
+        #[pymethods]
+        impl TestBatch {
+            fn _export_to_c(&self, array: usize, schema: usize) {
+                let data = StructArray::from(self.batch.clone()).into_data();
+                let (ffi_array, ffi_schema) = to_ffi(&data).unwrap();
+                unsafe {
+                    ptr::write(array as *mut FFI_ArrowArray, ffi_array);
+                    ptr::write(schema as *mut FFI_ArrowSchema, ffi_schema);
+                }
+            }
+        }
+
+        #[pyclass]
+        struct TestTable {
+            batches: Vec<Py<TestBatch>>,
+        }
+
+        #[pymethods]
+        impl TestTable {
+            fn to_batches(&self, py: Python) -> PyObject {
+                PyList::new(py, &self.batches).to_object(py)
+            }
+        }
+
+        /// Stands for `pyarrow.Array`, moving the imported array as pyarrow does
+        #[pyclass]
+        struct TestArray {
+            array: ArrayRef,
+        }
+
+        #[pymethods]
+        impl TestArray {
+            #[staticmethod]
+            fn _import_from_c(array: usize, schema: usize) -> TestArray {
+                let array = unsafe {
+                    let array = ptr::replace(array as *mut FFI_ArrowArray, FFI_ArrowArray::empty());
+                    let schema =
+                        ptr::replace(schema as *mut FFI_ArrowSchema, FFI_ArrowSchema::empty());
+                    from_ffi(array, &schema).unwrap()
+                };
+                TestArray {
+                    array: arrow::array::make_array(array),
+                }
+            }
+        }
+
+        fn test_batch(py: Python, values: Vec<i32>) -> Py<TestBatch> {
+            let batch = RecordBatch::try_from_iter(vec![
+                ("f0", Arc::new(Int32Array::from(values.clone())) as _),
+                ("f1", Arc::new(Int32Array::from(values)) as _),
+            ])
+            .unwrap();
+            Py::new(py, TestBatch { batch }).unwrap()
+        }
+
+        #[test]
+        fn test_predict_arrow() {
+            let gil = Python::acquire_gil();
+            let py = gil.python();
+            let pyarrow = PyModule::new(py, "pyarrow").unwrap();
+            pyarrow.add("Array", py.get_type::<TestArray>()).unwrap();
+            py.import("sys")
+                .unwrap()
+                .getattr("modules")
+                .unwrap()
+                .set_item("pyarrow", pyarrow)
+                .unwrap();
+
+            let predictor = load_model(&random_gbtree_model(5, "multi:softprob", 2, 3, 6, 3));
+            let expected = predictor
+                .predict_many(arr2(&[[1.0, 1.0], [2.0, 2.0]]).view(), false, 0)
+                .unwrap();
+            let wrapper = Py::new(py, PredictorWrapper { predictor }).unwrap();
+
+            let table = TestTable {
+                batches: vec![
+                    test_batch(py, vec![1]),
+                    test_batch(py, vec![]),
+                    test_batch(py, vec![2]),
+                ],
+            };
+            // the output groups do not depend on the number of rows
+            for (data, num_rows) in &[
+                (Py::new(py, table).unwrap().to_object(py), 2),
+                (test_batch(py, vec![]).to_object(py), 0),
+            ] {
+                let preds = wrapper.call_method1(py, "predict_arrow", (data,)).unwrap();
+                let preds: PyRef<TestArray> = preds.extract(py).unwrap();
+                let preds = preds.array.as_fixed_size_list();
+                assert_eq!((preds.len(), preds.value_length()), (*num_rows, 3));
+                for (row, expected) in expected.iter().take(*num_rows).enumerate() {
+                    let values = preds.value(row);
+                    assert_eq!(
+                        values.as_primitive::<Float32Type>(),
+                        &Float32Array::from(expected.clone())
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pyo3::prelude::*;
+
+    use crate::gbm::grad_booster::BoosterModel;
+    use crate::testing::{load_model, random_gbtree_model};
+
+    use super::PredictorWrapper;
+
+    #[test]
+    fn test_copy_keeps_model_and_settings() {
+        let gil = Python::acquire_gil();
+        let py = gil.python();
+        let mut wrapper = PredictorWrapper {
+            predictor: load_model(&random_gbtree_model(4, "reg:linear", 3, 1, 4, 3)),
+        };
+        let names = vec![String::from("a"), String::from("b"), String::from("c")];
+        wrapper.set_feature_names(names.clone()).unwrap();
+        wrapper.set_shape_policy("pad_missing").unwrap();
+        let wrapper = Py::new(py, wrapper).unwrap();
+
+        let copy = py.import("copy").unwrap();
+        let copied = copy.call1("deepcopy", (wrapper.clone_ref(py),)).unwrap();
+        let copied: PyRef<PredictorWrapper> = copied.extract().unwrap();
+        assert!(matches!(copied.predictor.booster(), BoosterModel::Trees(_)));
+        assert_eq!(copied.feature_names(), Some(names));
+        assert_eq!(copied.shape_policy(), "pad_missing");
+
+        // compact trees are copied as a mapped model
+        wrapper.borrow_mut(py).compact().unwrap();
+        let copied = copy.call1("copy", (wrapper,)).unwrap();
+        let copied: PyRef<PredictorWrapper> = copied.extract().unwrap();
+        assert!(matches!(
+            copied.predictor.booster(),
+            BoosterModel::Mapped(_)
+        ));
+    }
 }